@@ -0,0 +1,64 @@
+//! Counts heap allocations made by matching, which criterion cannot measure.
+//! Run with `cargo bench --bench alloc`.
+use ast_grep_core::{Language, Pattern};
+use ast_grep_language::SupportLang;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAlloc;
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+  unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    System.alloc(layout)
+  }
+  unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    System.dealloc(ptr, layout)
+  }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// source dominated by calls with zero to two arguments
+fn short_argument_lists() -> String {
+  let lines = ["foo();", "bar(a);", "baz(a, b);", "qux(f(a), g());"];
+  lines
+    .iter()
+    .cycle()
+    .take(20000)
+    .fold(String::new(), |mut src, l| {
+      src.push_str(l);
+      src.push('\n');
+      src
+    })
+}
+
+/// one array literal with ten thousand elements
+fn huge_array() -> String {
+  let items: Vec<_> = (0..10000).map(|i| format!("a{i}")).collect();
+  format!("x = [{}, last];", items.join(", "))
+}
+
+fn count_allocations(name: &str, pattern: &str, src: &str) {
+  let lang = SupportLang::TypeScript;
+  let sg = lang.ast_grep(src);
+  let pattern = Pattern::str(pattern, lang);
+  let before = ALLOCATIONS.load(Ordering::Relaxed);
+  let found = sg.root().find_all(&pattern).count();
+  let allocations = ALLOCATIONS.load(Ordering::Relaxed) - before;
+  println!("{name}: {found} matches, {allocations} allocations");
+}
+
+fn main() {
+  let src = short_argument_lists();
+  count_allocations("unnamed ellipsis", "$F($$$, $A)", &src);
+  count_allocations("named ellipsis", "$F($$$ARGS)", &src);
+  count_allocations("named leading ellipsis", "$F($$$ARGS, $A)", &src);
+  let src = "f(a, b, c, d, e);\n".repeat(10000);
+  count_allocations("many captures", "$F($A, $B, $C, $D, $E)", &src);
+  let src = huge_array();
+  count_allocations("unnamed ellipsis over huge array", "[$$$, last]", &src);
+  count_allocations("named ellipsis over huge array", "[$$$ITEMS, last]", &src);
+}
@@ -0,0 +1,136 @@
+use ast_grep_core::matcher::{nodes_structurally_equal, KindIndex};
+use ast_grep_core::{AstGrep, Doc, Language, Matcher, Node, Pattern, StrDoc};
+use ast_grep_language::SupportLang;
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::env::current_dir;
+use std::fs::read_to_string;
+
+fn read_fixture(path: &str) -> String {
+  let cwd = current_dir().unwrap();
+  read_to_string(cwd.join(path)).unwrap()
+}
+
+fn component() -> String {
+  read_fixture("fixtures/component.tsx.fixture")
+}
+
+fn find_all_bench(c: &mut Criterion) {
+  let lang = SupportLang::Tsx;
+  let sg = lang.ast_grep(component());
+  let patterns = [
+    // single node, no ellipsis
+    ("meta variable call", "$F($A)"),
+    // ellipsis in argument list
+    ("ellipsis arguments", "useCallback($$$ARGS)"),
+    // ellipsis followed by more goals
+    ("ellipsis before goal", "[$$$ITEMS, $LAST]"),
+    // nested structure with ellipsis body
+    ("nested ellipsis", "if ($COND) { $$$BODY }"),
+    // jsx element with attributes
+    (
+      "jsx element",
+      "<Button variant=\"danger\" onClick={$F}>$$$</Button>",
+    ),
+  ];
+  for (name, pattern) in patterns {
+    let pattern = Pattern::str(black_box(pattern), lang);
+    c.bench_function(&format!("find_all {name}(component.tsx)"), |b| {
+      b.iter(|| sg.root().find_all(&pattern).count())
+    });
+  }
+}
+
+// clones every visited node, unlike find_all which walks with a cursor
+fn count_recursive<D: Doc>(pattern: &Pattern<D::Lang>, node: Node<D>) -> usize {
+  let matched = usize::from(pattern.match_node(node.clone()).is_some());
+  let children: usize = node.children().map(|c| count_recursive(pattern, c)).sum();
+  matched + children
+}
+
+fn walk_bench(c: &mut Criterion) {
+  let lang = SupportLang::Tsx;
+  let sg = lang.ast_grep(component().repeat(20));
+  let pattern = Pattern::str("$F($A)", lang);
+  assert_eq!(
+    sg.root().find_all(&pattern).count(),
+    count_recursive(&pattern, sg.root())
+  );
+  c.bench_function("find_all cursor walk", |b| {
+    b.iter(|| sg.root().find_all(&pattern).count())
+  });
+  c.bench_function("find_all recursive walk", |b| {
+    b.iter(|| count_recursive(&pattern, sg.root()))
+  });
+}
+
+fn large_tree(copies: usize, edit: Option<(&str, &str)>) -> AstGrep<StrDoc<SupportLang>> {
+  let source = component();
+  let mut large = source.repeat(copies);
+  if let Some((from, to)) = edit {
+    // diverge in the last copy, the worst case for an exact comparison
+    let last = large
+      .rfind(from)
+      .expect("fixture must contain the edited text");
+    large.replace_range(last..last + from.len(), to);
+  }
+  SupportLang::Tsx.ast_grep(large)
+}
+
+fn exact_match_bench(c: &mut Criterion) {
+  let copies = 20;
+  let base = large_tree(copies, None);
+  let identical = large_tree(copies, None);
+  let divergent = large_tree(copies, Some(("Clear completed", "Clear done")));
+  let leaf_divergent = large_tree(copies, Some(("remaining={remaining}", "remaining={left}")));
+  c.bench_function("exact match identical trees", |b| {
+    b.iter(|| nodes_structurally_equal(&base.root(), &identical.root()))
+  });
+  c.bench_function("exact match divergent text", |b| {
+    b.iter(|| nodes_structurally_equal(&base.root(), &divergent.root()))
+  });
+  c.bench_function("exact match divergent leaf", |b| {
+    b.iter(|| nodes_structurally_equal(&base.root(), &leaf_divergent.root()))
+  });
+}
+
+// ellipsis consuming every child of a node with ten thousand children
+fn large_array_bench(c: &mut Criterion) {
+  let lang = SupportLang::Tsx;
+  let items: Vec<_> = (0..10000).map(|i| format!("a{i}")).collect();
+  let sg = lang.ast_grep(format!("x = [{}, last];", items.join(", ")));
+  for (name, pattern) in [("unnamed", "[$$$, last]"), ("named", "[$$$ITEMS, last]")] {
+    let pattern = Pattern::str(pattern, lang);
+    c.bench_function(&format!("{name} ellipsis over 10k array"), |b| {
+      b.iter(|| sg.root().find_all(&pattern).count())
+    });
+  }
+}
+
+// one `new` expression per copy, most subtrees have no candidate of the pattern's kind
+fn sparse_match_bench(c: &mut Criterion) {
+  let sg = large_tree(20, None);
+  let root = sg.root();
+  let pattern = Pattern::str("new $C($$$)", SupportLang::Tsx);
+  let index = KindIndex::new(&root);
+  assert_eq!(
+    root.find_all(&pattern).count(),
+    root.find_all_indexed(&pattern, &index).count()
+  );
+  c.bench_function("sparse find_all", |b| {
+    b.iter(|| root.find_all(&pattern).count())
+  });
+  c.bench_function("sparse find_all with kind index", |b| {
+    b.iter(|| root.find_all_indexed(&pattern, &index).count())
+  });
+  c.bench_function("build kind index", |b| b.iter(|| KindIndex::new(&root)));
+}
+
+criterion_group!(
+  benches,
+  find_all_bench,
+  walk_bench,
+  exact_match_bench,
+  large_array_bench,
+  sparse_match_bench
+);
+criterion_main!(benches);
@@ -42,6 +42,38 @@ fn find_all_bench(c: &mut Criterion) {
   });
 }
 
+fn non_match_bench(c: &mut Criterion) {
+  let lang = SupportLang::TypeScript;
+  // calls are everywhere in checker.ts but few of them have four arguments
+  let pattern = Pattern::str(black_box("$F($A, $B, $C, $D)"), lang);
+  let checker_sg = get_sg("fixtures/checker.ts.fixture");
+  c.bench_function("non-matching call(checker.ts)", |b| {
+    b.iter(|| find_pattern(&checker_sg, &pattern))
+  });
+  // most calls share the root kind but differ at the callee
+  let pattern = Pattern::str(black_box("Debug.assert($$$)"), lang);
+  c.bench_function("first child mismatch(checker.ts)", |b| {
+    b.iter(|| find_pattern(&checker_sg, &pattern))
+  });
+  // $A is bound by the condition before the body fails to match
+  let pattern = Pattern::str(black_box("if ($A) { return $A; }"), lang);
+  c.bench_function("near-miss binding(checker.ts)", |b| {
+    b.iter(|| find_pattern(&checker_sg, &pattern))
+  });
+}
+
+fn count_bench(c: &mut Criterion) {
+  let lang = SupportLang::TypeScript;
+  let pattern = Pattern::str(black_box("$F($$$ARGS)"), lang);
+  let checker_sg = get_sg("fixtures/checker.ts.fixture");
+  c.bench_function("find_all count(checker.ts)", |b| {
+    b.iter(|| checker_sg.root().find_all(&pattern).count())
+  });
+  c.bench_function("count_matches(checker.ts)", |b| {
+    b.iter(|| pattern.count_matches(checker_sg.root()))
+  });
+}
+
 fn rule_bench(c: &mut Criterion) {
   let ref_sg = get_sg("fixtures/ref.ts.fixture");
   let rule = read_rule();
@@ -57,5 +89,12 @@ fn build_pattern_bench(c: &mut Criterion) {
   });
 }
 
-criterion_group!(benches, find_all_bench, rule_bench, build_pattern_bench);
+criterion_group!(
+  benches,
+  find_all_bench,
+  non_match_bench,
+  count_bench,
+  rule_bench,
+  build_pattern_bench
+);
 criterion_main!(benches);
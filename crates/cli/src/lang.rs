@@ -3,6 +3,7 @@ mod lang_globs;
 
 use anyhow::Result;
 use ast_grep_core::language::TSLanguage;
+use ast_grep_core::{Doc, Node};
 use ast_grep_dynamic::DynamicLang;
 use ast_grep_language::{Language, SupportLang};
 use ignore::types::Types;
@@ -133,6 +134,27 @@ impl Language for SgLang {
     }
   }
 
+  fn is_trivial<D: Doc>(&self, node: &Node<D>) -> bool {
+    match self {
+      Builtin(b) => b.is_trivial(node),
+      Custom(c) => c.is_trivial(node),
+    }
+  }
+
+  fn leaf_kinds_compatible(&self, goal_kind: u16, candidate_kind: u16) -> bool {
+    match self {
+      Builtin(b) => b.leaf_kinds_compatible(goal_kind, candidate_kind),
+      Custom(c) => c.leaf_kinds_compatible(goal_kind, candidate_kind),
+    }
+  }
+
+  fn terminator_tokens(&self) -> &'static [&'static str] {
+    match self {
+      Builtin(b) => b.terminator_tokens(),
+      Custom(c) => c.terminator_tokens(),
+    }
+  }
+
   #[inline]
   fn meta_var_char(&self) -> char {
     match self {
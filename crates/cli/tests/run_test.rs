@@ -31,3 +31,24 @@ fn test_simple_specific_lang() -> Result<()> {
     .stdout(contains("console.log(456)"));
   Ok(())
 }
+
+#[test]
+fn test_repeated_meta_var_leaf_kinds() -> Result<()> {
+  let dir = create_test_files([
+    ("a.ts", "foo(a, x.a)"),
+    ("b.rs", "fn m() { Foo { a: a }; }"),
+  ])?;
+  Command::cargo_bin("sg")?
+    .current_dir(dir.path())
+    .args(["-p", "foo($A, x.$A)", "-l", "ts"])
+    .assert()
+    .success()
+    .stdout(contains("foo(a, x.a)"));
+  Command::cargo_bin("sg")?
+    .current_dir(dir.path())
+    .args(["-p", "Foo { $A: $A }", "-l", "rs"])
+    .assert()
+    .success()
+    .stdout(contains("Foo { a: a }"));
+  Ok(())
+}
@@ -1,6 +1,6 @@
 use crate::meta_var::{extract_meta_var, MetaVariable};
 use crate::AstGrep;
-use crate::StrDoc;
+use crate::{Doc, Node, StrDoc};
 use std::borrow::Cow;
 use std::path::Path;
 pub use tree_sitter::Language as TSLanguage;
@@ -28,6 +28,25 @@ pub trait Language: Clone {
     &[]
   }
 
+  /// Whether a candidate node is trivial and can be skipped if the pattern does not match it.
+  /// By default unnamed nodes like punctuation are trivial.
+  /// Override it if some unnamed tokens are significant, e.g. indentation tokens or operators.
+  fn is_trivial<D: Doc>(&self, node: &Node<D>) -> bool {
+    !node.is_named()
+  }
+
+  /// Whether two named leaves of different kinds with the same text are the same, e.g. when a repeated
+  /// meta variable is checked. By default only the text is compared, see gh#1087.
+  /// Override it to reject leaves of unrelated kinds, e.g. string_fragment `x` and identifier `x`.
+  fn leaf_kinds_compatible(&self, _goal_kind: u16, _candidate_kind: u16) -> bool {
+    true
+  }
+
+  /// Tokens that end a statement, e.g. `;` in C-like languages, see `NodeMatch::has_terminator`.
+  fn terminator_tokens(&self) -> &'static [&'static str] {
+    &[";"]
+  }
+
   /// normalize pattern code before matching
   /// e.g. remove expression_statement, or prefer parsing {} to object over block
   fn pre_process_pattern<'q>(&self, query: &'q str) -> Cow<'q, str> {
@@ -21,13 +21,14 @@ mod match_tree;
 mod node;
 
 pub use language::Language;
-pub use matcher::{Matcher, NodeMatch, Pattern, PatternError};
-pub use node::Node;
+pub use matcher::{MatchOptions, Matcher, NodeMatch, Pattern, PatternError};
+pub use node::{ColumnUnit, Node};
 pub use source::{Doc, StrDoc};
 
 #[doc(hidden)]
 pub use node::DisplayContext;
 
+use meta_var::MetaVarEnv;
 use replacer::Replacer;
 
 use node::Root;
@@ -95,6 +96,39 @@ impl<L: Language> AstGrep<StrDoc<L>> {
   pub fn generate(self) -> String {
     self.inner.doc.src
   }
+
+  /// Find all matches of `matcher` and rewrite them with the text returned by `f`.
+  /// A match is left untouched if `f` returns `None`. Matches nested in a rewritten
+  /// match are skipped so that all rewrites are non-overlapping.
+  /// Returns the edited source while `self` remains unchanged.
+  pub fn find_and_rewrite<M, F>(&self, matcher: M, f: F) -> String
+  where
+    M: Matcher<L>,
+    F: Fn(&MetaVarEnv<StrDoc<L>>) -> Option<String>,
+  {
+    let src = self.source();
+    let mut ret = String::with_capacity(src.len());
+    // end offset of the last rewrite, matches starting before it overlap
+    let mut written = 0;
+    for nm in self.root().find_all(&matcher) {
+      let start = nm.range().start;
+      if start < written {
+        continue;
+      }
+      let Some(text) = f(nm.get_env()) else {
+        continue;
+      };
+      let end = match matcher.get_match_len(nm.get_node().clone()) {
+        Some(len) => start + len,
+        None => nm.range().end,
+      };
+      ret.push_str(&src[written..start]);
+      ret.push_str(&text);
+      written = end;
+    }
+    ret.push_str(&src[written..]);
+    ret
+  }
 }
 
 #[cfg(test)]
@@ -149,6 +183,33 @@ mod test {
     Ok(())
   }
 
+  #[test]
+  fn test_find_and_rewrite() {
+    let grep = Tsx.ast_grep("let a = foo(1); let b = foo(2); let c = foo(3);");
+    let ret = grep.find_and_rewrite("foo($A)", |env| {
+      let a = env.get_match("A")?;
+      (a.text() != "2").then(|| format!("bar({})", a.text()))
+    });
+    assert_eq!(ret, "let a = bar(1); let b = foo(2); let c = bar(3);");
+    // source is not modified
+    assert_eq!(
+      grep.source(),
+      "let a = foo(1); let b = foo(2); let c = foo(3);"
+    );
+  }
+
+  #[test]
+  fn test_find_and_rewrite_nested() {
+    let grep = Tsx.ast_grep("Some(Some(1)); Some(Some(2))");
+    let ret = grep.find_and_rewrite("Some($A)", |env| {
+      let a = env.get_match("A")?;
+      (a.text() != "Some(2)").then(|| a.text().to_string())
+    });
+    // outer match of first statement is rewritten and inner one is skipped
+    // outer match of second statement is left so inner one is rewritten
+    assert_eq!(ret, "Some(1); Some(2)");
+  }
+
   #[test]
   fn test_replace_trivia_with_skipped() -> Result {
     let mut ast_grep = Tsx.ast_grep("return foo(1, 2,) /*haha*/;");
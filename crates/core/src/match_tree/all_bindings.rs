@@ -0,0 +1,352 @@
+use super::descendant::descendants;
+use super::repeat;
+use super::{
+  lines_contiguous, match_leaf_meta_var, match_root_ellipsis, separates_ellipsis,
+  try_get_ellipsis_mode, MatchOptions,
+};
+use crate::meta_var::{MetaVarEnv, MetaVariable};
+use crate::{Doc, Node, Pattern};
+
+use std::borrow::Cow;
+
+use thiserror::Error;
+
+/// Enumerating bindings gave up after the steps allowed by [`MatchOptions::max_backtrack_steps`].
+#[derive(Debug, Error, Clone, PartialEq, Eq)]
+#[error("Matching gave up after {0} backtracking steps.")]
+pub struct BacktrackLimitExceeded(pub usize);
+
+/// Enumerates every env under which goal matches candidate.
+/// Unlike `match_node_non_recursive`, which commits to the first split an ellipsis finds,
+/// this backtracks over all split points of every ellipsis, including nested ones.
+/// Envs that bind the same ranges are reported once, in the order they are found.
+pub fn match_all_bindings<'tree, D: Doc>(
+  goal: &Pattern<D::Lang>,
+  candidate: Node<'tree, D>,
+) -> Vec<MetaVarEnv<'tree, D>> {
+  let opts = MatchOptions::default();
+  match_all_bindings_with_options(goal, candidate, &opts).expect("unlimited steps never give up")
+}
+
+/// Like [`match_all_bindings`] but gives up once [`MatchOptions::max_backtrack_steps`] is exceeded.
+/// Other options are not applied to the enumeration.
+pub fn match_all_bindings_with_options<'tree, D: Doc>(
+  goal: &Pattern<D::Lang>,
+  candidate: Node<'tree, D>,
+  opts: &MatchOptions,
+) -> Result<Vec<MetaVarEnv<'tree, D>>, BacktrackLimitExceeded> {
+  let mut steps = Steps {
+    taken: 0,
+    max: opts.max_backtrack_steps,
+  };
+  let envs = all_node(goal, candidate, MetaVarEnv::new(), &mut steps);
+  if let Some(max) = steps.max.filter(|_| steps.exceeded()) {
+    return Err(BacktrackLimitExceeded(max));
+  }
+  let mut found = vec![];
+  for env in envs {
+    let key = binding_key(&env);
+    if !found.iter().any(|(k, _)| *k == key) {
+      found.push((key, env));
+    }
+  }
+  Ok(found.into_iter().map(|(_, env)| env).collect())
+}
+
+/// Splits tried so far, exploration stops once `max` is exceeded.
+struct Steps {
+  taken: usize,
+  max: Option<usize>,
+}
+
+impl Steps {
+  fn take(&mut self) -> bool {
+    self.taken += 1;
+    !self.exceeded()
+  }
+  fn exceeded(&self) -> bool {
+    matches!(self.max, Some(max) if self.taken > max)
+  }
+}
+
+fn all_node<'tree, D: Doc>(
+  goal: &Pattern<D::Lang>,
+  candidate: Node<'tree, D>,
+  env: MetaVarEnv<'tree, D>,
+  steps: &mut Steps,
+) -> Vec<MetaVarEnv<'tree, D>> {
+  match goal {
+    Pattern::Terminal { text, kind_id, .. } => {
+      if *kind_id == candidate.kind_id() && *text == candidate.text() {
+        vec![env]
+      } else {
+        vec![]
+      }
+    }
+    Pattern::MetaVar { meta_var, .. } => {
+      let mut env = Cow::Owned(env);
+      let opts = MatchOptions::default();
+      let matched = match try_get_ellipsis_mode(goal) {
+        Ok(name) => match_root_ellipsis(goal, &name, candidate, &mut env, &opts),
+        Err(_) => match_leaf_meta_var(meta_var, candidate, &mut env, &opts, None),
+      };
+      match matched {
+        Some(_) => vec![env.into_owned()],
+        None => vec![],
+      }
+    }
+    Pattern::Internal {
+      kind_id, children, ..
+    } => {
+      if *kind_id != candidate.kind_id() {
+        return vec![];
+      }
+      let cands: Vec<_> = candidate.children().collect();
+      let mut found = vec![];
+      all_children(children, &cands, env, &mut found, steps);
+      found
+    }
+    Pattern::Optional(p) => all_node(p, candidate, env, steps),
+    Pattern::Descendant {
+      meta_var,
+      inner,
+      max_depth,
+    } => {
+      let mut env = Cow::Owned(env);
+      let opts = MatchOptions::default();
+      if match_leaf_meta_var(meta_var, candidate.clone(), &mut env, &opts, None).is_none() {
+        return vec![];
+      }
+      // every matching descendant gives its own bindings
+      let env = env.into_owned();
+      descendants(&candidate, *max_depth)
+        .into_iter()
+        .flat_map(|sub| all_node(inner, sub, env.clone(), steps))
+        .collect()
+    }
+    // a repeated group only matches sibling candidates
+    Pattern::Repeat(_) => vec![],
+  }
+}
+
+fn all_children<'tree, D: Doc>(
+  goals: &[Pattern<D::Lang>],
+  cands: &[Node<'tree, D>],
+  env: MetaVarEnv<'tree, D>,
+  found: &mut Vec<MetaVarEnv<'tree, D>>,
+  steps: &mut Steps,
+) {
+  if !steps.take() {
+    return;
+  }
+  let Some((goal, rest)) = goals.split_first() else {
+    // all goals are matched, remaining candidates are ignored like match_node_non_recursive
+    found.push(env);
+    return;
+  };
+  if let Ok(name) = try_get_ellipsis_mode(goal) {
+    // trivial goals after ellipsis are optional, e.g. the comma in `$$$A, $$$B`
+    let skipped = rest.iter().take_while(|g| g.is_trivial()).count();
+    let rest = &rest[skipped..];
+    // ellipsis at the end consumes all candidates
+    let min_len = if rest.is_empty() { cands.len() } else { 0 };
+    let line_contiguous = matches!(
+      goal,
+      Pattern::MetaVar {
+        meta_var: MetaVariable::LineContiguousCapture(_),
+        ..
+      }
+    );
+    for len in min_len..=cands.len() {
+      if steps.exceeded() {
+        return;
+      }
+      let mut env = env.clone();
+      if let Some(name) = &name {
+        let captured = trim_unnamed(&cands[..len]).to_vec();
+        if line_contiguous && !lines_contiguous(&captured) {
+          continue;
+        }
+        if env.insert_multi(name, captured).is_none() {
+          continue;
+        }
+      }
+      all_children(rest, &cands[len..], env, found, steps);
+    }
+    return;
+  }
+  if let Pattern::Repeat(group) = goal {
+    // repetitions are greedy, only one split is tried
+    let mut env = Cow::Owned(env);
+    let opts = MatchOptions::default();
+    if let Some(len) = repeat::match_repeat(group, cands, &mut env, &opts, None) {
+      all_children(rest, &cands[len..], env.into_owned(), found, steps);
+    }
+    return;
+  }
+  if let Pattern::Optional(inner) = goal {
+    // an optional goal is either present or absent
+    one_child(inner, rest, cands, env.clone(), found, steps);
+    all_children(rest, cands, env, found, steps);
+    return;
+  }
+  let before = found.len();
+  one_child(goal, rest, cands, env.clone(), found, steps);
+  // a separator before an ellipsis may be absent, e.g. the last comma in `foo(a, $$$A, a, $$$B)`
+  if found.len() == before && separates_ellipsis(goals.iter()) {
+    all_children(rest, cands, env, found, steps);
+  }
+}
+
+fn one_child<'tree, D: Doc>(
+  goal: &Pattern<D::Lang>,
+  rest: &[Pattern<D::Lang>],
+  cands: &[Node<'tree, D>],
+  env: MetaVarEnv<'tree, D>,
+  found: &mut Vec<MetaVarEnv<'tree, D>>,
+  steps: &mut Steps,
+) {
+  // skip unnamed candidates that goal does not match
+  for (i, cand) in cands.iter().enumerate() {
+    let envs = all_node(goal, cand.clone(), env.clone(), steps);
+    if !envs.is_empty() {
+      for env in envs {
+        all_children(rest, &cands[i + 1..], env, found, steps);
+      }
+      return;
+    }
+    if cand.is_named() {
+      return;
+    }
+  }
+}
+
+pub(super) fn trim_unnamed<'a, 'tree, D: Doc>(nodes: &'a [Node<'tree, D>]) -> &'a [Node<'tree, D>] {
+  let start = nodes.iter().position(Node::is_named).unwrap_or(nodes.len());
+  let end = nodes
+    .iter()
+    .rposition(Node::is_named)
+    .map_or(start, |i| i + 1);
+  &nodes[start..end]
+}
+
+type BindingKey = Vec<(String, Vec<(usize, usize)>)>;
+
+fn binding_key<D: Doc>(env: &MetaVarEnv<D>) -> BindingKey {
+  use crate::meta_var::MetaVariable as MV;
+  let mut key: BindingKey = env
+    .get_matched_variables()
+    .filter_map(|var| match var {
+      MV::Capture(name, _) => {
+        let range = env.get_match(&name)?.range();
+        Some((name, vec![(range.start, range.end)]))
+      }
+      MV::MultiCapture(name) | MV::LineContiguousCapture(name) => {
+        let ranges = env.get_multiple_matches(&name);
+        let ranges = ranges.iter().map(|n| (n.range().start, n.range().end));
+        let ranges = ranges.collect();
+        Some((name, ranges))
+      }
+      _ => None,
+    })
+    .collect();
+  key.sort();
+  key
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::language::{Language, Tsx};
+  use std::collections::HashMap;
+
+  fn all_bindings(pattern: &str, src: &str) -> Vec<HashMap<String, String>> {
+    let goal = Pattern::new(pattern, Tsx);
+    let grep = Tsx.ast_grep(src);
+    let cand = grep.root().find("foo($$$)").expect("should find foo");
+    let envs = match_all_bindings(&goal, cand.get_node().clone());
+    envs.into_iter().map(HashMap::from).collect()
+  }
+
+  fn split(envs: &[HashMap<String, String>], a: &str, b: &str) -> Vec<(String, String)> {
+    let get = |env: &HashMap<String, String>, k: &str| env.get(k).cloned().unwrap_or_default();
+    envs.iter().map(|env| (get(env, a), get(env, b))).collect()
+  }
+
+  #[test]
+  fn test_ellipsis_splits() {
+    let envs = all_bindings("foo($$$A, $$$B)", "foo(a, b)");
+    let splits = split(&envs, "A", "B");
+    assert_eq!(
+      splits,
+      [
+        ("[]".into(), "[a, ,, b]".into()),
+        ("[a]".into(), "[b]".into()),
+        ("[a, ,, b]".into(), "[]".into()),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_single_split() {
+    // the first split found by $$$A does not match but a later one does
+    let envs = all_bindings("foo($$$A, $B)", "foo(a, b, c)");
+    assert_eq!(split(&envs, "A", "B"), [("[a, ,, b]".into(), "c".into())]);
+  }
+
+  #[test]
+  fn test_splits_with_meta_var() {
+    // $B can be any argument, the comma before $$$C is absent if $B is the last one
+    let envs = all_bindings("foo($$$A, $B, $$$C)", "foo(a, b, c)");
+    assert_eq!(
+      split(&envs, "A", "B"),
+      [
+        ("[]".into(), "a".into()),
+        ("[a]".into(), "b".into()),
+        ("[a, ,, b]".into(), "c".into()),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_nested_splits() {
+    let envs = all_bindings("foo(bar($$$A, $$$B), $$$C)", "foo(bar(a, b), c)");
+    assert_eq!(envs.len(), 3);
+    assert!(envs.iter().all(|env| env["C"] == "[c]"));
+  }
+
+  #[test]
+  fn test_repeated_anchor_splits() {
+    // `a $$$ a` against `a a a`: the second anchor can be either trailing `a`
+    let envs = all_bindings("foo(a, $$$A, a, $$$B)", "foo(a, a, a)");
+    assert_eq!(
+      split(&envs, "A", "B"),
+      [("[]".into(), "[a]".into()), ("[a]".into(), "[]".into())]
+    );
+  }
+
+  #[test]
+  fn test_max_backtrack_steps() {
+    // no split of the ellipses can match the missing `x`, so every split is tried
+    let goal = Pattern::new("foo($$$A, $$$B, $$$C, $$$D, x)", Tsx);
+    let args = vec!["a"; 30].join(", ");
+    let grep = Tsx.ast_grep(format!("foo({args})"));
+    let cand = grep.root().find("foo($$$)").expect("should find foo");
+    let opts = MatchOptions::default().max_backtrack_steps(Some(1000));
+    let res = match_all_bindings_with_options(&goal, cand.get_node().clone(), &opts);
+    assert_eq!(res.err(), Some(BacktrackLimitExceeded(1000)));
+    // a small enough input is enumerated within the same budget
+    let grep = Tsx.ast_grep("foo(a, x)");
+    let cand = grep.root().find("foo($$$)").expect("should find foo");
+    let envs = match_all_bindings_with_options(&goal, cand.get_node().clone(), &opts);
+    assert_eq!(envs.expect("should not give up").len(), 4);
+  }
+
+  #[test]
+  fn test_consistent_splits() {
+    // both ellipses must capture the same nodes
+    let envs = all_bindings("foo($$$A, x, $$$A)", "foo(a, x, a)");
+    assert_eq!(envs.len(), 1);
+    assert_eq!(envs[0]["A"], "[a]");
+  }
+}
@@ -0,0 +1,104 @@
+use super::{match_node_non_recursive, MatchOptions, Validator};
+use crate::meta_var::{rollback_env, MetaVarEnv};
+use crate::{Doc, Language, Node, Pattern};
+
+use std::borrow::Cow;
+
+/// Returns None if flattening does not apply, i.e. goal and candidate are not chains of the same
+/// associative operator, see [`MatchOptions::associative_operators`]. Otherwise returns whether the
+/// goal's operands match a contiguous run of the candidate's operands, e.g. `a + b` in `c + a + b`.
+pub(super) fn match_flattened<'tree, D: Doc>(
+  goal: &Pattern<D::Lang>,
+  candidate: &Node<'tree, D>,
+  env: &mut Cow<MetaVarEnv<'tree, D>>,
+  opts: &MatchOptions,
+  validator: Validator<'_, 'tree, D>,
+) -> Option<bool> {
+  let Pattern::Internal { kind_id, .. } = goal else {
+    return None;
+  };
+  let op = goal_operator(goal)?;
+  if !opts.is_associative(*kind_id, op) || candidate.kind_id() != *kind_id {
+    return None;
+  }
+  if candidate_operator(candidate).as_deref() != Some(op) {
+    return None;
+  }
+  let mut goals = vec![];
+  flatten_goal(goal, *kind_id, op, &mut goals);
+  let mut cands = vec![];
+  flatten_candidate(candidate.clone(), *kind_id, op, &mut cands);
+  if goals.len() > cands.len() {
+    return Some(false);
+  }
+  for start in 0..=cands.len() - goals.len() {
+    let checkpoint = env.checkpoint();
+    let window = cands[start..].iter().cloned();
+    let matched = goals
+      .iter()
+      .zip(window)
+      .all(|(g, c)| match_node_non_recursive(g, c, env, opts, validator).is_some());
+    if matched {
+      return Some(true);
+    }
+    rollback_env(env, checkpoint);
+  }
+  Some(false)
+}
+
+/// The operator of a binary goal like `$A + $B`, an unnamed terminal between two operands.
+fn goal_operator<L: Language>(goal: &Pattern<L>) -> Option<&str> {
+  match goal {
+    Pattern::Internal { children, .. } if children.len() == 3 => match &children[1] {
+      Pattern::Terminal {
+        text,
+        is_named: false,
+        ..
+      } => Some(text),
+      _ => None,
+    },
+    _ => None,
+  }
+}
+
+fn candidate_operator<D: Doc>(node: &Node<D>) -> Option<String> {
+  if node.inner.child_count() != 3 {
+    return None;
+  }
+  let op = node.child(1)?;
+  (!op.is_named()).then(|| op.text().to_string())
+}
+
+fn flatten_goal<'p, L: Language>(
+  goal: &'p Pattern<L>,
+  kind: u16,
+  op: &str,
+  out: &mut Vec<&'p Pattern<L>>,
+) {
+  match goal {
+    Pattern::Internal {
+      kind_id, children, ..
+    } if *kind_id == kind && goal_operator(goal) == Some(op) => {
+      flatten_goal(&children[0], kind, op, out);
+      flatten_goal(&children[2], kind, op, out);
+    }
+    _ => out.push(goal),
+  }
+}
+
+fn flatten_candidate<'t, D: Doc>(
+  node: Node<'t, D>,
+  kind: u16,
+  op: &str,
+  out: &mut Vec<Node<'t, D>>,
+) {
+  let is_chain = node.kind_id() == kind && candidate_operator(&node).as_deref() == Some(op);
+  // a chain node has exactly three children, see candidate_operator
+  match (is_chain, node.child(0), node.child(2)) {
+    (true, Some(left), Some(right)) => {
+      flatten_candidate(left, kind, op, out);
+      flatten_candidate(right, kind, op, out);
+    }
+    _ => out.push(node),
+  }
+}
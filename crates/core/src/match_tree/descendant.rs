@@ -0,0 +1,104 @@
+use super::{match_leaf_meta_var, match_node_non_recursive, MatchOptions, Validator};
+use crate::meta_var::{rollback_env, MetaVarEnv, MetaVariable};
+use crate::{Doc, Node, Pattern};
+
+use std::borrow::Cow;
+
+/// Matches [`Pattern::Descendant`]: binds `meta_var` to candidate and finds the first descendant,
+/// at most `max_depth` levels below it, that `inner` matches. Returns the candidate if found.
+/// Bindings are rolled back if no descendant matches.
+pub(super) fn match_descendant<'tree, D: Doc>(
+  meta_var: &MetaVariable,
+  inner: &Pattern<D::Lang>,
+  max_depth: usize,
+  candidate: Node<'tree, D>,
+  env: &mut Cow<MetaVarEnv<'tree, D>>,
+  opts: &MatchOptions,
+  validator: Validator<'_, 'tree, D>,
+) -> Option<Node<'tree, D>> {
+  let checkpoint = env.checkpoint();
+  let node = match_leaf_meta_var(meta_var, candidate, env, opts, validator)?;
+  for sub in descendants(&node, max_depth) {
+    let tried = env.checkpoint();
+    if match_node_non_recursive(inner, sub, env, opts, validator).is_some() {
+      return Some(node);
+    }
+    rollback_env(env, tried);
+  }
+  rollback_env(env, checkpoint);
+  None
+}
+
+/// Descendants of node at most `max_depth` levels below it, in pre-order. The node itself is excluded.
+pub(super) fn descendants<'t, D: Doc>(node: &Node<'t, D>, max_depth: usize) -> Vec<Node<'t, D>> {
+  let mut found = vec![];
+  let mut stack: Vec<_> = children_rev(node).map(|c| (c, 1)).collect();
+  while let Some((sub, depth)) = stack.pop() {
+    if depth < max_depth {
+      stack.extend(children_rev(&sub).map(|c| (c, depth + 1)));
+    }
+    found.push(sub);
+  }
+  found
+}
+
+fn children_rev<'t, D: Doc>(node: &Node<'t, D>) -> impl Iterator<Item = Node<'t, D>> {
+  let children: Vec<_> = node.children().collect();
+  children.into_iter().rev()
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::language::{Language, Tsx};
+
+  fn descendant_pattern(max_depth: usize) -> Pattern<Tsx> {
+    let inner = Pattern::new("foo($X)", Tsx);
+    Pattern::new("const $F = () => $BODY", Tsx).with_descendant("BODY", inner, max_depth)
+  }
+
+  #[test]
+  fn test_nested_call() {
+    let src = "const f = () => { if (a) { for (;;) { bar(foo(1)) } } }";
+    let grep = Tsx.ast_grep(src);
+    let found = grep
+      .root()
+      .find(descendant_pattern(usize::MAX))
+      .expect("should match");
+    let env = found.get_env();
+    assert_eq!(env.get_match("F").map(|n| n.text()), Some("f".into()));
+    assert_eq!(env.get_match("X").map(|n| n.text()), Some("1".into()));
+    assert!(env.get_match("BODY").is_some());
+    let vars = descendant_pattern(usize::MAX).defined_vars().len();
+    assert_eq!(vars, 3);
+  }
+
+  #[test]
+  fn test_depth_bound() {
+    let grep = Tsx.ast_grep("const f = () => { if (a) { foo(1) } }");
+    // body > if > block > statement > call
+    assert!(grep.root().find(descendant_pattern(3)).is_none());
+    assert!(grep.root().find(descendant_pattern(4)).is_some());
+  }
+
+  #[test]
+  fn test_no_descendant() {
+    let grep = Tsx.ast_grep("const f = () => { bar(1) }; foo(2)");
+    assert!(grep.root().find(descendant_pattern(usize::MAX)).is_none());
+    // the node itself is not its descendant
+    let grep = Tsx.ast_grep("const f = () => foo(1)");
+    assert!(grep.root().find(descendant_pattern(usize::MAX)).is_none());
+  }
+
+  #[test]
+  fn test_descendants_order() {
+    let grep = Tsx.ast_grep("a(b(c), d)");
+    let call = grep.root().find("a($$$)").expect("should find call");
+    let texts: Vec<_> = descendants(call.get_node(), 2)
+      .iter()
+      .filter(|n| n.is_named())
+      .map(|n| n.text().to_string())
+      .collect();
+    assert_eq!(texts, ["a", "(b(c), d)", "b(c)", "d"]);
+  }
+}
@@ -0,0 +1,2035 @@
+mod all_bindings;
+mod associative;
+mod descendant;
+mod options;
+mod partial;
+mod repeat;
+mod set;
+mod trace;
+
+use crate::meta_var::{rollback_env, MetaVarEnv, MetaVariable};
+use crate::{Doc, Language, Node, Pattern};
+
+use smallvec::SmallVec;
+use std::borrow::Cow;
+use std::ops::Range;
+
+pub use all_bindings::{
+  match_all_bindings, match_all_bindings_with_options, BacktrackLimitExceeded,
+};
+pub(crate) use options::STANDARD_OPTIONS;
+pub use options::{IdentityResolver, MatchOptions, MATCH_VAR, MAX_TRANSPARENT_DEPTH};
+pub use partial::{match_partial, PartialMatch};
+#[cfg(feature = "trace")]
+pub use trace::{near_misses, trace_match, NearMiss};
+pub use trace::{MatchTrace, TraceEvent};
+
+/// Candidates consumed by an ellipsis. Only a named ellipsis keeps the nodes for its capture,
+/// an unnamed one just counts the named nodes, so a huge child list is neither cloned nor collected.
+struct EllipsisMatched<'t, D: Doc> {
+  /// moved into env as is, an empty Vec does not allocate
+  nodes: Vec<Node<'t, D>>,
+  uncaptured_named: usize,
+  /// whether the captured nodes must be on consecutive lines, see `MetaVariable::MultiCapture`
+  line_contiguous: bool,
+}
+
+impl<'t, D: Doc> EllipsisMatched<'t, D> {
+  fn new(goal: &Pattern<D::Lang>) -> Self {
+    let line_contiguous = matches!(
+      goal,
+      Pattern::MetaVar {
+        meta_var: MetaVariable::LineContiguousCapture(_),
+        ..
+      }
+    );
+    Self {
+      nodes: Vec::new(),
+      uncaptured_named: 0,
+      line_contiguous,
+    }
+  }
+
+  fn consume(&mut self, node: Node<'t, D>, capture: bool) {
+    if capture {
+      self.nodes.push(node);
+    } else if node.is_named() {
+      self.uncaptured_named += 1;
+    }
+  }
+}
+
+/// Custom check consulted before a meta variable is bound, called with its name and the candidate.
+/// A capture is rejected if it returns false.
+pub(crate) type Validator<'v, 'tree, D> = Option<&'v dyn Fn(&str, &Node<'tree, D>) -> bool>;
+
+fn match_leaf_meta_var<'tree, D: Doc>(
+  mv: &MetaVariable,
+  candidate: Node<'tree, D>,
+  env: &mut Cow<MetaVarEnv<'tree, D>>,
+  opts: &MatchOptions,
+  validator: Validator<'_, 'tree, D>,
+) -> Option<Node<'tree, D>> {
+  use MetaVariable as MV;
+  if !matches!(
+    mv,
+    MV::Multiple | MV::MultiCapture(_) | MV::LineContiguousCapture(_)
+  ) && !opts.can_capture(&candidate)
+  {
+    trace::record(|| TraceEvent::Option {
+      name: "forbid_trivia_capture",
+    });
+    return None;
+  }
+  match mv {
+    MV::Capture(name, named) => {
+      if *named && !candidate.is_named() {
+        trace::record(|| TraceEvent::UnnamedCandidate);
+        None
+      } else if !opts.text_glob_matches(name, &candidate)
+        || !opts.string_quote_matches(name, &candidate)
+      {
+        trace::record(|| TraceEvent::Rejected {
+          name: format!("${name}"),
+        });
+        None
+      } else {
+        let node = bind_leaf(name, *named, candidate, env, opts, validator)?;
+        options::bind_block_body(name, &node, env, opts)?;
+        Some(node)
+      }
+    }
+    MV::Dropped(named) => {
+      if *named && !candidate.is_named() {
+        trace::record(|| TraceEvent::UnnamedCandidate);
+        None
+      } else {
+        Some(candidate)
+      }
+    }
+    // Ellipsis will be matched in parent level
+    MV::Multiple => {
+      debug_assert!(false, "Ellipsis should be matched in parent level");
+      Some(candidate)
+    }
+    MV::MultiCapture(name) | MV::LineContiguousCapture(name) => {
+      bind_leaf(name, false, candidate, env, opts, validator)
+    }
+  }
+}
+
+/// Ellipses are matched by the parent's children, so an ellipsis reaching a single candidate is
+/// the whole pattern, e.g. `$$$A`. It matches the candidate's children without the leading and trailing
+/// unnamed ones like brackets, so `$$$A` against the arguments `(a, b)` binds `a`, `,` and `b`.
+/// A leaf candidate has no children and binds an empty list.
+fn match_root_ellipsis<'tree, D: Doc>(
+  goal: &Pattern<D::Lang>,
+  name: &Option<String>,
+  candidate: Node<'tree, D>,
+  env: &mut Cow<MetaVarEnv<'tree, D>>,
+  opts: &MatchOptions,
+) -> Option<Node<'tree, D>> {
+  let children: Vec<_> = candidate.children().collect();
+  let children = all_bindings::trim_unnamed(&children).iter().cloned();
+  update_ellipsis_env(name, EllipsisMatched::new(goal), env, children, &[], opts)?;
+  Some(candidate)
+}
+
+fn bind_leaf<'tree, D: Doc>(
+  name: &str,
+  named: bool,
+  candidate: Node<'tree, D>,
+  env: &mut Cow<MetaVarEnv<'tree, D>>,
+  opts: &MatchOptions,
+  validator: Validator<'_, 'tree, D>,
+) -> Option<Node<'tree, D>> {
+  if matches!(validator, Some(valid) if !valid(name, &candidate)) {
+    trace::record(|| TraceEvent::Rejected {
+      name: format!("${name}"),
+    });
+    return None;
+  }
+  let equal = |bound: &Node<D>, cand: &Node<D>| opts.bound_node_matches(bound, cand);
+  if env
+    .to_mut()
+    .insert_capture_by(name, candidate.clone(), named, equal)
+    .is_none()
+  {
+    trace::record(|| TraceEvent::BindConflict {
+      name: format!("${name}"),
+    });
+    return None;
+  }
+  trace::record(|| TraceEvent::Bind {
+    name: format!("${name}"),
+    text: candidate.text().to_string(),
+  });
+  Some(candidate)
+}
+
+/// Returns Ok if ellipsis pattern is found. If the ellipsis is named, returns it name.
+/// If the ellipsis is unnamed, returns None. If it is not ellipsis node, returns Err.
+fn try_get_ellipsis_mode(node: &Pattern<impl Language>) -> Result<Option<String>, ()> {
+  let Pattern::MetaVar { meta_var, .. } = node else {
+    return Err(());
+  };
+  match meta_var {
+    MetaVariable::Multiple => Ok(None),
+    MetaVariable::MultiCapture(n) | MetaVariable::LineContiguousCapture(n) => Ok(Some(n.into())),
+    _ => Err(()),
+  }
+}
+
+/// Trivial goals skipped after an ellipsis, e.g. the comma in `$$$A, $B`.
+type SkippedTrivia<'p, L> = SmallVec<[&'p Pattern<L>; 2]>;
+
+fn update_ellipsis_env<'t, D: Doc>(
+  optional_name: &Option<String>,
+  matched: EllipsisMatched<'t, D>,
+  env: &mut Cow<MetaVarEnv<'t, D>>,
+  cand_children: impl Iterator<Item = Node<'t, D>>,
+  skipped: &[&Pattern<D::Lang>],
+  opts: &MatchOptions,
+) -> Option<()> {
+  if let Some(name) = optional_name.as_ref() {
+    let line_contiguous = matched.line_contiguous;
+    let mut matched = matched.nodes;
+    matched.extend(cand_children);
+    // trailing candidates of the skipped trivial goals are not captured.
+    // a skipped goal may have no candidate, e.g. the optional trailing comma in `foo($$$A,)`
+    for goal in skipped.iter().rev() {
+      let Pattern::Terminal { kind_id, .. } = goal else {
+        continue;
+      };
+      if matches!(matched.last(), Some(n) if !n.is_named() && n.kind_id() == *kind_id) {
+        matched.pop();
+      }
+    }
+    let min_named = opts.min_named_of(name);
+    if min_named > 0 && matched.iter().filter(|n| n.is_named()).count() < min_named {
+      trace::record(|| TraceEvent::Rejected {
+        name: format!("$$${name}"),
+      });
+      return None;
+    }
+    if line_contiguous && !lines_contiguous(&matched) {
+      trace::record(|| TraceEvent::Rejected {
+        name: format!("$$$${name}"),
+      });
+      return None;
+    }
+    // nodes are moved into env, keep their text for tracing
+    let text = trace::is_enabled().then(|| {
+      let texts: Vec<_> = matched.iter().map(|n| n.text()).collect();
+      texts.join(" ")
+    });
+    if env.to_mut().insert_multi(name, matched).is_none() {
+      trace::record(|| TraceEvent::BindConflict {
+        name: format!("$$${name}"),
+      });
+      return None;
+    }
+    trace::record(|| TraceEvent::Bind {
+      name: format!("$$${name}"),
+      text: text.unwrap_or_default(),
+    });
+  } else {
+    let rest = cand_children.filter(|n| n.is_named()).count();
+    env
+      .to_mut()
+      .push_ellipsis_count(matched.uncaptured_named + rest);
+  }
+  Some(())
+}
+
+/// Whether consecutive named nodes are at most one blank line apart.
+pub(super) fn lines_contiguous<D: Doc>(nodes: &[Node<D>]) -> bool {
+  let mut named = nodes.iter().filter(|n| n.is_named());
+  let Some(mut prev) = named.next() else {
+    return true;
+  };
+  named.all(|node| {
+    let gap = node.start_pos().0.saturating_sub(prev.end_pos().0);
+    prev = node;
+    gap <= 2
+  })
+}
+
+/// The byte range matched by goal at candidate, from the candidate's start to the end computed by
+/// `match_end_non_recursive`. The end excludes trailing candidate children the goal does not cover,
+/// e.g. a comment after `return 123`.
+pub fn match_span_non_recursive<D: Doc>(
+  goal: &Pattern<D::Lang>,
+  candidate: Node<D>,
+  opts: &MatchOptions,
+) -> Option<Range<usize>> {
+  let start = candidate.range().start;
+  let end = match_end_non_recursive(goal, candidate, opts)?;
+  Some(start..end)
+}
+
+pub fn match_end_non_recursive<D: Doc>(
+  goal: &Pattern<D::Lang>,
+  candidate: Node<D>,
+  opts: &MatchOptions,
+) -> Option<usize> {
+  use Pattern as P;
+  let same_kind = match_kind(goal, &candidate, opts)?;
+  if opts.is_skipped_error(&candidate) {
+    return None;
+  }
+  let mut env = Cow::Owned(MetaVarEnv::new());
+  if opts.matches_kinds_specially() {
+    if let Some(matched) = options::match_opaque(goal, &candidate, opts) {
+      return matched.then(|| candidate.range().end);
+    }
+    if let Some(matched) = options::match_cooked(goal, &candidate, opts) {
+      return matched.then(|| candidate.range().end);
+    }
+    if let Some(matched) = set::match_set(goal, &candidate, &mut env, opts, None) {
+      return matched.then(|| candidate.range().end);
+    }
+    if let Some(matched) = set::match_attributes(goal, &candidate, &mut env, opts, None) {
+      return matched.then(|| candidate.range().end);
+    }
+  }
+  match goal {
+    P::MetaVar { .. } | P::Descendant { .. } => Some(candidate.range().end),
+    P::Optional(p) => match_end_non_recursive(p, candidate, opts),
+    P::Internal { children, .. } if same_kind => {
+      let cand_children = opts.aligned_children(&candidate);
+      let empty_end = candidate.range().end;
+      let children = opts.trim_terminator(children);
+      let end = match_multi_nodes_end_non_recursive(children, cand_children, empty_end, opts);
+      match end {
+        Some(end) if opts.trailing_allowed(&candidate) => Some(end),
+        _ => {
+          let flattened = associative::match_flattened(goal, &candidate, &mut env, opts, None);
+          flattened.unwrap_or(false).then(|| candidate.range().end)
+        }
+      }
+    }
+    P::Terminal { text, kind_id, .. } if same_kind => {
+      if opts.leaf_text_matches(text, &candidate.text(), *kind_id) {
+        Some(candidate.range().end)
+      } else {
+        None
+      }
+    }
+    _ => {
+      let child = opts.unwrap_transparent(&candidate, |n| kind_may_match(goal, n, opts))?;
+      match_end_non_recursive(goal, child, opts)?;
+      Some(candidate.range().end)
+    }
+  }
+}
+
+/// `empty_end` is returned if there is no candidate and every goal can match nothing.
+fn match_multi_nodes_end_non_recursive<'c, D: Doc + 'c>(
+  goals: &[Pattern<D::Lang>],
+  candidates: impl Iterator<Item = Node<'c, D>>,
+  empty_end: usize,
+  opts: &MatchOptions,
+) -> Option<usize> {
+  let mut goal_children = goals.iter().peekable();
+  let mut cand_children = candidates.peekable();
+  let Some(first) = cand_children.peek() else {
+    return goals.iter().all(matches_empty).then_some(empty_end);
+  };
+  let mut end = first.range().end;
+  loop {
+    let curr_node = *goal_children.peek().unwrap();
+    if let Pattern::Repeat(group) = curr_node {
+      let rest_goals = &goals[goals.len() - goal_children.len() + 1..];
+      let cands: Vec<_> = cand_children.collect();
+      let consumed = repeat::repeat_len(group, &cands, opts);
+      if let Some(last) = cands[..consumed].iter().rfind(|n| !is_zero_width(n)) {
+        end = last.range().end;
+      }
+      let rest = cands.into_iter().skip(consumed);
+      if rest_goals.is_empty() {
+        return no_extra_children(rest, opts).then_some(end);
+      }
+      return match_multi_nodes_end_non_recursive(rest_goals, rest, end, opts);
+    }
+    if is_optional_single(curr_node) {
+      let cand = cand_children.peek().unwrap().clone();
+      let separators = absent_separators(goal_children.clone(), |next| {
+        match_end_non_recursive(next, cand.clone(), opts).is_some()
+      });
+      match separators {
+        Some(0) => {
+          goal_children.next();
+          continue;
+        }
+        Some(n) => {
+          // `$$A` either takes the candidate or is absent with the separators after it
+          let rest_goals = &goals[goals.len() - goal_children.len() + 1..];
+          let cands: Vec<_> = cand_children.collect();
+          let present = match_end_non_recursive(curr_node, cand.clone(), opts).and_then(|taken| {
+            let taken = if is_zero_width(&cand) { end } else { taken };
+            let rest = cands[1..].iter().cloned();
+            match_multi_nodes_end_non_recursive(rest_goals, rest, taken, opts)
+          });
+          return present.or_else(|| {
+            match_multi_nodes_end_non_recursive(&rest_goals[n..], cands.into_iter(), end, opts)
+          });
+        }
+        None => {}
+      }
+    }
+    if let Pattern::Optional(inner) = curr_node {
+      let cand = cand_children.peek().unwrap().clone();
+      if let Some(matched_end) = match_end_non_recursive(inner, cand.clone(), opts) {
+        if !is_zero_width(&cand) {
+          end = matched_end;
+        }
+        cand_children.next();
+      }
+      goal_children.next();
+      if goal_children.peek().is_none() {
+        return no_extra_children(cand_children, opts).then_some(end);
+      }
+      if cand_children.peek().is_none() {
+        return all_optional(goal_children).then_some(end);
+      }
+      continue;
+    }
+    if try_get_ellipsis_mode(curr_node).is_ok() {
+      goal_children.next();
+      // goal has all matched
+      if goal_children.peek().is_none() {
+        // TODO: handle named and unnamed ellipsis
+        // we need to consume all cand_children to match ellipsis
+        let updated_end = last_end(cand_children).unwrap_or(end);
+        return Some(updated_end);
+      }
+      // skip trivial nodes in goal after ellipsis
+      while goal_children.peek().unwrap().is_trivial() {
+        goal_children.next();
+        if goal_children.peek().is_none() {
+          // TODO: handle named and unnamed ellipsis
+          // we need to consume all cand_children to match ellipsis
+          let updated_end = last_end(cand_children).unwrap_or(end);
+          return Some(updated_end);
+        }
+      }
+      // adjacent ellipses act as one, the last of them consumes the candidates
+      if try_get_ellipsis_mode(goal_children.peek().unwrap()).is_ok() {
+        if opts.adjacent_ellipsis_consumes_one {
+          let consumed = cand_children.next().unwrap();
+          if !is_zero_width(&consumed) {
+            end = consumed.range().end;
+          }
+          cand_children.peek()?;
+        }
+        continue;
+      }
+      // optional goals may be absent, so the ellipsis also stops before the next required goal
+      let required = next_required(goal_children.clone());
+      loop {
+        let cand = cand_children.peek().unwrap();
+        let found = |goal: &Pattern<_>| match_end_non_recursive(goal, cand.clone(), opts).is_some();
+        if found(goal_children.peek().unwrap()) || required.map_or(false, found) {
+          // found match non Ellipsis,
+          break;
+        }
+        cand_children.next();
+        cand_children.peek()?;
+      }
+    }
+    // the ellipsis stopped before an optional goal
+    if matches!(goal_children.peek(), Some(Pattern::Optional(_))) {
+      continue;
+    }
+    // skip if cand children is trivial
+    let mut skipped_trivia = 0;
+    let matched_end = loop {
+      let Some(cand) = cand_children.peek() else {
+        // if cand runs out, remaining goal is not matched
+        return None;
+      };
+      let matched_end = match_end_non_recursive(goal_children.peek().unwrap(), cand.clone(), opts);
+      // try match goal node with candidate node
+      if let Some(matched_end) = matched_end {
+        break Some(if is_zero_width(cand) {
+          end
+        } else {
+          matched_end
+        });
+      } else if separates_ellipsis(goal_children.clone()) {
+        break None;
+      } else if opts.is_skippable(cand) {
+        // skip trivial node
+        // TODO: nade with field should not be skipped
+        skipped_trivia += 1;
+        if !opts.may_skip_trivia(skipped_trivia) {
+          return None;
+        }
+        cand_children.next();
+      } else {
+        // unmatched significant node
+        return None;
+      }
+    };
+    let Some(matched_end) = matched_end else {
+      goal_children.next();
+      continue;
+    };
+    end = matched_end;
+    goal_children.next();
+    if goal_children.peek().is_none() {
+      // all goal found, return
+      cand_children.next();
+      return no_extra_children(cand_children, opts).then_some(end);
+    }
+    cand_children.next();
+    if cand_children.peek().is_none() {
+      return all_optional(goal_children).then_some(end);
+    }
+  }
+}
+
+/// Zero-width candidates, e.g. MISSING nodes inserted by error recovery, do not move the end of a match.
+/// Otherwise a missing `)` placed after a comment would stretch the match over that comment.
+fn is_zero_width<D: Doc>(node: &Node<D>) -> bool {
+  node.range().is_empty()
+}
+
+/// End of the last candidate that is not zero-width.
+fn last_end<'t, D: Doc + 't>(cands: impl Iterator<Item = Node<'t, D>>) -> Option<usize> {
+  cands
+    .filter(|n| !is_zero_width(n))
+    .last()
+    .map(|n| n.range().end)
+}
+
+/// Whether the candidates left after all goals are matched are allowed, see `exact_children`.
+fn no_extra_children<'t, D: Doc + 't>(
+  mut rest: impl Iterator<Item = Node<'t, D>>,
+  opts: &MatchOptions,
+) -> bool {
+  !opts.exact_children || rest.all(|n| opts.is_skippable(&n))
+}
+
+/// The first non-optional goal if the next goal is optional.
+fn next_required<'p, L: Language + 'p>(
+  goals: impl Iterator<Item = &'p Pattern<L>>,
+) -> Option<&'p Pattern<L>> {
+  let mut goals = goals.peekable();
+  if !matches!(goals.peek(), Some(Pattern::Optional(_))) {
+    return None;
+  }
+  goals.find(|g| !matches!(g, Pattern::Optional(_)))
+}
+
+/// Whether the next goal is a trivial goal followed by an ellipsis or `$$A`, possibly after other trivial goals.
+/// Such a separator may be absent since the ellipsis or `$$A` can match nothing,
+/// e.g. the comma in `foo($$$, b, $$$)` against `foo(b)` or in `foo(a, $$A)` against `foo(a)`.
+fn separates_ellipsis<'p, L: Language + 'p>(
+  mut goals: impl Iterator<Item = &'p Pattern<L>>,
+) -> bool {
+  if !matches!(goals.next(), Some(g) if g.is_trivial()) {
+    return false;
+  }
+  let mut rest = goals.skip_while(|g| g.is_trivial());
+  matches!(rest.next(), Some(g) if try_get_ellipsis_mode(g).is_ok() || is_optional_single(g))
+}
+
+/// Whether the goal is `$$A` or `$$_`, which matches one node of any kind or nothing, at any position.
+/// It matches nothing if no candidate is left or the next goal matches the current candidate,
+/// e.g. `foo($$A)` against `foo()`. Otherwise it takes the candidate, even an unnamed one,
+/// so `return $$A` binds the semicolon in `return;`. If only the next significant goal matches the candidate,
+/// taking it is tried first and then `$$A` is absent together with the separators after it,
+/// e.g. `foo($$A, b)` against `foo(b)`. Separators before a trailing `$$A` may be absent too,
+/// e.g. `foo(a, $$A)` against `foo(a)` or `let $A = $$B` against `let a`.
+fn is_optional_single<L: Language>(goal: &Pattern<L>) -> bool {
+  matches!(
+    goal,
+    Pattern::MetaVar {
+      meta_var: MetaVariable::Capture(_, false) | MetaVariable::Dropped(false),
+      ..
+    }
+  )
+}
+
+/// How many separators an absent `$$A` skips along with itself, None if it takes the candidate.
+/// Zero if the goal after it satisfies `takes`, otherwise the number of trivial goals before
+/// the next significant goal if that one does. Ellipses never count as taking the candidate.
+fn absent_separators<'p, L: Language + 'p>(
+  mut goals: impl Iterator<Item = &'p Pattern<L>>,
+  mut takes: impl FnMut(&'p Pattern<L>) -> bool,
+) -> Option<usize> {
+  goals.next();
+  for (separators, next) in goals.enumerate() {
+    if try_get_ellipsis_mode(next).is_err() && takes(next) {
+      return Some(separators);
+    }
+    if !next.is_trivial() {
+      return None;
+    }
+  }
+  None
+}
+
+/// Whether the goal can match no candidate, like an ellipsis or an optional goal.
+fn matches_empty<L: Language>(goal: &Pattern<L>) -> bool {
+  matches!(goal, Pattern::Optional(_) | Pattern::Repeat(_))
+    || is_optional_single(goal)
+    || try_get_ellipsis_mode(goal).is_ok()
+}
+
+/// Match goals against no candidate, binding named ellipses to empty lists.
+fn match_empty<'p, 't, D: Doc + 't>(
+  goals: impl Iterator<Item = &'p Pattern<D::Lang>>,
+  env: &mut Cow<MetaVarEnv<'t, D>>,
+  opts: &MatchOptions,
+) -> Option<()>
+where
+  D::Lang: 'p,
+{
+  for goal in goals {
+    if let Ok(name) = try_get_ellipsis_mode(goal) {
+      update_ellipsis_env(
+        &name,
+        EllipsisMatched::new(goal),
+        env,
+        std::iter::empty(),
+        &[],
+        opts,
+      )?;
+    } else if let Pattern::Repeat(group) = goal {
+      repeat::match_repeat(group, &[], env, opts, None)?;
+    } else if !matches!(goal, Pattern::Optional(_)) && !is_optional_single(goal) {
+      return None;
+    }
+  }
+  Some(())
+}
+
+/// Whether the remaining goals can match no candidate.
+/// Separators go with an absent `$$A` after them, e.g. the `=` in `let $A = $$B` against `let a`.
+fn all_optional<'p, L: Language + 'p>(goals: impl Iterator<Item = &'p Pattern<L>>) -> bool {
+  let mut separated = false;
+  for g in goals {
+    if g.is_trivial() {
+      separated = true;
+    } else if is_optional_single(g) {
+      separated = false;
+    } else if separated || !matches!(g, Pattern::Optional(_) | Pattern::Repeat(_)) {
+      return false;
+    }
+  }
+  !separated
+}
+
+/// Matches a list of goal children against a list of sibling candidates without a parent node,
+/// e.g. the children of an argument list pattern against nodes found by another tool.
+/// The lists are aligned like the children of a matching `Internal` goal: trivial candidates are skipped
+/// and ellipses consume runs of candidates. The candidates should be siblings from one tree.
+/// Returns the bindings on success.
+pub fn match_node_list<'tree, D: Doc>(
+  goals: &[Pattern<D::Lang>],
+  candidates: impl Iterator<Item = Node<'tree, D>>,
+  opts: &MatchOptions,
+) -> Option<MetaVarEnv<'tree, D>> {
+  if goals.is_empty() {
+    return no_extra_children(candidates, opts).then(MetaVarEnv::new);
+  }
+  let mut env = Cow::Owned(MetaVarEnv::new());
+  match_nodes_non_recursive(goals, candidates, &mut env, opts, None)?;
+  Some(env.into_owned())
+}
+
+pub fn match_node_non_recursive<'tree, D: Doc>(
+  goal: &Pattern<D::Lang>,
+  candidate: Node<'tree, D>,
+  env: &mut Cow<MetaVarEnv<'tree, D>>,
+  opts: &MatchOptions,
+  validator: Validator<'_, 'tree, D>,
+) -> Option<Node<'tree, D>> {
+  #[cfg(feature = "trace")]
+  if trace::is_enabled() {
+    trace::enter(goal, &candidate);
+    let ret = match_node_impl(goal, candidate, env, opts, validator);
+    trace::exit(ret.is_some());
+    return ret;
+  }
+  match_node_impl(goal, candidate, env, opts, validator)
+}
+
+fn match_node_impl<'tree, D: Doc>(
+  goal: &Pattern<D::Lang>,
+  candidate: Node<'tree, D>,
+  env: &mut Cow<MetaVarEnv<'tree, D>>,
+  opts: &MatchOptions,
+  validator: Validator<'_, 'tree, D>,
+) -> Option<Node<'tree, D>> {
+  use Pattern as P;
+  let Some(same_kind) = match_kind(goal, &candidate, opts) else {
+    trace::record(|| TraceEvent::KindMismatch);
+    return None;
+  };
+  if opts.is_skipped_error(&candidate) {
+    trace::record(|| TraceEvent::Option {
+      name: "skip_error_nodes",
+    });
+    return None;
+  }
+  if opts.matches_kinds_specially() {
+    if let Some(matched) = options::match_opaque(goal, &candidate, opts) {
+      trace::record(|| TraceEvent::Option { name: "opaque" });
+      return matched.then_some(candidate);
+    }
+    if let Some(matched) = options::match_cooked(goal, &candidate, opts) {
+      trace::record(|| TraceEvent::Option { name: "cooked" });
+      return matched.then_some(candidate);
+    }
+    if let Some(matched) = set::match_set(goal, &candidate, env, opts, validator) {
+      trace::record(|| TraceEvent::Option {
+        name: "exhaustive set",
+      });
+      return matched.then_some(candidate);
+    }
+    if let Some(matched) = set::match_attributes(goal, &candidate, env, opts, validator) {
+      trace::record(|| TraceEvent::Option { name: "attributes" });
+      return matched.then_some(candidate);
+    }
+  }
+  match goal {
+    // leaf = without named children
+    P::Terminal { text, kind_id, .. } if same_kind => {
+      let equal = opts.leaf_text_matches(text, &candidate.text(), *kind_id);
+      trace::record(|| TraceEvent::TextCompared { equal });
+      equal.then_some(candidate)
+    }
+    P::MetaVar { meta_var, .. } => match try_get_ellipsis_mode(goal) {
+      Ok(name) => match_root_ellipsis(goal, &name, candidate, env, opts),
+      Err(_) => match_leaf_meta_var(meta_var, candidate, env, opts, validator),
+    },
+    P::Optional(p) => match_node_non_recursive(p, candidate, env, opts, validator),
+    P::Descendant {
+      meta_var,
+      inner,
+      max_depth,
+    } => descendant::match_descendant(meta_var, inner, *max_depth, candidate, env, opts, validator),
+    P::Internal {
+      children,
+      first_kind,
+      ..
+    } if same_kind => {
+      let matched = match_internal(
+        children,
+        *first_kind,
+        candidate.clone(),
+        env,
+        opts,
+        validator,
+      );
+      if matched.is_some() {
+        return matched;
+      }
+      let flattened = associative::match_flattened(goal, &candidate, env, opts, validator)?;
+      trace::record(|| TraceEvent::Option {
+        name: "associative",
+      });
+      flattened.then_some(candidate)
+    }
+    _ => {
+      if let Some(child) = opts.unwrap_transparent(&candidate, |n| kind_may_match(goal, n, opts)) {
+        trace::record(|| TraceEvent::Option {
+          name: "transparent",
+        });
+        return match_node_non_recursive(goal, child, env, opts, validator).map(|_| candidate);
+      }
+      trace::record(|| TraceEvent::KindMismatch);
+      None
+    }
+  }
+}
+
+/// Whether the kinds of goal and candidate match, a goal without kind like a meta variable matches any.
+/// None if the candidate is rejected by kind, which is checked first since most candidates differ in kind.
+/// No option matches across kinds, only transparent wrappers are seen through, see `transparent_kinds`.
+fn match_kind<D: Doc>(
+  goal: &Pattern<D::Lang>,
+  candidate: &Node<D>,
+  opts: &MatchOptions,
+) -> Option<bool> {
+  let (Pattern::Terminal { kind_id, .. } | Pattern::Internal { kind_id, .. }) = goal else {
+    return Some(true);
+  };
+  let cand_kind = candidate.kind_id();
+  let same_kind = opts.kind_matches(*kind_id, cand_kind);
+  (same_kind || opts.is_transparent(cand_kind)).then_some(same_kind)
+}
+
+/// Matches the children of an internal goal whose kind matches the candidate's.
+fn match_internal<'tree, D: Doc>(
+  children: &[Pattern<D::Lang>],
+  first_kind: Option<u16>,
+  candidate: Node<'tree, D>,
+  env: &mut Cow<MetaVarEnv<'tree, D>>,
+  opts: &MatchOptions,
+  validator: Validator<'_, 'tree, D>,
+) -> Option<Node<'tree, D>> {
+  let children = opts.trim_terminator(children);
+  // every non-ellipsis goal consumes one candidate, bail out early if candidate has too few
+  let may_skip = children.iter().any(matches_empty);
+  if !may_skip && (candidate.inner.child_count() as usize) < children.len() {
+    trace::record(|| TraceEvent::TooFewChildren);
+    return None;
+  }
+  let mut rest = opts.aligned_children(&candidate);
+  let mut leading = SmallVec::new();
+  if let Some(kind) = first_kind {
+    if !first_child_may_match(kind, &mut rest, &mut leading, opts) {
+      trace::record(|| TraceEvent::FirstKindMismatch);
+      return None;
+    }
+  }
+  // undo bindings of partially matched children so a failed attempt leaves env untouched
+  let checkpoint = env.checkpoint();
+  let cand_children = leading.into_iter().chain(rest);
+  let matched = match_nodes_non_recursive(children, cand_children, env, opts, validator);
+  if matched.is_some() && opts.trailing_allowed(&candidate) {
+    Some(candidate)
+  } else {
+    if env.checkpoint() != checkpoint {
+      trace::record(|| TraceEvent::Rollback);
+    }
+    rollback_env(env, checkpoint);
+    None
+  }
+}
+
+/// Whether goal may match candidate without seeing through it, i.e. they have matching kinds.
+fn kind_may_match<D: Doc>(
+  goal: &Pattern<D::Lang>,
+  candidate: &Node<D>,
+  opts: &MatchOptions,
+) -> bool {
+  match goal {
+    Pattern::Terminal { kind_id, .. } | Pattern::Internal { kind_id, .. } => {
+      opts.kind_matches(*kind_id, candidate.kind_id())
+    }
+    _ => true,
+  }
+}
+
+/// Fast reject if the first goal child cannot align with any leading candidate child.
+/// Leading unnamed candidates and comments can be skipped so we look up to the first significant child.
+/// The children looked at are kept in `leading` to be matched after, so no second cursor is walked.
+fn first_child_may_match<'t, D: Doc + 't>(
+  first_kind: u16,
+  children: &mut impl Iterator<Item = Node<'t, D>>,
+  leading: &mut SmallVec<[Node<'t, D>; 2]>,
+  opts: &MatchOptions,
+) -> bool {
+  for child in children {
+    let kind = child.kind_id();
+    let may_match = opts.kind_matches(first_kind, kind) || opts.is_transparent(kind);
+    let skippable = opts.is_skippable(&child);
+    leading.push(child);
+    if may_match {
+      return true;
+    }
+    if !skippable {
+      return false;
+    }
+  }
+  false
+}
+
+/// Field of the first named node an ellipsis consumes. See `MatchOptions::ellipsis_respects_fields`.
+struct EllipsisField {
+  enabled: bool,
+  first: Option<Option<u16>>,
+}
+
+impl EllipsisField {
+  fn new(opts: &MatchOptions) -> Self {
+    Self {
+      enabled: opts.ellipsis_respects_fields,
+      first: None,
+    }
+  }
+
+  /// Whether the ellipsis can consume the node. Unnamed nodes are always accepted.
+  fn accepts<D: Doc>(&mut self, node: &Node<D>) -> bool {
+    if !self.enabled || !node.is_named() {
+      return true;
+    }
+    let field = node.field_id_in_parent();
+    *self.first.get_or_insert(field) == field
+  }
+}
+
+fn match_nodes_non_recursive<'p, 'tree, D: Doc + 'tree, I>(
+  goals: I,
+  candidates: impl Iterator<Item = Node<'tree, D>>,
+  env: &mut Cow<MetaVarEnv<'tree, D>>,
+  opts: &MatchOptions,
+  validator: Validator<'_, 'tree, D>,
+) -> Option<()>
+where
+  D::Lang: 'p,
+  I: IntoIterator<Item = &'p Pattern<D::Lang>>,
+  I::IntoIter: Clone,
+{
+  let mut goal_children = goals.into_iter().peekable();
+  let mut cand_children = candidates.peekable();
+  if cand_children.peek().is_none() {
+    return match_empty(goal_children, env, opts);
+  }
+  loop {
+    let curr_node = *goal_children.peek().unwrap();
+    if let Pattern::Repeat(group) = curr_node {
+      // repetitions are greedy, the remaining goals match the remaining candidates
+      goal_children.next();
+      let cands: Vec<_> = cand_children.collect();
+      let consumed = repeat::match_repeat(group, &cands, env, opts, validator)?;
+      let rest = cands.into_iter().skip(consumed);
+      let rest_goals: Vec<_> = goal_children.collect();
+      if rest_goals.is_empty() {
+        return no_extra_children(rest, opts).then_some(());
+      }
+      return match_nodes_non_recursive(rest_goals, rest, env, opts, validator);
+    }
+    if is_optional_single(curr_node) {
+      let cand = cand_children.peek().unwrap().clone();
+      // only probe the next goals, the bindings are undone
+      let separators = absent_separators(goal_children.clone(), |next| {
+        let checkpoint = env.checkpoint();
+        let found = match_node_non_recursive(next, cand.clone(), env, opts, validator).is_some();
+        rollback_env(env, checkpoint);
+        found
+      });
+      match separators {
+        Some(0) => {
+          trace::record(|| TraceEvent::OptionalAbsent);
+          goal_children.next();
+          continue;
+        }
+        Some(n) => {
+          // `$$A` either takes the candidate or is absent with the separators after it
+          goal_children.next();
+          let rest_goals: Vec<_> = goal_children.collect();
+          let cands: Vec<_> = cand_children.collect();
+          let checkpoint = env.checkpoint();
+          let rest = cands[1..].iter().cloned();
+          if match_node_non_recursive(curr_node, cand, env, opts, validator).is_some()
+            && match_nodes_non_recursive(rest_goals.clone(), rest, env, opts, validator).is_some()
+          {
+            return Some(());
+          }
+          rollback_env(env, checkpoint);
+          trace::record(|| TraceEvent::OptionalAbsent);
+          let rest_goals = rest_goals[n..].to_vec();
+          return match_nodes_non_recursive(rest_goals, cands.into_iter(), env, opts, validator);
+        }
+        None => {}
+      }
+    }
+    if let Pattern::Optional(inner) = curr_node {
+      // an optional goal only consumes the candidate if it matches
+      let cand = cand_children.peek().unwrap().clone();
+      if match_node_non_recursive(inner, cand, env, opts, validator).is_some() {
+        cand_children.next();
+      } else {
+        trace::record(|| TraceEvent::OptionalAbsent);
+      }
+      goal_children.next();
+      if goal_children.peek().is_none() {
+        return no_extra_children(cand_children, opts).then_some(());
+      }
+      if cand_children.peek().is_none() {
+        return all_optional(goal_children).then_some(());
+      }
+      continue;
+    }
+    if let Ok(optional_name) = try_get_ellipsis_mode(curr_node) {
+      let mut matched = EllipsisMatched::new(curr_node);
+      let mut field = EllipsisField::new(opts);
+      goal_children.next();
+      // goal has all matched
+      if goal_children.peek().is_none() {
+        trace::record(|| TraceEvent::EllipsisRest);
+        let rest = cand_children.take_while(|n| field.accepts(n));
+        update_ellipsis_env(&optional_name, matched, env, rest, &[], opts)?;
+        return Some(());
+      }
+      // skip trivial nodes in goal after ellipsis
+      let mut skipped = SkippedTrivia::new();
+      while goal_children.peek().unwrap().is_trivial() {
+        skipped.extend(goal_children.next());
+        if goal_children.peek().is_none() {
+          trace::record(|| TraceEvent::EllipsisRest);
+          let rest = cand_children.take_while(|n| field.accepts(n));
+          update_ellipsis_env(&optional_name, matched, env, rest, &skipped, opts)?;
+          return Some(());
+        }
+      }
+      // adjacent ellipses act as one, the last of them consumes the candidates
+      // and the earlier ones match nothing, e.g. `$$$A, $$$B` binds every argument to B
+      if try_get_ellipsis_mode(goal_children.peek().unwrap()).is_ok() {
+        if opts.adjacent_ellipsis_consumes_one {
+          trace::record(|| TraceEvent::Option {
+            name: "adjacent_ellipsis_consumes_one",
+          });
+          let consumed = cand_children.next().unwrap();
+          matched.consume(consumed, optional_name.is_some());
+        }
+        update_ellipsis_env(&optional_name, matched, env, std::iter::empty(), &[], opts)?;
+        if opts.adjacent_ellipsis_consumes_one {
+          cand_children.peek()?;
+        }
+        continue;
+      }
+      // optional goals may be absent, so the ellipsis also stops before the next required goal
+      let required = next_required(goal_children.clone());
+      loop {
+        // the candidate is only cloned to be matched, consumed ones are moved into the capture
+        let cand = cand_children.peek().unwrap();
+        let goal = goal_children.peek().unwrap();
+        // only probe the goal, it is matched again after the ellipsis is recorded
+        // so bindings and ellipsis counts follow the pattern order
+        let checkpoint = env.checkpoint();
+        let found = match_node_non_recursive(goal, cand.clone(), env, opts, validator).is_some()
+          || required.map_or(false, |goal| {
+            match_node_non_recursive(goal, cand.clone(), env, opts, validator).is_some()
+          });
+        rollback_env(env, checkpoint);
+        if found {
+          // found match non Ellipsis,
+          update_ellipsis_env(
+            &optional_name,
+            matched,
+            env,
+            std::iter::empty(),
+            &skipped,
+            opts,
+          )?;
+          break;
+        }
+        let consumed = cand_children.next().unwrap();
+        if !field.accepts(&consumed) {
+          return None;
+        }
+        trace::record(|| TraceEvent::EllipsisConsume {
+          candidate: trace::describe_node(&consumed),
+        });
+        matched.consume(consumed, optional_name.is_some());
+        cand_children.peek()?;
+      }
+    }
+    // the ellipsis stopped before an optional goal
+    if matches!(goal_children.peek(), Some(Pattern::Optional(_))) {
+      continue;
+    }
+    // skip if cand children is trivial
+    let mut skipped_trivia = 0;
+    let goal_absent = loop {
+      let Some(cand) = cand_children.peek() else {
+        // if cand runs out, remaining goal is not matched
+        return None;
+      };
+      let goal = goal_children.peek().unwrap();
+      let matched = match_node_non_recursive(goal, cand.clone(), env, opts, validator).is_some();
+      // try match goal node with candidate node
+      if matched {
+        break false;
+      } else if separates_ellipsis(goal_children.clone()) {
+        trace::record(|| TraceEvent::OptionalAbsent);
+        break true;
+      } else if opts.is_skippable(cand) {
+        // skip trivial node
+        // TODO: nade with field should not be skipped
+        skipped_trivia += 1;
+        if !opts.may_skip_trivia(skipped_trivia) {
+          return None;
+        }
+        trace::record(|| TraceEvent::SkipTrivial {
+          candidate: trace::describe_node(cand),
+        });
+        cand_children.next();
+      } else {
+        // unmatched significant node, a trivial goal never skips it to stay aligned
+        return None;
+      }
+    };
+    if goal_absent {
+      goal_children.next();
+      continue;
+    }
+    goal_children.next();
+    if goal_children.peek().is_none() {
+      // all goal found, return
+      cand_children.next();
+      return no_extra_children(cand_children, opts).then_some(());
+    }
+    cand_children.next();
+    if cand_children.peek().is_none() {
+      return all_optional(goal_children).then_some(());
+    }
+  }
+}
+
+/// Whether candidate is the same code as goal, used to check repeated meta variables like `$A == $A`.
+/// A named leaf is compared by text with the other node even if that node is not a leaf (gh#1087),
+/// so the parameter `x` in `(x) => x` equals the identifier `x`. Use it for pattern matching,
+/// where a meta variable may bind the same name in different syntactic roles.
+pub fn does_node_match_exactly<D: Doc>(goal: &Node<D>, candidate: &Node<D>) -> bool {
+  nodes_equal(goal, candidate, true)
+}
+
+/// Like [`does_node_match_exactly`] but without the leaf text fallback: nodes must have the same kind
+/// and recursively equal children, and leaves the same text. Use it for exact AST comparison, e.g. dedup,
+/// where a parameter and an identifier of the same text are different nodes.
+pub fn does_node_match_strict<D: Doc>(goal: &Node<D>, candidate: &Node<D>) -> bool {
+  nodes_equal(goal, candidate, false)
+}
+
+/// Options to compare nodes with [`nodes_equal_with_options`].
+#[derive(Clone, Debug)]
+pub struct EqualityOptions {
+  /// whether a named leaf equals any node of the same text, see gh#1087
+  leaf_text: bool,
+}
+
+impl Default for EqualityOptions {
+  fn default() -> Self {
+    Self { leaf_text: true }
+  }
+}
+
+impl EqualityOptions {
+  /// Compare a named leaf with the other node by text, even if the other node is not a leaf.
+  /// For example, the parameter `x` in `(x) => x` is a `required_parameter` wrapping an identifier,
+  /// but it equals the identifier `x`. This is the default, as in pattern matching (gh#1087).
+  /// Turn it off to require both nodes to have the same kind and shape.
+  pub fn leaf_text(mut self, leaf_text: bool) -> Self {
+    self.leaf_text = leaf_text;
+    self
+  }
+}
+
+/// Whether two nodes are the same code, the same check used for a meta variable bound twice.
+/// Whitespace is ignored since it is not part of the tree. Comments are nodes, so they must be equal too.
+/// Internal nodes are equal if they have the same kind and equal children,
+/// unnamed children like punctuation included. Named leaves are compared by text,
+/// and a named leaf also equals an internal node of the same text, e.g. a parameter wrapping an identifier.
+/// Use [`nodes_equal_with_options`] to disable the latter.
+pub fn nodes_structurally_equal<D: Doc>(a: &Node<D>, b: &Node<D>) -> bool {
+  does_node_match_exactly(a, b)
+}
+
+/// Like [`nodes_structurally_equal`], customized with `options`.
+pub fn nodes_equal_with_options<D: Doc>(
+  a: &Node<D>,
+  b: &Node<D>,
+  options: &EqualityOptions,
+) -> bool {
+  if options.leaf_text {
+    does_node_match_exactly(a, b)
+  } else {
+    does_node_match_strict(a, b)
+  }
+}
+
+fn nodes_equal<D: Doc>(goal: &Node<D>, candidate: &Node<D>, leaf_text: bool) -> bool {
+  first_mismatch(goal, candidate, leaf_text).is_none()
+}
+
+/// Where [`does_node_match_exactly`] fails. `path` is the child indices from the compared nodes
+/// down to the diverging pair, unnamed children included, and the ranges are of that pair.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ExactMismatch {
+  /// The nodes have different kinds, or a named leaf is compared with a leaf of an incompatible kind.
+  Kind {
+    path: Vec<usize>,
+    goal: Range<usize>,
+    candidate: Range<usize>,
+  },
+  /// The nodes have the same kind but a different number of children.
+  ChildCount {
+    path: Vec<usize>,
+    goal: Range<usize>,
+    candidate: Range<usize>,
+  },
+  /// The leaves, or a named leaf and the node compared with it, have different text.
+  LeafText {
+    path: Vec<usize>,
+    goal: Range<usize>,
+    candidate: Range<usize>,
+  },
+}
+
+impl ExactMismatch {
+  fn new<D: Doc>(reason: MismatchReason, goal: &Node<D>, candidate: &Node<D>) -> Self {
+    let (path, goal, candidate) = (vec![], goal.range(), candidate.range());
+    match reason {
+      MismatchReason::Kind => Self::Kind {
+        path,
+        goal,
+        candidate,
+      },
+      MismatchReason::ChildCount => Self::ChildCount {
+        path,
+        goal,
+        candidate,
+      },
+      MismatchReason::LeafText => Self::LeafText {
+        path,
+        goal,
+        candidate,
+      },
+    }
+  }
+
+  pub fn path(&self) -> &[usize] {
+    match self {
+      Self::Kind { path, .. } | Self::ChildCount { path, .. } | Self::LeafText { path, .. } => path,
+    }
+  }
+
+  /// The ranges of the diverging goal and candidate nodes.
+  pub fn ranges(&self) -> (Range<usize>, Range<usize>) {
+    match self {
+      Self::Kind {
+        goal, candidate, ..
+      }
+      | Self::ChildCount {
+        goal, candidate, ..
+      }
+      | Self::LeafText {
+        goal, candidate, ..
+      } => (goal.clone(), candidate.clone()),
+    }
+  }
+
+  fn path_mut(&mut self) -> &mut Vec<usize> {
+    match self {
+      Self::Kind { path, .. } | Self::ChildCount { path, .. } | Self::LeafText { path, .. } => path,
+    }
+  }
+}
+
+enum MismatchReason {
+  Kind,
+  ChildCount,
+  LeafText,
+}
+
+/// Like [`does_node_match_exactly`] but reports the first divergence instead of a bool,
+/// e.g. to debug why two snippets expected to be identical differ. Returns None if they match.
+pub fn does_node_match_exactly_explain<D: Doc>(
+  goal: &Node<D>,
+  candidate: &Node<D>,
+) -> Option<ExactMismatch> {
+  let mut mismatch = first_mismatch(goal, candidate, true)?;
+  // the path is collected from the innermost pair outwards
+  mismatch.path_mut().reverse();
+  Some(mismatch)
+}
+
+fn first_mismatch<D: Doc>(
+  goal: &Node<D>,
+  candidate: &Node<D>,
+  leaf_text: bool,
+) -> Option<ExactMismatch> {
+  use MismatchReason as R;
+  let mismatch = |reason| Some(ExactMismatch::new(reason, goal, candidate));
+  // return true if goal and candidate are the same node
+  if goal.node_id() == candidate.node_id() {
+    return None;
+  }
+  // gh issue #1087, we make pattern matching a little bit more permissive
+  // compare node text if at least one node is leaf
+  if leaf_text && (goal.is_named_leaf() || candidate.is_named_leaf()) {
+    if !leaf_kinds_compatible(goal, candidate) {
+      return mismatch(R::Kind);
+    }
+    if goal.text() != candidate.text() {
+      return mismatch(R::LeafText);
+    }
+    return None;
+  }
+  if goal.kind_id() != candidate.kind_id() {
+    return mismatch(R::Kind);
+  }
+  // anonymous leaves like operators are compared by text too, a kind can cover several tokens
+  if goal.is_leaf() {
+    if goal.text() != candidate.text() {
+      return mismatch(R::LeafText);
+    }
+    return None;
+  }
+  let goal_children = goal.children();
+  let cand_children = candidate.children();
+  if goal_children.len() != cand_children.len() {
+    return mismatch(R::ChildCount);
+  }
+  goal_children
+    .zip(cand_children)
+    .enumerate()
+    .find_map(|(i, (g, c))| {
+      let mut mismatch = first_mismatch(&g, &c, leaf_text)?;
+      mismatch.path_mut().push(i);
+      Some(mismatch)
+    })
+}
+
+/// Two named leaves of different kinds can share text, e.g. string_fragment `x` and identifier `x`.
+/// The language decides if they are compatible, see `Language::leaf_kinds_compatible`.
+/// A leaf is compatible with any internal node, e.g. identifier and required_parameter.
+fn leaf_kinds_compatible<D: Doc>(goal: &Node<D>, candidate: &Node<D>) -> bool {
+  if !goal.is_named_leaf() || !candidate.is_named_leaf() {
+    return true;
+  }
+  let lang = goal.lang();
+  lang.leaf_kinds_compatible(goal.kind_id(), candidate.kind_id())
+}
+
+pub fn extract_var_from_node<D: Doc>(goal: &Node<D>) -> Option<MetaVariable> {
+  let key = goal.text();
+  goal.lang().extract_meta_var(&key)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::language::Tsx;
+  use crate::{Root, StrDoc};
+  use std::collections::HashMap;
+
+  fn find_node_recursive<'tree>(
+    goal: &Pattern<Tsx>,
+    node: Node<'tree, StrDoc<Tsx>>,
+    env: &mut Cow<MetaVarEnv<'tree, StrDoc<Tsx>>>,
+  ) -> Option<Node<'tree, StrDoc<Tsx>>> {
+    // walk with a cursor so only matched nodes are kept
+    node
+      .dfs()
+      .find_map(|sub| match_node_non_recursive(goal, sub, env, &MatchOptions::default(), None))
+  }
+
+  fn match_ranges_recursive(
+    goal: &Pattern<Tsx>,
+    node: Node<StrDoc<Tsx>>,
+    ranges: &mut Vec<std::ops::Range<usize>>,
+  ) {
+    let mut env = Cow::Owned(MetaVarEnv::new());
+    let opts = MatchOptions::default();
+    if let Some(matched) = match_node_non_recursive(goal, node.clone(), &mut env, &opts, None) {
+      ranges.push(matched.range());
+    }
+    for child in node.children() {
+      match_ranges_recursive(goal, child, ranges);
+    }
+  }
+
+  #[test]
+  #[cfg(debug_assertions)]
+  #[should_panic(expected = "same language")]
+  fn test_language_mismatch() {
+    use crate::language::TSLanguage;
+    let tsx: TSLanguage = tree_sitter_typescript::language_tsx().into();
+    let ts: TSLanguage = tree_sitter_typescript::language_typescript().into();
+    let goal = Pattern::new("foo($A)", tsx);
+    let cand = Root::<StrDoc<TSLanguage>>::new("foo(1)", ts);
+    let _ = cand.root().find(&goal);
+  }
+
+  #[test]
+  fn test_find_all_same_as_recursive() {
+    let src = r"
+      function a() { foo(1); if (x) { foo(foo(2)) } }
+      class B { m() { return [1, foo(3), 4] } }
+      let c = { d: foo(), e: bar(5) };
+    ";
+    let root = Root::new(src, Tsx);
+    for pattern in [
+      "foo($A)",
+      "foo($$$)",
+      "$F($$$A)",
+      "[$$$, $B]",
+      "$A",
+      "{ $$$ }",
+    ] {
+      let goal = Pattern::new(pattern, Tsx);
+      let mut expected = vec![];
+      match_ranges_recursive(&goal, root.root(), &mut expected);
+      let found: Vec<_> = root.root().find_all(&goal).map(|m| m.range()).collect();
+      assert_eq!(found, expected, "pattern: {pattern}");
+    }
+  }
+
+  fn test_match(s1: &str, s2: &str) -> HashMap<String, String> {
+    let goal = Pattern::new(s1, Tsx);
+    let cand = Root::new(s2, Tsx);
+    let cand = cand.root();
+    let mut env = Cow::Owned(MetaVarEnv::new());
+    let ret = find_node_recursive(&goal, cand.clone(), &mut env);
+    assert!(
+      ret.is_some(),
+      "goal: {goal:?}, candidate: {}",
+      cand.to_sexp(),
+    );
+    HashMap::from(env.into_owned())
+  }
+
+  fn test_non_match(s1: &str, s2: &str) {
+    let goal = Pattern::new(s1, Tsx);
+    let cand = Root::new(s2, Tsx);
+    let cand = cand.root();
+    let mut env = Cow::Owned(MetaVarEnv::new());
+    let ret = find_node_recursive(&goal, cand, &mut env);
+    assert!(ret.is_none());
+  }
+
+  #[test]
+  fn test_simple_match() {
+    test_match("const a = 123", "const a=123");
+    test_non_match("const a = 123", "var a = 123");
+  }
+
+  #[test]
+  fn test_nested_match() {
+    test_match("const a = 123", "function() {const a= 123;}");
+    test_match("const a = 123", "class A { constructor() {const a= 123;}}");
+    test_match(
+      "const a = 123",
+      "for (let a of []) while (true) { const a = 123;}",
+    );
+  }
+
+  #[test]
+  fn test_should_exactly_match() {
+    test_match(
+      "function foo() { let a = 123; }",
+      "function foo() { let a = 123; }",
+    );
+    test_non_match(
+      "function foo() { let a = 123; }",
+      "function bar() { let a = 123; }",
+    );
+  }
+
+  #[test]
+  fn test_match_inner() {
+    test_match(
+      "function bar() { let a = 123; }",
+      "function foo() { function bar() {let a = 123; }}",
+    );
+    test_non_match(
+      "function foo() { let a = 123; }",
+      "function foo() { function bar() {let a = 123; }}",
+    );
+  }
+
+  #[test]
+  fn test_single_ellipsis() {
+    test_match("foo($$$)", "foo(a, b, c)");
+    test_match("foo($$$)", "foo()");
+  }
+  #[test]
+  fn test_named_ellipsis() {
+    test_match("foo($$$A, c)", "foo(a, b, c)");
+    test_match("foo($$$A, b, c)", "foo(a, b, c)");
+    test_match("foo($$$A, a, b, c)", "foo(a, b, c)");
+    test_non_match("foo($$$A, a, b, c)", "foo(b, c)");
+  }
+
+  #[test]
+  fn test_ellipsis_capture_sizes() {
+    // captures below and above the inline capacity of the accumulator
+    let cases = [
+      ("foo()", "[]"),
+      ("foo(a)", "[a]"),
+      ("foo(a, b)", "[a, ,, b]"),
+      ("foo(a, b, c, d)", "[a, ,, b, ,, c, ,, d]"),
+    ];
+    for (src, expected) in cases {
+      let env = test_match("foo($$$A)", src);
+      assert_eq!(env.get("A").map_or("[]", |s| s.as_str()), expected);
+    }
+    // trailing trivia in goal is truncated from the capture
+    let env = test_match("foo($$$A, d)", "foo(a, b, c, d)");
+    assert_eq!(env["A"], "[a, ,, b, ,, c]");
+    let env = test_match("foo(a, $$$A, e)", "foo(a, b, c, d, e)");
+    assert_eq!(env["A"], "[b, ,, c, ,, d]");
+  }
+
+  #[test]
+  fn test_ellipsis_before_trivial_goals() {
+    // `,` and `)` are skipped after $$$A, only candidates matching them are dropped
+    let cases = [
+      ("foo(a)", "[a]"),
+      ("foo(a, b)", "[a, ,, b]"),
+      ("foo(a, b,)", "[a, ,, b]"),
+    ];
+    for (src, expected) in cases {
+      let env = test_match("foo($$$A,)", src);
+      assert_eq!(env["A"], expected, "{src}");
+    }
+  }
+
+  #[test]
+  fn test_ellipsis_before_two_trivial_goals() {
+    // each trailing comma of the goal drops at most one candidate comma, in reverse order
+    let cases = [
+      ("x = [a, b]", "[a, ,, b]"),
+      ("x = [a, b,]", "[a, ,, b]"),
+      ("x = [a, b,,]", "[a, ,, b]"),
+      ("x = [a, b,,,]", "[a, ,, b, ,]"),
+    ];
+    for (src, expected) in cases {
+      let env = test_match("[$$$A,,]", src);
+      assert_eq!(env["A"], expected, "{src}");
+    }
+    let env = test_match("[$$$A,]", "x = [a, b,,]");
+    assert_eq!(env["A"], "[a, ,, b, ,]");
+    let env = test_match("foo($$$A,,)", "foo(a, b,,)");
+    assert_eq!(env["A"], "[a, ,, b]");
+  }
+
+  #[test]
+  fn test_root_ellipsis() {
+    let goal = Pattern::new("$$$A", Tsx);
+    assert!(matches!(goal, Pattern::MetaVar { .. }));
+    let root = Root::str("foo(a, b); { c(); d() }", Tsx);
+    let find = |kind: &str| root.root().dfs().find(|n| n.kind() == kind);
+    let args = find("arguments").expect("should find arguments");
+    let mut env = Cow::Owned(MetaVarEnv::new());
+    let opts = MatchOptions::default();
+    assert!(match_node_non_recursive(&goal, args, &mut env, &opts, None).is_some());
+    assert_eq!(HashMap::from(env.into_owned())["A"], "[a, ,, b]");
+    // select all statements in a block
+    let block = find("statement_block").expect("should find block");
+    let mut env = Cow::Owned(MetaVarEnv::new());
+    assert!(match_node_non_recursive(&goal, block, &mut env, &opts, None).is_some());
+    assert_eq!(HashMap::from(env.into_owned())["A"], "[c();, d()]");
+    // a leaf has no children
+    let leaf = find("identifier").expect("should find identifier");
+    let mut env = Cow::Owned(MetaVarEnv::new());
+    assert!(match_node_non_recursive(&goal, leaf.clone(), &mut env, &opts, None).is_some());
+    assert_eq!(env.get_multi_ranges("A"), Some(vec![]));
+    // anonymous ellipsis matches any node
+    let goal = Pattern::new("$$$", Tsx);
+    let mut env = Cow::Owned(MetaVarEnv::new());
+    assert!(match_node_non_recursive(&goal, leaf, &mut env, &opts, None).is_some());
+  }
+
+  #[test]
+  fn test_leading_ellipsis() {
+    test_match("foo($$$, c)", "foo(a, b, c)");
+    test_match("foo($$$, b, c)", "foo(a, b, c)");
+    test_match("foo($$$, a, b, c)", "foo(a, b, c)");
+    test_non_match("foo($$$, a, b, c)", "foo(b, c)");
+  }
+  #[test]
+  fn test_trailing_ellipsis() {
+    test_match("foo(a, $$$)", "foo(a, b, c)");
+    test_match("foo(a, b, $$$)", "foo(a, b, c)");
+    // test_match("foo(a, b, c, $$$)", "foo(a, b, c)");
+    test_non_match("foo(a, b, c, $$$)", "foo(b, c)");
+  }
+
+  #[test]
+  fn test_meta_var_named() {
+    test_match("return $A", "return 123;");
+    test_match("return $_", "return 123;");
+    test_non_match("return $A", "return;");
+    test_non_match("return $_", "return;");
+    test_match("return $$A", "return;");
+    test_match("return $$_A", "return;");
+  }
+
+  #[test]
+  fn test_optional_single_meta_var_separators() {
+    // leading
+    let env = test_match("foo($$A, b)", "foo(b)");
+    assert!(!env.contains_key("A"));
+    let env = test_match("foo($$A, b)", "foo(a, b)");
+    assert_eq!(env["A"], "a");
+    // the next significant goal is a meta variable, taking the candidate is preferred
+    let env = test_match("foo($$A, $B)", "foo(a, b)");
+    assert_eq!(env["A"], "a");
+    assert_eq!(env["B"], "b");
+    let env = test_match("foo($$A, $B)", "foo(b)");
+    assert!(!env.contains_key("A"));
+    assert_eq!(env["B"], "b");
+    // middle
+    let env = test_match("foo(a, $$A, c)", "foo(a, c)");
+    assert!(!env.contains_key("A"));
+    let env = test_match("foo(a, $$A, c)", "foo(a, b, c)");
+    assert_eq!(env["A"], "b");
+    test_non_match("foo(a, $$A, c)", "foo(a, b, d, c)");
+    // trailing
+    let env = test_match("foo(a, $$A)", "foo(a)");
+    assert!(!env.contains_key("A"));
+    let env = test_match("let $A = $$B", "let a");
+    assert_eq!(env["A"], "a");
+    assert!(!env.contains_key("B"));
+    let env = test_match("let $A = $$B", "let a = 1");
+    assert_eq!(env["B"], "1");
+    // match_end agrees
+    assert_eq!(test_end("foo($$A, b)", "foo(b)"), Some(6));
+    assert_eq!(test_end("foo(a, $$A, c)", "foo(a, c)"), Some(9));
+    assert_eq!(test_end("foo(a, $$A)", "foo(a)"), Some(6));
+  }
+
+  #[test]
+  fn test_optional_single_meta_var() {
+    // absent when the next goal matches the candidate
+    let env = test_match("foo($$A)", "foo()");
+    assert!(!env.contains_key("A"));
+    let env = test_match("foo($$A)", "foo(x)");
+    assert_eq!(env["A"], "x");
+    let env = test_match("[$$A]", "[]");
+    assert!(!env.contains_key("A"));
+    let env = test_match("new Foo($$A)", "new Foo(1)");
+    assert_eq!(env["A"], "1");
+    // absent when no candidate is left
+    let env = test_match("return $$A", "return");
+    assert!(!env.contains_key("A"));
+    // trailing unnamed tokens are still captured
+    let env = test_match("return $$A", "return;");
+    assert_eq!(env["A"], ";");
+    test_non_match("foo($$A)", "foo(x, y)");
+  }
+
+  #[test]
+  fn test_meta_var_multiple_occurrence() {
+    test_match("$A($$$)", "test(123)");
+    test_match("$A($B)", "test(123)");
+    test_non_match("$A($A)", "test(aaa)");
+    test_non_match("$A($A)", "test(123)");
+    test_non_match("$A($A, $A)", "test(123, 456)");
+    test_match("$A($A)", "test(test)");
+    test_non_match("$A($A)", "foo(bar)");
+  }
+
+  #[test]
+  fn test_string() {
+    test_match("'a'", "'a'");
+    test_match("'abcdefg'", "'abcdefg'");
+    test_match("`abcdefg`", "`abcdefg`");
+    test_non_match("'a'", "'b'");
+    test_non_match("'abcdefg'", "'gggggg'");
+  }
+
+  #[test]
+  fn test_skip_trivial_node() {
+    test_match("foo($A, $B)", "foo(a, b,)");
+    test_match("class A { b() {}}", "class A { get b() {}}");
+  }
+
+  #[test]
+  fn test_trivia_in_pattern() {
+    test_match("foo($A, $B,)", "foo(a, b,)");
+    test_non_match("foo($A, $B,)", "foo(a, b)");
+    test_match("class A { get b() {}}", "class A { get b() {}}");
+    test_non_match("class A { get b() {}}", "class A { b() {}}");
+  }
+
+  #[test]
+  fn test_trivia_goal_before_significant() {
+    // trailing comma in goal must be present in candidate
+    test_match("foo($A,)", "foo(a,)");
+    test_non_match("foo($A,)", "foo(a)");
+    test_non_match("foo($A,)", "foo(a, b)");
+    test_non_match("foo(,)", "foo(a)");
+    // extra trivia in goal is not aligned with a later trivial candidate
+    test_non_match("foo($A, ,)", "foo(a,)");
+    test_non_match("let a = [$A, , $B]", "let a = [1, 2, 3]");
+    test_non_match("let a = [1, , 2]", "let a = [1, 2]");
+    // extra trivia in candidate is skipped
+    test_match("let a = [1, 2]", "let a = [1, , 2]");
+  }
+
+  fn find_end_recursive(goal: &Pattern<Tsx>, node: Node<StrDoc<Tsx>>) -> Option<usize> {
+    match_end_non_recursive(goal, node.clone(), &MatchOptions::default()).or_else(|| {
+      node
+        .children()
+        .find_map(|sub| find_end_recursive(goal, sub))
+    })
+  }
+
+  #[test]
+  fn test_match_span() {
+    let goal = Pattern::new("return $A", Tsx);
+    let cand = Root::str("return 123 /* trivia */", Tsx);
+    let stmt = cand.root().child(0).expect("should have statement");
+    let span = match_span_non_recursive(&goal, stmt.clone(), &MatchOptions::default());
+    assert_eq!(span, Some(0..10));
+    let cand = Root::str("let a = 1; return 123 /* trivia */", Tsx);
+    let stmt = cand.root().child(1).expect("should have statement");
+    let span = match_span_non_recursive(&goal, stmt.clone(), &MatchOptions::default());
+    assert_eq!(span, Some(11..21));
+    let span = match_span_non_recursive(&goal, cand.root(), &MatchOptions::default());
+    assert_eq!(span, None);
+  }
+
+  fn test_end(s1: &str, s2: &str) -> Option<usize> {
+    let goal = Pattern::new(s1, Tsx);
+    let cand = Root::new(s2, Tsx);
+    let cand = cand.root();
+    find_end_recursive(&goal, cand.clone())
+  }
+
+  #[test]
+  fn test_match_end() {
+    let end = test_end("return $A", "return 123 /* trivia */");
+    assert_eq!(end.expect("should work"), 10);
+    let end = test_end("return f($A)", "return f(1,) /* trivia */");
+    assert_eq!(end.expect("should work"), 12);
+  }
+
+  // see https://github.com/ast-grep/ast-grep/issues/411
+  #[test]
+  fn test_ellipsis_end() {
+    let end = test_end(
+      "import {$$$A, B, $$$C} from 'a'",
+      "import {A, B, C} from 'a'",
+    );
+    assert_eq!(end.expect("must match"), 25);
+  }
+
+  #[test]
+  fn test_zero_width_end() {
+    // error recovery inserts a zero-width `)` after the comment
+    let root = Root::str("foo(a, /* c */; bar()", Tsx);
+    let args = root.root().dfs().find(|n| n.kind() == "arguments");
+    let args = args.expect("should find arguments");
+    let children: Vec<_> = args.children().collect();
+    let missing = children.last().expect("should recover");
+    assert_eq!((missing.kind().as_ref(), missing.range()), (")", 14..14));
+    // the end is that of the comment, not the zero-width node
+    let inner = children[1..].iter().cloned();
+    assert_eq!(last_end(inner), Some(14));
+    assert_eq!(last_end(children.into_iter().skip(4)), None);
+    let goal = Pattern::new("foo($$$A)", Tsx);
+    let span = match_span_non_recursive(
+      &goal,
+      args.parent().expect("call"),
+      &MatchOptions::default(),
+    );
+    assert_eq!(span, Some(0..14));
+    assert_eq!(test_end("foo($$$)", "foo(1; bar()"), Some(5));
+  }
+
+  #[test]
+  fn test_gh_1087() {
+    test_match("($P) => $F($P)", "(x) => bar(x)");
+  }
+
+  #[test]
+  fn test_exact_match_leaf_kinds() {
+    let root = Root::str("x; 'x'; a.x", Tsx);
+    let node = root.root();
+    let find = |kind: &str| {
+      let found = node.dfs().find(|n| n.kind() == kind);
+      found.expect("should find kind")
+    };
+    let ident = find("identifier");
+    let fragment = find("string_fragment");
+    let property = find("property_identifier");
+    assert_eq!(ident.text(), fragment.text());
+    // by default only leaf text is compared
+    assert!(does_node_match_exactly(&ident, &fragment));
+    assert!(does_node_match_exactly(&ident, &property));
+  }
+
+  #[derive(Clone)]
+  struct StrictTsx;
+  impl Language for StrictTsx {
+    fn get_ts_language(&self) -> crate::language::TSLanguage {
+      Tsx.get_ts_language()
+    }
+    fn leaf_kinds_compatible(&self, goal_kind: u16, candidate_kind: u16) -> bool {
+      goal_kind == candidate_kind
+    }
+  }
+
+  #[test]
+  fn test_exact_match_strict_leaf_kinds() {
+    let root = Root::str("x; 'x'; a.x; y.x", StrictTsx);
+    let node = root.root();
+    let leaves: Vec<_> = node.dfs().filter(|n| n.text() == "x").collect();
+    let [ident, fragment, property, other] = &leaves[..] else {
+      panic!("should find four leaves");
+    };
+    assert!(!does_node_match_exactly(ident, fragment));
+    assert!(!does_node_match_exactly(fragment, ident));
+    assert!(!does_node_match_exactly(ident, property));
+    assert!(does_node_match_exactly(property, other));
+    // repeated meta variables follow the language too
+    let pattern = Pattern::str("foo($A, x.$A)", StrictTsx);
+    let cand = Root::str("foo(a, x.a)", StrictTsx);
+    assert!(cand.root().find(&pattern).is_none());
+  }
+
+  #[test]
+  fn test_exact_match_operators() {
+    let root = Root::str("a + b; a - b; a + b", Tsx);
+    let exprs: Vec<_> = root
+      .root()
+      .dfs()
+      .filter(|n| n.kind() == "binary_expression")
+      .collect();
+    assert!(!does_node_match_exactly(&exprs[0], &exprs[1]));
+    assert!(!does_node_match_strict(&exprs[0], &exprs[1]));
+    assert!(does_node_match_exactly(&exprs[0], &exprs[2]));
+    let ops: Vec<_> = exprs.iter().filter_map(|n| n.child(1)).collect();
+    assert!(!does_node_match_exactly(&ops[0], &ops[1]));
+    assert!(does_node_match_exactly(&ops[0], &ops[2]));
+  }
+
+  #[test]
+  fn test_exact_match_explain() {
+    let root = Root::str(
+      "foo(a, [b, c]); foo(a, [b, d]); foo(a, [b]); foo(a, (b, c));",
+      Tsx,
+    );
+    let calls: Vec<_> = root.root().children().collect();
+    assert_eq!(does_node_match_exactly_explain(&calls[0], &calls[0]), None);
+    // statement > call > arguments > array > identifier
+    let mismatch = does_node_match_exactly_explain(&calls[0], &calls[1]);
+    let mismatch = mismatch.expect("should differ");
+    assert!(matches!(mismatch, ExactMismatch::LeafText { .. }));
+    assert_eq!(mismatch.path(), [0, 1, 3, 3]);
+    let (goal, cand) = mismatch.ranges();
+    assert_eq!(&root.get_text()[goal], "c");
+    assert_eq!(&root.get_text()[cand], "d");
+    let mismatch = does_node_match_exactly_explain(&calls[0], &calls[2]);
+    let mismatch = mismatch.expect("should differ");
+    assert!(matches!(mismatch, ExactMismatch::ChildCount { .. }));
+    assert_eq!(mismatch.path(), [0, 1, 3]);
+    let mismatch = does_node_match_exactly_explain(&calls[0], &calls[3]);
+    assert!(matches!(mismatch, Some(ExactMismatch::Kind { .. })));
+  }
+
+  #[test]
+  fn test_too_few_children() {
+    test_non_match("foo($A, $B)", "foo(a)");
+    test_non_match("class A { a = 1; b = 2 }", "class A { a = 1 }");
+    // ellipsis can match zero nodes so the precheck is skipped
+    test_match("foo($$$)", "foo()");
+    test_match("foo($$$A,)", "foo()");
+  }
+
+  #[test]
+  fn test_failed_child_does_not_bind() {
+    // bar(x, 2) binds $A before failing on 1
+    let env = test_match("foo($$$, bar($A, 1))", "foo(bar(x, 2), bar(y, 1))");
+    assert_eq!(env["A"], "y");
+    let env = test_match("foo($$$B, bar($A, 1))", "foo(bar(x, 2), bar(y, 1))");
+    assert_eq!(env["B"], "[bar(x, 2)]");
+    // sibling candidates tried by the recursive search do not leak either
+    let env = test_match("bar($A, 1)", "baz(bar(x, 2), bar(y, 1))");
+    assert_eq!(env["A"], "y");
+  }
+
+  #[test]
+  fn test_nodes_equal_with_options() {
+    let root = Root::str("(x) => x", Tsx);
+    let node = root.root();
+    let param = node.dfs().find(|n| n.kind() == "required_parameter");
+    let param = param.expect("should find parameter");
+    let body = node.dfs().filter(|n| n.kind() == "identifier").last();
+    let body = body.expect("should find body");
+    assert_eq!(param.text(), body.text());
+    assert!(nodes_structurally_equal(&param, &body));
+    let strict = EqualityOptions::default().leaf_text(false);
+    assert!(!nodes_equal_with_options(&param, &body, &strict));
+    assert!(nodes_equal_with_options(
+      &param,
+      &body,
+      &EqualityOptions::default()
+    ));
+    // whitespace is ignored in both modes
+    let root = Root::str("foo(a,b); foo( a , b );", Tsx);
+    let calls: Vec<_> = root.root().children().collect();
+    assert!(nodes_structurally_equal(&calls[0], &calls[1]));
+    assert!(nodes_equal_with_options(&calls[0], &calls[1], &strict));
+  }
+
+  #[test]
+  fn test_empty_candidates() {
+    // the arguments node has parenthesis children, both matchers agree
+    assert_eq!(test_end("foo($$$)", "foo()"), Some(5));
+    test_match("foo($$$)", "foo()");
+    let multi = |name: &str| Pattern::MetaVar {
+      meta_var: MetaVariable::MultiCapture(name.into()),
+      kind: None,
+    };
+    let ellipsis = Pattern::MetaVar {
+      meta_var: MetaVariable::Multiple,
+      kind: None,
+    };
+    let opts = MatchOptions::default();
+    let empty = || std::iter::empty::<Node<StrDoc<Tsx>>>();
+    let goals = [ellipsis, multi("A")];
+    let end = match_multi_nodes_end_non_recursive(&goals, empty(), 42, &opts);
+    assert_eq!(end, Some(42));
+    let mut env = Cow::Owned(MetaVarEnv::new());
+    assert!(match_nodes_non_recursive(&goals, empty(), &mut env, &opts, None).is_some());
+    assert_eq!(env.get_multi_ranges("A"), Some(vec![]));
+    // a non ellipsis goal needs a candidate
+    let goals = [multi("A"), Pattern::str("a", Tsx)];
+    let end = match_multi_nodes_end_non_recursive(&goals, empty(), 42, &opts);
+    assert_eq!(end, None);
+    let mut env = Cow::Owned(MetaVarEnv::new());
+    assert!(match_nodes_non_recursive(&goals, empty(), &mut env, &opts, None).is_none());
+  }
+
+  #[test]
+  fn test_does_node_match_strict() {
+    let root = Root::str("(x) => x; (y) => y", Tsx);
+    let node = root.root();
+    let params: Vec<_> = node
+      .dfs()
+      .filter(|n| n.kind() == "required_parameter")
+      .collect();
+    let idents: Vec<_> = node.dfs().filter(|n| n.kind() == "identifier").collect();
+    // the parameter x and the body x
+    let (param, body) = (&params[0], &idents[1]);
+    assert_eq!(param.text(), body.text());
+    assert!(does_node_match_exactly(param, body));
+    assert!(!does_node_match_strict(param, body));
+    // same kind and text is equal in both modes
+    let arrows: Vec<_> = node
+      .dfs()
+      .filter(|n| n.kind() == "arrow_function")
+      .collect();
+    assert!(does_node_match_strict(&idents[0], &idents[1]));
+    assert!(!does_node_match_strict(&arrows[0], &arrows[1]));
+  }
+
+  #[test]
+  fn test_adjacent_ellipses() {
+    for src in ["foo()", "foo(a)", "foo(a, b, c)"] {
+      test_match("foo($$$, $$$)", src);
+      test_match("foo($$$ $$$)", src);
+    }
+    let env = test_match("foo($$$A, $$$B)", "foo()");
+    assert_eq!(env["A"], "[]");
+    assert_eq!(env["B"], "[]");
+    let env = test_match("foo($$$A, $$$B)", "foo(a, b, c)");
+    assert_eq!(env["A"], "[]");
+    assert_eq!(env["B"], "[a, ,, b, ,, c]");
+    let env = test_match("foo($$$A, $$$B, c)", "foo(a, b, c)");
+    assert_eq!(env["A"], "[]");
+    assert_eq!(env["B"], "[a, ,, b]");
+    let env = test_match("foo(x, $$$A, $$$B)", "foo(x, a)");
+    assert_eq!(env["B"], "[a]");
+    test_non_match("foo($$$, $$$, c)", "foo(a, b)");
+    assert_eq!(test_end("foo($$$, $$$)", "foo(a, b)"), Some(9));
+  }
+
+  /// Tsx where commas are significant.
+  #[derive(Clone)]
+  struct StrictComma;
+  impl Language for StrictComma {
+    fn get_ts_language(&self) -> crate::language::TSLanguage {
+      Tsx.get_ts_language()
+    }
+    fn is_trivial<D: Doc>(&self, node: &Node<D>) -> bool {
+      !node.is_named() && node.kind() != ","
+    }
+  }
+
+  #[test]
+  fn test_match_node_list() {
+    let pattern = Pattern::contextual("foo($A, $$$REST)", "arguments", Tsx).expect("should parse");
+    let Pattern::Internal { children, .. } = &pattern else {
+      panic!("arguments should be internal");
+    };
+    let grep = Root::new("bar(1, 2, 3)", Tsx);
+    let args = grep.root().find("bar($$$)").expect("should find call");
+    let args = args
+      .get_node()
+      .field("arguments")
+      .expect("should have arguments");
+    let opts = MatchOptions::default();
+    let env = match_node_list(children, args.children(), &opts).expect("should match");
+    let env = HashMap::from(env);
+    assert_eq!(env["A"], "1");
+    assert_eq!(env["REST"], "[2, ,, 3]");
+    // a hand picked list without brackets does not align with the goals
+    let named = args.children().filter(|n| n.is_named());
+    assert!(match_node_list(children, named, &opts).is_none());
+    assert!(match_node_list(&[], args.children(), &opts).is_some());
+    let exact = MatchOptions::default().exact_children(true);
+    assert!(match_node_list(&[], args.children(), &exact).is_none());
+  }
+
+  #[test]
+  fn test_line_contiguous_ellipsis() {
+    let block = "function f() {\n  a()\n  b()\n\n  c()\n\n\n  d()\n}";
+    // one blank line is allowed between captured statements
+    let env = test_match("function f() {\n  $$$$BODY\n  d()\n}", block);
+    assert_eq!(env["BODY"], "[a(), b(), c()]");
+    test_non_match("function f() { $$$$BODY }", block);
+    let env = test_match("function f() { $$$BODY }", block);
+    assert_eq!(env["BODY"], "[a(), b(), c(), d()]");
+    // a trailing ellipsis takes every remaining statement, including d() after two blank lines
+    test_non_match("function f() {\n  a()\n  $$$$BODY\n}", block);
+  }
+
+  #[test]
+  fn test_goal_trivial_with_field() {
+    // `+` is the unnamed `operator` field of the binary expression, the ellipsis must not skip it
+    test_non_match("$$$A + b", "x - b");
+    let env = test_match("$$$A + b", "x + b");
+    assert_eq!(env["A"], "[x]");
+    // unnamed leaves without field are still trivial
+    let env = test_match("foo($$$A, b)", "foo(a, b)");
+    assert_eq!(env["A"], "[a]");
+  }
+
+  #[test]
+  fn test_language_is_trivial() {
+    let matches = |pattern: &str, src: &str| {
+      let pattern = Pattern::str(pattern, StrictComma);
+      let root = Root::str(src, StrictComma);
+      let mut env = Cow::Owned(MetaVarEnv::new());
+      let opts = MatchOptions::default();
+      let found = root
+        .root()
+        .dfs()
+        .find_map(|n| match_node_non_recursive(&pattern, n, &mut env, &opts, None));
+      found.is_some()
+    };
+    test_match("foo(a)", "foo(a,)");
+    assert!(!matches("foo(a)", "foo(a,)"));
+    assert!(matches("foo(a,)", "foo(a,)"));
+    // other unnamed nodes are still trivial
+    test_match("foo(a)", "foo(a);");
+    assert!(matches("foo(a)", "foo(a);"));
+  }
+
+  #[test]
+  fn test_leading_and_trailing_ellipsis() {
+    for src in [
+      "foo(a, b, c)",
+      "foo(b)",
+      "foo(a, b)",
+      "foo(b, c)",
+      "foo(a, b, c, d)",
+    ] {
+      test_match("foo($$$, b, $$$)", src);
+      assert!(test_end("foo($$$, b, $$$)", src).is_some(), "{src}");
+    }
+    test_non_match("foo($$$, b, $$$)", "foo(a, c)");
+    test_non_match("foo($$$, b, $$$)", "foo()");
+    let env = test_match("foo($$$A, b, $$$C)", "foo(b)");
+    assert_eq!(env["A"], "[]");
+    assert_eq!(env["C"], "[]");
+    let env = test_match("foo($$$A, b, $$$C)", "foo(a, b, c, d)");
+    assert_eq!(env["A"], "[a]");
+    assert_eq!(env["C"], "[c, ,, d]");
+  }
+}
@@ -0,0 +1,1853 @@
+use super::does_node_match_exactly;
+use crate::meta_var::MetaVarEnv;
+#[cfg(feature = "globset")]
+use crate::PatternError;
+use crate::{Doc, Language, Node, Pattern};
+
+use bit_set::BitSet;
+use std::borrow::Cow;
+use std::ops::Range;
+use unicode_normalization::UnicodeNormalization;
+
+/// Max number of nested wrappers seen through, see [`MatchOptions::transparent_kinds`].
+/// Deeper wrapped candidates do not match.
+pub const MAX_TRANSPARENT_DEPTH: usize = 32;
+
+/// Resolves a reference, given its kind id and byte range, to an id of its declaration.
+/// See [`MatchOptions::identity_resolver`].
+pub type IdentityResolver = fn(u16, Range<usize>) -> Option<usize>;
+
+/// Name of the meta variable bound to the whole matched node, see `MatchOptions::bind_match`.
+pub const MATCH_VAR: &str = "MATCH";
+
+/// Options to customize how a pattern is matched against candidate nodes.
+/// The default options keep ast-grep's standard matching behavior.
+#[derive(Clone, Debug)]
+pub struct MatchOptions {
+  /// kinds compared by their cooked value, that is, after escape sequences are processed
+  cooked_kinds: Vec<u16>,
+  /// kinds whose named children are matched as a set without extra children
+  pub(super) exhaustive_set_kinds: Vec<u16>,
+  /// kinds whose content is ignored, any candidate of the same kind matches
+  opaque_kinds: Vec<u16>,
+  /// kinds whose named children are matched as a set, extra children are allowed
+  pub(super) unordered_kinds: Vec<u16>,
+  /// element kinds whose attribute children are matched regardless of order
+  pub(super) attribute_element_kinds: Vec<u16>,
+  /// kinds of the attribute children of `attribute_element_kinds`
+  pub(super) attribute_kinds: Vec<u16>,
+  /// whether candidate elements must not have extra attributes
+  pub(super) exact_attributes: bool,
+  /// whether an ellipsis only consumes candidates assigned to the same field
+  pub(super) ellipsis_respects_fields: bool,
+  /// kinds skipped like unnamed nodes unless the goal matches them
+  comment_kinds: Vec<u16>,
+  /// max edit distance between the text of a goal leaf and its candidate
+  fuzzy_leaf_distance: Option<u32>,
+  /// whether candidates must not have significant children after the goal's last child
+  pub(super) exact_children: bool,
+  /// kinds whose inner children are also bound when captured by a single meta variable
+  block_kinds: Vec<u16>,
+  /// leaf kinds compared after Unicode NFC normalization
+  nfc_kinds: Vec<u16>,
+  /// kinds of trailing terminators, e.g. `;`, that may be absent in the candidate
+  optional_terminators: Vec<u16>,
+  /// max depth of captured nodes below the matched node
+  max_capture_depth: Option<usize>,
+  /// min number of named nodes each multi meta variable must capture
+  min_named: Vec<(String, usize)>,
+  /// whether single meta variables refuse trivial and comment nodes
+  forbid_trivia_capture: bool,
+  /// wrapper kinds matched through their single significant child
+  transparent_kinds: Vec<u16>,
+  /// number literal kinds compared by value
+  numeric_kinds: Vec<u16>,
+  /// globs the text captured by each single meta variable must match
+  #[cfg(feature = "globset")]
+  text_globs: Vec<(String, globset::GlobMatcher)>,
+  /// quote character the string captured by each single meta variable must use
+  string_quotes: Vec<(String, char)>,
+  /// candidate kinds accepted in place of each goal kind
+  kind_aliases: Vec<(u16, Vec<u16>)>,
+  /// whether `ERROR` nodes never match a goal and are skipped like trivia
+  skip_error_nodes: bool,
+  /// whether a repeated single meta variable must bind the same source text
+  textual_consistency: bool,
+  /// resolves a reference to an id of its declaration, see `identity_resolver`
+  identity_resolver: Option<IdentityResolver>,
+  /// pairs of meta variables that must not bind equal nodes
+  distinct: Vec<(String, String)>,
+  /// whether an ellipsis followed by another ellipsis consumes exactly one candidate
+  pub(super) adjacent_ellipsis_consumes_one: bool,
+  /// whether the matched node is bound to `$MATCH`
+  bind_match: bool,
+  /// kinds the last significant child of a candidate of each parent kind must not have
+  forbidden_trailing: Vec<(u16, Vec<u16>)>,
+  /// decides whether a candidate is skippable given its kind and the default decision
+  trivia_override: Option<fn(u16, bool) -> bool>,
+  /// fields whose candidate children are left out of alignment
+  ignored_fields: Vec<u16>,
+  /// classes of kinds interchangeable with each other
+  kind_classes: Vec<Vec<u16>>,
+  /// steps enumerating all bindings may take before giving up
+  pub(super) max_backtrack_steps: Option<usize>,
+  /// meta variable names merged into another name, see `MetaVarEnv::alias_var`
+  var_aliases: Vec<(String, String)>,
+  /// binary kinds with the operators whose chains are flattened
+  associative_operators: Vec<(u16, Vec<String>)>,
+  /// leaf kinds the renames apply to
+  rename_kinds: Vec<u16>,
+  /// candidate identifiers with the names they are matched as
+  renames: Vec<(String, String)>,
+  /// consecutive trivial candidates one goal may skip to be aligned
+  max_trivia_skip: Option<usize>,
+}
+
+/// The default options, shared by patterns matched without options so no value is built per candidate.
+pub(crate) static STANDARD_OPTIONS: MatchOptions = MatchOptions::standard();
+
+impl Default for MatchOptions {
+  fn default() -> Self {
+    Self::standard()
+  }
+}
+
+impl MatchOptions {
+  const fn standard() -> Self {
+    Self {
+      cooked_kinds: Vec::new(),
+      exhaustive_set_kinds: Vec::new(),
+      opaque_kinds: Vec::new(),
+      unordered_kinds: Vec::new(),
+      attribute_element_kinds: Vec::new(),
+      attribute_kinds: Vec::new(),
+      exact_attributes: false,
+      ellipsis_respects_fields: false,
+      comment_kinds: Vec::new(),
+      fuzzy_leaf_distance: None,
+      exact_children: false,
+      block_kinds: Vec::new(),
+      nfc_kinds: Vec::new(),
+      optional_terminators: Vec::new(),
+      max_capture_depth: None,
+      min_named: Vec::new(),
+      forbid_trivia_capture: false,
+      transparent_kinds: Vec::new(),
+      numeric_kinds: Vec::new(),
+      #[cfg(feature = "globset")]
+      text_globs: Vec::new(),
+      string_quotes: Vec::new(),
+      kind_aliases: Vec::new(),
+      skip_error_nodes: false,
+      textual_consistency: false,
+      identity_resolver: None,
+      distinct: Vec::new(),
+      adjacent_ellipsis_consumes_one: false,
+      bind_match: false,
+      forbidden_trailing: Vec::new(),
+      trivia_override: None,
+      ignored_fields: Vec::new(),
+      kind_classes: Vec::new(),
+      max_backtrack_steps: None,
+      var_aliases: Vec::new(),
+      associative_operators: Vec::new(),
+      rename_kinds: Vec::new(),
+      renames: Vec::new(),
+      max_trivia_skip: None,
+    }
+  }
+
+  /// Compare nodes of the given kinds, e.g. template strings, by their cooked value instead of raw source.
+  /// For example, `` `a\tb` `` and `` `a<TAB>b` `` are equal when cooked.
+  /// Patterns containing meta variables are still matched structurally.
+  pub fn cooked_kinds<I: IntoIterator<Item = u16>>(mut self, kinds: I) -> Self {
+    self.cooked_kinds = kinds.into_iter().collect();
+    self
+  }
+
+  /// Match named children of the given kinds, e.g. objects or JSX elements, as a set.
+  /// Every goal child must match a distinct candidate child in any order,
+  /// and the candidate must not have other named children.
+  /// For example, `{ a: $A, b: $B }` matches `{ b: 2, a: 1 }` but not `{ a: 1, b: 2, c: 3 }`.
+  /// An ellipsis in the goal allows extra children, which `$$$REST` captures.
+  pub fn exhaustive_set_kinds<I: IntoIterator<Item = u16>>(mut self, kinds: I) -> Self {
+    self.exhaustive_set_kinds = kinds.into_iter().collect();
+    self
+  }
+
+  /// Match named children of the given kinds, e.g. object properties, regardless of order.
+  /// Every goal child must match a distinct candidate child but the candidate may have more children,
+  /// so `{ a: $X, b: $Y }` matches `{ c: 3, b: 2, a: 1 }`. See `exhaustive_set_kinds` to forbid extras.
+  pub fn unordered_kinds<I: IntoIterator<Item = u16>>(mut self, kinds: I) -> Self {
+    self.unordered_kinds = kinds.into_iter().collect();
+    self
+  }
+
+  /// Match attributes of the given element kinds, e.g. JSX opening and self-closing elements, regardless of order.
+  /// Children of `attributes` kinds are matched as a set while other children, like the element name, stay positional.
+  /// So `<A b={1} c='d' />` matches `<A c='d' e b={1} />`. See `exact_attributes` to forbid extra attributes.
+  pub fn unordered_attributes<E, A>(mut self, elements: E, attributes: A) -> Self
+  where
+    E: IntoIterator<Item = u16>,
+    A: IntoIterator<Item = u16>,
+  {
+    self.attribute_element_kinds = elements.into_iter().collect();
+    self.attribute_kinds = attributes.into_iter().collect();
+    self
+  }
+
+  /// Require candidate elements of `unordered_attributes` to have no attribute beyond the goal's.
+  /// An ellipsis in the goal still allows extra attributes, which `$$$REST` captures.
+  pub fn exact_attributes(mut self, exact: bool) -> Self {
+    self.exact_attributes = exact;
+    self
+  }
+
+  /// Stop an ellipsis from spanning named candidates assigned to different fields of their parent.
+  /// For example, `if ($C) $$$REST` captures both `foo();` and `else bar();` in `if (x) foo(); else bar();`,
+  /// but only `foo();` with this option because the else clause is the `alternative` field, not `consequence`.
+  /// An ellipsis followed by other goals fails to match instead of crossing a field.
+  pub fn ellipsis_respects_fields(mut self, respects: bool) -> Self {
+    self.ellipsis_respects_fields = respects;
+    self
+  }
+
+  /// Treat candidate nodes of the given kinds as comments. A comment is skipped like an unnamed node
+  /// if the goal does not match it, so `foo(a, b)` matches `foo(a, /* c */ b)`.
+  /// A comment in the goal is still matched against candidate comments by text,
+  /// e.g. `// TODO: fix` only matches comments with that text.
+  pub fn comment_kinds<I: IntoIterator<Item = u16>>(mut self, kinds: I) -> Self {
+    self.comment_kinds = kinds.into_iter().collect();
+    self
+  }
+
+  /// Match leaf text within the given Levenshtein distance, e.g. to find near-duplicate code with typos.
+  /// With distance 1, `foo($A)` matches `fooo(x)`. The tree structure must still match exactly.
+  /// `None`, the default, requires leaf text to be equal.
+  pub fn fuzzy_leaf_distance(mut self, distance: Option<u32>) -> Self {
+    self.fuzzy_leaf_distance = distance;
+    self
+  }
+
+  /// Require the goal's children to cover all significant children of the candidate.
+  /// By default, matching stops once every goal child is matched and later candidate siblings are ignored,
+  /// e.g. `let $A = $B` matches `let a = 1, b`. With this option the extra declarator fails the match.
+  /// Unnamed nodes and `comment_kinds` are not significant. A trailing ellipsis still captures the rest.
+  pub fn exact_children(mut self, exact: bool) -> Self {
+    self.exact_children = exact;
+    self
+  }
+
+  /// Also bind the content of blocks of the given kinds, e.g. `statement_block`, captured by `$A`.
+  /// `$A` still captures the whole block node with its range, and the children between its delimiters,
+  /// like the statements inside `{ ... }`, are bound as the multi meta variable `A.body`.
+  /// Read them with `env.get_multiple_matches("A.body")`. The name cannot clash with a pattern's variable.
+  pub fn block_kinds<I: IntoIterator<Item = u16>>(mut self, kinds: I) -> Self {
+    self.block_kinds = kinds.into_iter().collect();
+    self
+  }
+
+  /// Compare leaves of the given kinds, e.g. identifiers, after Unicode NFC normalization.
+  /// So `café` written with a combining accent matches the precomposed `café`.
+  /// Only list identifier-like kinds: normalizing string literals would change what they mean.
+  pub fn nfc_kinds<I: IntoIterator<Item = u16>>(mut self, kinds: I) -> Self {
+    self.nfc_kinds = kinds.into_iter().collect();
+    self
+  }
+
+  /// Match candidate leaves of the given kinds, e.g. identifiers, as if they were renamed by `renames`,
+  /// pairs of an old name and its new name. So a pattern written with new names matches code with old ones:
+  /// `newName()` matches `oldName()` under `("oldName", "newName")`, and `oldName()` no longer does.
+  /// Leaves of other kinds, e.g. strings or property names unless listed, are compared as is.
+  pub fn rename_map<I: IntoIterator<Item = u16>>(
+    mut self,
+    kinds: I,
+    renames: &[(&str, &str)],
+  ) -> Self {
+    self.rename_kinds = kinds.into_iter().collect();
+    let renames = renames
+      .iter()
+      .map(|(old, new)| (old.to_string(), new.to_string()));
+    self.renames = renames.collect();
+    self
+  }
+
+  /// Compare number literals of the given kinds by value, so `0x1`, `1.0` and `1_000` match `1`, `1` and `1000`.
+  /// Hexadecimal, octal and binary prefixes, underscores and exponents are understood.
+  /// Text that is not a number is compared as is. Like `nfc_kinds`, the normalization also applies
+  /// when a repeated meta variable is checked, so `foo($A, $A)` matches `foo(0x1, 1)`.
+  pub fn numeric_kinds<I: IntoIterator<Item = u16>>(mut self, kinds: I) -> Self {
+    self.numeric_kinds = kinds.into_iter().collect();
+    self
+  }
+
+  /// Let a trailing terminator of the given kinds, e.g. `;` in JavaScript, be absent in the candidate.
+  /// A goal whose last child is such a terminator matches without it, so `const a = 1;` matches `const a = 1`.
+  /// The other direction already matches by default: `const a = 1` ignores the candidate's trailing `;`.
+  /// Only the last child is affected, a terminator in the middle of the goal is still required.
+  pub fn optional_terminators<I: IntoIterator<Item = u16>>(mut self, kinds: I) -> Self {
+    self.optional_terminators = kinds.into_iter().collect();
+    self
+  }
+
+  /// Reject a match if a node captured by the pattern's meta variables is more than `depth` levels
+  /// below the matched node. A direct child of the matched node is at depth 1.
+  /// So with depth 1, `$A + $B` matches `a + b` but `foo($A)` does not match `foo(a)`,
+  /// where `a` is inside the `arguments` node. Useful with [`crate::Pattern::with_descendant`],
+  /// whose inner captures can be at any depth. Bindings made outside the matched node are not checked.
+  pub fn max_capture_depth(mut self, depth: Option<usize>) -> Self {
+    self.max_capture_depth = depth;
+    self
+  }
+
+  /// Require the multi meta variable `$$$name` to capture at least `count` named nodes.
+  /// Unnamed nodes like commas do not count, so `{ $$$BODY }` with `min_named("BODY", 2)`
+  /// matches a block of at least two statements. Call it again for other variables.
+  /// An ellipsis does not backtrack, so a split with too few named nodes fails the match.
+  pub fn min_named(mut self, name: &str, count: usize) -> Self {
+    self.min_named.retain(|(n, _)| n != name);
+    self.min_named.push((name.to_string(), count));
+    self
+  }
+
+  /// Forbid single meta variables, named or not, from capturing nodes that can be skipped:
+  /// trivial nodes like punctuation and nodes of `comment_kinds`. By default `$$A` and `$$_`
+  /// bind punctuation and `$A` binds comments, so `foo($A)` does not match `foo(/* a */ b)`.
+  /// With this option and comments configured, `$A` skips the comment and binds `b`.
+  pub fn forbid_trivia_capture(mut self, forbid: bool) -> Self {
+    self.forbid_trivia_capture = forbid;
+    self
+  }
+
+  /// See through candidate nodes of the given wrapper kinds, e.g. `expression_statement` or `statement_block`.
+  /// If a goal does not match such a candidate and the candidate has exactly one child that cannot be skipped,
+  /// the goal is matched against that child instead. So `() => foo($A)` matches `() => { foo(1); }`
+  /// with `statement_block` and `expression_statement` transparent. The wrapper is reported as matched,
+  /// so searching may find both the wrapper and its child. Unlike trivia, the wrapper is not skipped over.
+  /// Nested wrappers are seen through at any depth up to [`MAX_TRANSPARENT_DEPTH`], so with
+  /// `parenthesized_expression` transparent `foo(x)` matches `foo(((x)))`.
+  pub fn transparent_kinds<I: IntoIterator<Item = u16>>(mut self, kinds: I) -> Self {
+    self.transparent_kinds = kinds.into_iter().collect();
+    self
+  }
+
+  /// Accept candidates of `candidate_kinds` where the goal has a node of `goal_kind`, e.g. a kind renamed
+  /// between grammar versions, so a pattern built with one version matches trees parsed by another.
+  /// The goal's children are still matched against the candidate's. Call it again for other goal kinds.
+  pub fn kind_alias<I: IntoIterator<Item = u16>>(
+    mut self,
+    goal_kind: u16,
+    candidate_kinds: I,
+  ) -> Self {
+    self.kind_aliases.retain(|(k, _)| *k != goal_kind);
+    self
+      .kind_aliases
+      .push((goal_kind, candidate_kinds.into_iter().collect()));
+    self
+  }
+
+  /// Treat the given kinds as interchangeable, e.g. `identifier` and `property_identifier`, so a goal node
+  /// of any kind in the class matches candidates of every kind in it. Unlike `kind_alias` the class is
+  /// symmetric, so one pattern covers all its kinds. Leaves are still compared by text and internal nodes
+  /// by children. Call it again for other classes, a kind should belong to one class.
+  pub fn kind_class<I: IntoIterator<Item = u16>>(mut self, kinds: I) -> Self {
+    self.kind_classes.push(kinds.into_iter().collect());
+    self
+  }
+
+  /// Cap the steps [`ConfiguredPattern::match_all_bindings`](crate::matcher::ConfiguredPattern::match_all_bindings)
+  /// takes backtracking over ellipsis splits, so a pattern with many ellipses gives up with an error
+  /// instead of hanging on a long child list. A step is one split tried. Unlimited by default.
+  pub fn max_backtrack_steps(mut self, steps: Option<usize>) -> Self {
+    self.max_backtrack_steps = steps;
+    self
+  }
+
+  /// Treat the meta variable `alias` as `canonical`, e.g. `$Y` as `$X`, when patterns are composed
+  /// from fragments naming the same capture differently. Both names must bind the same node and
+  /// either name looks the binding up, see [`MetaVarEnv::alias_var`].
+  pub fn meta_var_alias(mut self, alias: &str, canonical: &str) -> Self {
+    self.var_aliases.push((alias.into(), canonical.into()));
+    self
+  }
+
+  /// Register the meta variable aliases in the env before matching.
+  pub(crate) fn apply_var_aliases<D: Doc>(&self, env: &mut Cow<MetaVarEnv<D>>) {
+    if self.var_aliases.is_empty() {
+      return;
+    }
+    let env = env.to_mut();
+    for (alias, canonical) in &self.var_aliases {
+      env.alias_var(alias, canonical);
+    }
+  }
+
+  /// Treat chains of the given operators in binary nodes of `kind` as associative, e.g. `+` in TSX
+  /// `binary_expression`. `a + b + c` parses as `(a + b) + c`, so `b + c` has no node of its own.
+  /// With flattening, a goal chain that does not match structurally is matched against every contiguous
+  /// run of the candidate chain's operands, so `b + c` and `$A + $B` with `$A = b` match `a + b + c`.
+  /// The whole candidate chain is reported as the matched node. Only chains of one operator are flattened.
+  pub fn associative_operators(mut self, kind: u16, operators: &[&str]) -> Self {
+    let operators = operators.iter().map(|op| op.to_string()).collect();
+    self.associative_operators.push((kind, operators));
+    self
+  }
+
+  pub(super) fn is_associative(&self, kind: u16, operator: &str) -> bool {
+    self
+      .associative_operators
+      .iter()
+      .any(|(k, ops)| *k == kind && ops.iter().any(|op| op == operator))
+  }
+
+  /// Whether a candidate of `cand_kind` can match a goal node of `goal_kind`.
+  pub(super) fn kind_matches(&self, goal_kind: u16, cand_kind: u16) -> bool {
+    goal_kind == cand_kind
+      || self
+        .kind_aliases
+        .iter()
+        .any(|(k, aliases)| *k == goal_kind && aliases.contains(&cand_kind))
+      || self
+        .kind_classes
+        .iter()
+        .any(|class| class.contains(&goal_kind) && class.contains(&cand_kind))
+  }
+
+  /// Add the aliases and class members of the goal kinds in `kinds`,
+  /// so a kind prefilter does not skip aliased candidates.
+  pub(crate) fn add_kind_aliases(&self, kinds: &mut BitSet) {
+    for (kind, aliases) in &self.kind_aliases {
+      if kinds.contains((*kind).into()) {
+        kinds.extend(aliases.iter().map(|&a| usize::from(a)));
+      }
+    }
+    for class in &self.kind_classes {
+      if class.iter().any(|&k| kinds.contains(k.into())) {
+        kinds.extend(class.iter().map(|&k| usize::from(k)));
+      }
+    }
+  }
+
+  pub(super) fn is_transparent(&self, kind_id: u16) -> bool {
+    self.transparent_kinds.contains(&kind_id)
+  }
+
+  /// The node under the transparent wrappers of candidate, unwrapped until `stop` accepts a node
+  /// or no wrapper is left. None if candidate is no wrapper or wrapping is deeper than the guard.
+  pub(super) fn unwrap_transparent<'t, D: Doc>(
+    &self,
+    candidate: &Node<'t, D>,
+    stop: impl Fn(&Node<'t, D>) -> bool,
+  ) -> Option<Node<'t, D>> {
+    let mut node = self.transparent_child(candidate)?;
+    for _ in 1..MAX_TRANSPARENT_DEPTH {
+      if stop(&node) {
+        return Some(node);
+      }
+      match self.transparent_child(&node) {
+        Some(child) => node = child,
+        None => return Some(node),
+      }
+    }
+    stop(&node).then_some(node)
+  }
+
+  /// The single significant child of a transparent candidate, see `transparent_kinds`.
+  pub(super) fn transparent_child<'t, D: Doc>(
+    &self,
+    candidate: &Node<'t, D>,
+  ) -> Option<Node<'t, D>> {
+    if !self.is_transparent(candidate.kind_id()) {
+      return None;
+    }
+    let mut significant = candidate.children().filter(|c| !self.is_skippable(c));
+    let child = significant.next()?;
+    significant.next().is_none().then_some(child)
+  }
+
+  /// Treat `ERROR` nodes that tree-sitter inserts on malformed input as non-matchable. No goal, not even
+  /// a meta variable, matches them, and they are skipped between goals like trivia. So `foo($A, $B)`
+  /// matches `foo(a, b c)`, where `c` is wrapped in an error node, and `foo(a, %, b)` binds `$B` to `b`.
+  /// An ellipsis still captures error nodes it consumes. By default error nodes are matched like any other node.
+  pub fn skip_error_nodes(mut self, skip: bool) -> Self {
+    self.skip_error_nodes = skip;
+    self
+  }
+
+  /// Whether the candidate is an error node no goal may match, see `skip_error_nodes`.
+  pub(super) fn is_skipped_error<D: Doc>(&self, candidate: &Node<D>) -> bool {
+    self.skip_error_nodes && candidate.is_error()
+  }
+
+  /// Whether any option matches a node of some kind by other means than its children in order,
+  /// e.g. as opaque, cooked or a set. Standard options do not, so their candidates skip those checks.
+  pub(super) fn matches_kinds_specially(&self) -> bool {
+    !(self.opaque_kinds.is_empty()
+      && self.cooked_kinds.is_empty()
+      && self.exhaustive_set_kinds.is_empty()
+      && self.unordered_kinds.is_empty()
+      && self.attribute_element_kinds.is_empty())
+  }
+
+  /// Whether a single meta variable may capture the candidate, see `forbid_trivia_capture`.
+  pub(super) fn can_capture<D: Doc>(&self, candidate: &Node<D>) -> bool {
+    !self.forbid_trivia_capture || !self.is_skippable(candidate)
+  }
+
+  /// Require the text captured by the single meta variable `$name` to match `glob`, e.g. `test_*`.
+  /// `*` matches any text, `?` one character and `[abc]` one of the listed characters,
+  /// so `test_*` is a prefix and `*_test` a suffix constraint. The glob is compiled once here.
+  /// Call it again for other variables, a later glob replaces an earlier one of the same name.
+  /// Requires the `globset` feature.
+  #[cfg(feature = "globset")]
+  pub fn text_glob(mut self, name: &str, glob: &str) -> Result<Self, PatternError> {
+    let matcher = globset::Glob::new(glob)
+      .map_err(|e| PatternError::InvalidGlob {
+        glob: glob.to_string(),
+        reason: e.kind().to_string(),
+      })?
+      .compile_matcher();
+    self.text_globs.retain(|(n, _)| n != name);
+    self.text_globs.push((name.to_string(), matcher));
+    Ok(self)
+  }
+
+  /// Whether the candidate's text satisfies the glob of `$name`, see `text_glob`.
+  pub(super) fn text_glob_matches<D: Doc>(&self, name: &str, candidate: &Node<D>) -> bool {
+    #[cfg(feature = "globset")]
+    if let Some((_, glob)) = self.text_globs.iter().find(|(n, _)| n == name) {
+      return glob.is_match(candidate.text().as_ref());
+    }
+    let _ = (name, candidate);
+    true
+  }
+
+  /// Require the single meta variable `$name` to capture a string literal quoted with `quote`,
+  /// e.g. `'"'` so `foo($S)` finds `foo("a")` but not `foo('a')` or `foo(a)`, for rules enforcing
+  /// a quote style. The quote is read from the literal's opening token, or from its first character
+  /// if the grammar parses the literal as a single token. A later quote replaces an earlier one of the same name.
+  pub fn string_quote(mut self, name: &str, quote: char) -> Self {
+    self.string_quotes.retain(|(n, _)| n != name);
+    self.string_quotes.push((name.to_string(), quote));
+    self
+  }
+
+  /// Whether the candidate is a string literal with the quote of `$name`, see `string_quote`.
+  pub(super) fn string_quote_matches<D: Doc>(&self, name: &str, candidate: &Node<D>) -> bool {
+    let Some((_, quote)) = self.string_quotes.iter().find(|(n, _)| n == name) else {
+      return true;
+    };
+    let mut buf = [0; 4];
+    let quote = &*quote.encode_utf8(&mut buf);
+    if candidate.is_leaf() {
+      let text = candidate.text();
+      return text.len() > quote.len() && text.starts_with(quote) && text.ends_with(quote);
+    }
+    // the opening quote is an unnamed token, e.g. `"` in TSX `(string "\"" (string_fragment) "\"")`
+    let opening = candidate.child(0);
+    matches!(opening, Some(open) if !open.is_named() && open.text() == quote)
+  }
+
+  pub(super) fn min_named_of(&self, name: &str) -> usize {
+    let found = self.min_named.iter().find(|(n, _)| n == name);
+    found.map_or(0, |(_, count)| *count)
+  }
+
+  /// Whether the nodes `goal` captured in `env` are within `max_capture_depth` below `root`.
+  pub(crate) fn captures_within_depth<D: Doc>(
+    &self,
+    goal: &Pattern<D::Lang>,
+    root: &Node<D>,
+    env: &MetaVarEnv<D>,
+  ) -> bool {
+    let Some(max) = self.max_capture_depth else {
+      return true;
+    };
+    let depth = |node: &Node<D>| {
+      let up = node
+        .ancestors()
+        .position(|a| a.node_id() == root.node_id())?;
+      Some(up + 1)
+    };
+    goal.defined_vars().into_iter().all(|name| {
+      let single = env.get_match(name).cloned();
+      let multi = env.get_multiple_matches(name);
+      single
+        .into_iter()
+        .chain(multi)
+        .all(|node| depth(&node).map_or(true, |d| d <= max))
+    })
+  }
+
+  /// Goal children without a trailing terminator of `optional_terminators` kinds.
+  /// Extra trailing candidates are ignored, so the terminator matches whether the candidate has it or not.
+  pub(super) fn trim_terminator<'p, L: Language>(
+    &self,
+    goals: &'p [Pattern<L>],
+  ) -> &'p [Pattern<L>] {
+    match goals.split_last() {
+      Some((Pattern::Terminal { kind_id, .. }, rest))
+        if self.optional_terminators.contains(kind_id) =>
+      {
+        rest
+      }
+      _ => goals,
+    }
+  }
+
+  /// Whether a goal leaf's text matches the candidate's, see `fuzzy_leaf_distance` and `nfc_kinds`.
+  pub(super) fn leaf_text_matches(&self, goal: &str, candidate: &str, kind_id: u16) -> bool {
+    let candidate = self.renamed(candidate, kind_id);
+    if goal == candidate {
+      return true;
+    }
+    let goal = self.normalize_leaf(goal, kind_id);
+    let candidate = self.normalize_leaf(candidate, kind_id);
+    goal == candidate || self.within_fuzzy_distance(&goal, &candidate)
+  }
+
+  /// The name candidate leaf text is matched as, see `rename_map`.
+  fn renamed<'a>(&'a self, text: &'a str, kind_id: u16) -> &'a str {
+    if !self.rename_kinds.contains(&kind_id) {
+      return text;
+    }
+    let renamed = self.renames.iter().find(|(old, _)| old == text);
+    renamed.map_or(text, |(_, new)| new.as_str())
+  }
+
+  /// Leaf text after `nfc_kinds` and `numeric_kinds` normalization.
+  fn normalize_leaf<'a>(&self, text: &'a str, kind_id: u16) -> Cow<'a, str> {
+    if self.nfc_kinds.contains(&kind_id) {
+      Cow::Owned(text.nfc().collect())
+    } else if self.numeric_kinds.contains(&kind_id) {
+      normalize_number(text).map_or(Cow::Borrowed(text), Cow::Owned)
+    } else {
+      Cow::Borrowed(text)
+    }
+  }
+
+  /// Choose how a single meta variable appearing twice, e.g. `$A` in `f($A)($A)`, checks its bindings agree.
+  /// By default the bound nodes must be structurally equal, see [`does_node_match_exactly`], so formatting
+  /// is ignored and `f($A)($A)` matches `f(a + b)(a+b)`. With textual consistency the nodes must have
+  /// the same source text instead, and that candidate does not match. Multi meta variables are always structural.
+  pub fn textual_consistency(mut self, textual: bool) -> Self {
+    self.textual_consistency = textual;
+    self
+  }
+
+  /// Require a single meta variable appearing twice to bind occurrences of the same declaration,
+  /// not only equal code, e.g. so `$A($A)` does not match `x(x)` where the two `x` are different variables.
+  /// The matcher is syntactic, so `resolve` provides the scoping: it gets the kind id and byte range of a
+  /// bound node and returns an id of the declaration it refers to, or None if it is not a reference.
+  /// Bound nodes and their descendants must still be equal, and each pair that both resolve must get
+  /// the same id. None compares bindings syntactically only, which is the default.
+  pub fn identity_resolver(mut self, resolve: Option<IdentityResolver>) -> Self {
+    self.identity_resolver = resolve;
+    self
+  }
+
+  /// Whether the nodes and their descendants paired in order refer to the same declarations.
+  /// Nodes of different shapes are left to the syntactic comparison.
+  fn same_identity_within<D: Doc>(&self, bound: &Node<D>, candidate: &Node<D>) -> bool {
+    let bound = bound.dfs();
+    let candidate = candidate.dfs();
+    bound
+      .zip(candidate)
+      .all(|(b, c)| self.same_identity(&b, &c))
+  }
+
+  /// Whether two nodes refer to the same declaration, true if either is not a reference.
+  fn same_identity<D: Doc>(&self, bound: &Node<D>, candidate: &Node<D>) -> bool {
+    let Some(resolve) = self.identity_resolver else {
+      return true;
+    };
+    let bound = resolve(bound.kind_id(), bound.range());
+    let candidate = resolve(candidate.kind_id(), candidate.range());
+    match (bound, candidate) {
+      (Some(b), Some(c)) => b == c,
+      _ => true,
+    }
+  }
+
+  /// Reject a match where the meta variables `$a` and `$b` bind equal nodes, i.e. the constraint `$A != $B`.
+  /// For example, `memcpy($DST, $SRC, $N)` with `distinct("DST", "SRC")` matches `memcpy(a, b, n)`
+  /// but not the self copy `memcpy(a, a, n)`. Nodes are compared like a repeated meta variable, see
+  /// `textual_consistency`. Multi meta variables are equal if they bind equal nodes pairwise.
+  /// The constraint is checked after the pattern matched and holds trivially if either variable is unbound.
+  pub fn distinct(mut self, a: &str, b: &str) -> Self {
+    self.distinct.push((a.to_string(), b.to_string()));
+    self
+  }
+
+  /// Whether the nodes bound in `env` satisfy every `distinct` constraint.
+  pub(crate) fn captures_distinct<D: Doc>(&self, env: &MetaVarEnv<D>) -> bool {
+    self.distinct.iter().all(|(a, b)| {
+      if let (Some(x), Some(y)) = (env.get_match(a), env.get_match(b)) {
+        return !self.bound_node_matches(x, y);
+      }
+      let (xs, ys) = (env.get_multiple_matches(a), env.get_multiple_matches(b));
+      if xs.is_empty() || ys.is_empty() {
+        return true;
+      }
+      let equal = xs.len() == ys.len()
+        && xs
+          .iter()
+          .zip(&ys)
+          .all(|(x, y)| self.bound_node_matches(x, y));
+      !equal
+    })
+  }
+
+  /// Choose how an ellipsis directly followed by another one, e.g. `$$$A` in `foo($$$A, $$$B)`, matches.
+  /// By default adjacent ellipses are merged: the earlier ones match nothing and the last one consumes
+  /// the candidates, so `$$$A` binds `[]` and `$$$B` binds `[a, ,, b]` in `foo(a, b)`.
+  /// With this option each earlier ellipsis consumes exactly one candidate, whatever its kind, and the
+  /// skipped separator is not aligned, so `$$$A` binds `[a]` and `$$$B` binds `[,, b]` in `foo(a, b)`.
+  /// The match fails if no candidate is left for the later ellipses, e.g. `foo($$$, $$$)` against `foo()`.
+  pub fn adjacent_ellipsis_consumes_one(mut self, consume: bool) -> Self {
+    self.adjacent_ellipsis_consumes_one = consume;
+    self
+  }
+
+  /// Bind the whole matched node to the reserved meta variable `$MATCH` after a successful match,
+  /// so a rewrite template can reference the matched snippet. Off by default to keep envs small.
+  /// If the pattern itself uses `$MATCH`, the match fails unless that capture is the matched node.
+  pub fn bind_match(mut self, bind: bool) -> Self {
+    self.bind_match = bind;
+    self
+  }
+
+  /// Binds `matched` to `$MATCH` if `bind_match` is set. Returns None on a conflicting binding.
+  pub(crate) fn insert_match<'t, D: Doc>(
+    &self,
+    matched: &Node<'t, D>,
+    env: &mut Cow<MetaVarEnv<'t, D>>,
+  ) -> Option<()> {
+    if self.bind_match {
+      env.to_mut().insert(MATCH_VAR, matched.clone())?;
+    }
+    Some(())
+  }
+
+  /// Reject a candidate of `parent_kind` whose last significant child is one of `kinds`, a negative
+  /// assertion on the trailing element. For example, with parent kind `arguments` and kinds
+  /// `arrow_function` and `function_expression`, `foo($$$ARGS)` matches calls that do not end with a
+  /// callback: `foo(a, b)` but not `foo(a, () => {})`. Trivia like a trailing comma is not significant.
+  /// The check applies wherever a goal of `parent_kind` matches, even if an ellipsis took the last child.
+  /// Call it again for other parent kinds.
+  pub fn forbid_trailing<I: IntoIterator<Item = u16>>(
+    mut self,
+    parent_kind: u16,
+    kinds: I,
+  ) -> Self {
+    self.forbidden_trailing.retain(|(k, _)| *k != parent_kind);
+    let kinds = kinds.into_iter().collect();
+    self.forbidden_trailing.push((parent_kind, kinds));
+    self
+  }
+
+  /// Whether the last significant child of candidate is allowed, see `forbid_trailing`.
+  pub(super) fn trailing_allowed<D: Doc>(&self, candidate: &Node<D>) -> bool {
+    if self.forbidden_trailing.is_empty() {
+      return true;
+    }
+    let kind_id = candidate.kind_id();
+    let Some((_, kinds)) = self.forbidden_trailing.iter().find(|(k, _)| *k == kind_id) else {
+      return true;
+    };
+    let last = candidate
+      .children()
+      .filter(|n| !self.is_skippable(n))
+      .last();
+    last.map_or(true, |n| !kinds.contains(&n.kind_id()))
+  }
+
+  /// Whether a node equals the one a meta variable is already bound to, see [`does_node_match_exactly`].
+  /// Leaves of `nfc_kinds` and `numeric_kinds` are compared after normalization.
+  pub(super) fn bound_node_matches<D: Doc>(&self, bound: &Node<D>, candidate: &Node<D>) -> bool {
+    if self.identity_resolver.is_some() && !self.same_identity_within(bound, candidate) {
+      return false;
+    }
+    if self.textual_consistency {
+      return bound.text() == candidate.text();
+    }
+    if does_node_match_exactly(bound, candidate) {
+      return true;
+    }
+    if self.nfc_kinds.is_empty() && self.numeric_kinds.is_empty() {
+      return false;
+    }
+    if bound.kind_id() != candidate.kind_id() {
+      return false;
+    }
+    if bound.is_leaf() {
+      let kind_id = bound.kind_id();
+      let bound = self.normalize_leaf(&bound.text(), kind_id).into_owned();
+      return bound == self.normalize_leaf(&candidate.text(), kind_id);
+    }
+    let bound_children = bound.children();
+    let cand_children = candidate.children();
+    bound_children.len() == cand_children.len()
+      && bound_children
+        .zip(cand_children)
+        .all(|(b, c)| self.bound_node_matches(&b, &c))
+  }
+
+  fn within_fuzzy_distance(&self, goal: &str, candidate: &str) -> bool {
+    match self.fuzzy_leaf_distance {
+      Some(max) => within_distance(goal, candidate, max as usize),
+      None => false,
+    }
+  }
+
+  /// Whether the node is of `comment_kinds`.
+  pub(crate) fn is_comment<D: Doc>(&self, node: &Node<D>) -> bool {
+    !self.comment_kinds.is_empty() && self.comment_kinds.contains(&node.kind_id())
+  }
+
+  /// Whether the candidate can be skipped when the goal does not match it.
+  pub(super) fn is_skippable<D: Doc>(&self, candidate: &Node<D>) -> bool {
+    let skippable = candidate.lang().is_trivial(candidate)
+      || self.is_comment(candidate)
+      || self.is_skipped_error(candidate);
+    match self.trivia_override {
+      Some(decide) => decide(candidate.kind_id(), skippable),
+      None => skippable,
+    }
+  }
+
+  /// Leave candidate children assigned to the named fields out of matching, e.g. decoration fields.
+  /// With the `decorator` field of TypeScript ignored, `class A {}` matches `@dec class A {}`.
+  /// Names are resolved to field ids of `lang` once here, names the grammar does not have are dropped.
+  /// Goal children in those fields are still matched, against the remaining candidate children.
+  pub fn ignore_fields<L: Language>(mut self, lang: &L, names: &[&str]) -> Self {
+    let lang = lang.get_ts_language();
+    let ids = names.iter().filter_map(|name| lang.field_id_for_name(name));
+    self.ignored_fields = ids.collect();
+    self
+  }
+
+  /// Children of candidate except those assigned to `ignored_fields`.
+  pub(super) fn aligned_children<'s, 't: 's, D: Doc>(
+    &'s self,
+    candidate: &Node<'t, D>,
+  ) -> impl Iterator<Item = Node<'t, D>> + 's {
+    let root = candidate.root;
+    let mut cursor = candidate.inner.walk();
+    let mut remaining = candidate.inner.child_count();
+    cursor.goto_first_child();
+    std::iter::from_fn(move || loop {
+      if remaining == 0 {
+        return None;
+      }
+      remaining -= 1;
+      let inner = cursor.node();
+      let ignored = !self.ignored_fields.is_empty()
+        && matches!(cursor.field_id(), Some(f) if self.ignored_fields.contains(&f));
+      cursor.goto_next_sibling();
+      if !ignored {
+        return Some(Node { inner, root });
+      }
+    })
+  }
+
+  /// Decide per match call which candidates are skipped like trivia, on top of the language's
+  /// `Language::is_trivial`, `comment_kinds` and `skip_error_nodes`. The function gets the candidate's
+  /// kind id and that default decision and returns whether the candidate is skippable.
+  /// For example, a function returning false for the comment kind and the default otherwise
+  /// makes comments significant for the options it is set on. None keeps the default.
+  pub fn trivia_override(mut self, decide: Option<fn(u16, bool) -> bool>) -> Self {
+    self.trivia_override = decide;
+    self
+  }
+
+  /// Bound how many consecutive trivial candidates, e.g. comments, are skipped to align one goal.
+  /// The match fails once a goal would skip more, instead of scanning a long run of trivia.
+  /// Candidates consumed by an ellipsis are not counted. Unbounded by default.
+  pub fn max_trivia_skip(mut self, max: Option<usize>) -> Self {
+    self.max_trivia_skip = max;
+    self
+  }
+
+  pub(super) fn may_skip_trivia(&self, skipped: usize) -> bool {
+    self.max_trivia_skip.map_or(true, |max| skipped <= max)
+  }
+
+  /// Treat nodes of the given kinds, e.g. string literals, as opaque.
+  /// A goal node of such kind matches any candidate of the same kind without comparing content,
+  /// so `foo("a")` matches `foo("long string")`. Meta variables inside the goal node are not bound.
+  pub fn opaque_kinds<I: IntoIterator<Item = u16>>(mut self, kinds: I) -> Self {
+    self.opaque_kinds = kinds.into_iter().collect();
+    self
+  }
+}
+
+/// The value of a number literal in a canonical form, None if the text is not a number.
+/// Integers are kept exact so that values above 2^53 stay distinct, only fractions and exponents go through `f64`.
+fn normalize_number(text: &str) -> Option<String> {
+  let digits = text.replace('_', "");
+  let lower = digits.to_ascii_lowercase();
+  let radix = |prefix: &str, radix: u32| {
+    let rest = lower.strip_prefix(prefix)?;
+    u128::from_str_radix(rest, radix).ok()
+  };
+  let integer = radix("0x", 16)
+    .or_else(|| radix("0o", 8))
+    .or_else(|| radix("0b", 2));
+  if let Some(value) = integer {
+    return Some(value.to_string());
+  }
+  if !lower.contains(['.', 'e']) {
+    if let Ok(value) = lower.parse::<u128>() {
+      return Some(value.to_string());
+    }
+  }
+  Some(lower.parse::<f64>().ok()?.to_string())
+}
+
+/// Bind the content of a captured block as `{name}.body` if the block is of `block_kinds`.
+/// Returns None if the body conflicts with an existing binding.
+pub(super) fn bind_block_body<'t, D: Doc>(
+  name: &str,
+  block: &Node<'t, D>,
+  env: &mut Cow<MetaVarEnv<'t, D>>,
+  opts: &MatchOptions,
+) -> Option<()> {
+  if opts.block_kinds.is_empty() || !opts.block_kinds.contains(&block.kind_id()) {
+    return Some(());
+  }
+  let mut body: Vec<_> = block.children().collect();
+  // drop delimiters like braces
+  if matches!(body.last(), Some(n) if !n.is_named()) {
+    body.pop();
+  }
+  if matches!(body.first(), Some(n) if !n.is_named()) {
+    body.remove(0);
+  }
+  env.to_mut().insert_multi(&format!("{name}.body"), body)?;
+  Some(())
+}
+
+/// Returns None if goal is not opaque, otherwise returns whether candidate has the same kind.
+pub(super) fn match_opaque<D: Doc>(
+  goal: &Pattern<D::Lang>,
+  candidate: &Node<D>,
+  opts: &MatchOptions,
+) -> Option<bool> {
+  let kind = match goal {
+    Pattern::Terminal { kind_id, .. } | Pattern::Internal { kind_id, .. } => *kind_id,
+    Pattern::MetaVar { .. }
+    | Pattern::Optional(_)
+    | Pattern::Descendant { .. }
+    | Pattern::Repeat(_) => return None,
+  };
+  if !opts.opaque_kinds.contains(&kind) {
+    return None;
+  }
+  Some(opts.kind_matches(kind, candidate.kind_id()))
+}
+
+/// Returns None if cooked comparison does not apply to the goal,
+/// otherwise returns whether goal's cooked value equals candidate's.
+pub(super) fn match_cooked<D: Doc>(
+  goal: &Pattern<D::Lang>,
+  candidate: &Node<D>,
+  opts: &MatchOptions,
+) -> Option<bool> {
+  if opts.cooked_kinds.is_empty() {
+    return None;
+  }
+  let kind = match goal {
+    Pattern::Terminal { kind_id, .. } | Pattern::Internal { kind_id, .. } => *kind_id,
+    Pattern::MetaVar { .. }
+    | Pattern::Optional(_)
+    | Pattern::Descendant { .. }
+    | Pattern::Repeat(_) => return None,
+  };
+  if !opts.cooked_kinds.contains(&kind) {
+    return None;
+  }
+  let mut raw = String::new();
+  concat_terminals(goal, &mut raw)?;
+  if kind != candidate.kind_id() {
+    return Some(false);
+  }
+  Some(cook_escapes(&raw) == cook_escapes(&candidate.text()))
+}
+
+/// Returns None if the pattern contains meta variables.
+fn concat_terminals<L: Language>(goal: &Pattern<L>, text: &mut String) -> Option<()> {
+  match goal {
+    Pattern::Terminal { text: t, .. } => text.push_str(t),
+    Pattern::Internal { children, .. } => {
+      for child in children {
+        concat_terminals(child, text)?;
+      }
+    }
+    Pattern::MetaVar { .. }
+    | Pattern::Optional(_)
+    | Pattern::Descendant { .. }
+    | Pattern::Repeat(_) => return None,
+  }
+  Some(())
+}
+
+/// Whether the Levenshtein distance between the chars of `a` and `b` is at most `max`.
+fn within_distance(a: &str, b: &str, max: usize) -> bool {
+  let a: Vec<_> = a.chars().collect();
+  let b: Vec<_> = b.chars().collect();
+  if a.len().abs_diff(b.len()) > max {
+    return false;
+  }
+  // single row of the edit distance table
+  let mut row: Vec<_> = (0..=b.len()).collect();
+  for (i, ca) in a.iter().enumerate() {
+    let mut diagonal = row[0];
+    row[0] = i + 1;
+    for (j, cb) in b.iter().enumerate() {
+      let substitute = diagonal + usize::from(ca != cb);
+      diagonal = row[j + 1];
+      row[j + 1] = substitute.min(row[j] + 1).min(diagonal + 1);
+    }
+    if row.iter().min().map_or(false, |d| *d > max) {
+      return false;
+    }
+  }
+  row[b.len()] <= max
+}
+
+/// Process escape sequences in the way JavaScript cooks template strings.
+fn cook_escapes(raw: &str) -> Cow<'_, str> {
+  if !raw.contains('\\') {
+    return Cow::Borrowed(raw);
+  }
+  let mut cooked = String::with_capacity(raw.len());
+  let mut chars = raw.chars().peekable();
+  while let Some(c) = chars.next() {
+    if c != '\\' {
+      cooked.push(c);
+      continue;
+    }
+    let Some(escaped) = chars.next() else {
+      cooked.push(c);
+      break;
+    };
+    match escaped {
+      'n' => cooked.push('\n'),
+      't' => cooked.push('\t'),
+      'r' => cooked.push('\r'),
+      'b' => cooked.push('\u{8}'),
+      'f' => cooked.push('\u{c}'),
+      'v' => cooked.push('\u{b}'),
+      '0' => cooked.push('\0'),
+      // line continuation
+      '\n' => (),
+      'x' => {
+        let hex: String = chars.by_ref().take(2).collect();
+        push_code_point(&mut cooked, &hex, 'x');
+      }
+      'u' if chars.peek() == Some(&'{') => {
+        chars.next();
+        let hex: String = chars.by_ref().take_while(|c| *c != '}').collect();
+        push_code_point(&mut cooked, &hex, 'u');
+      }
+      'u' => {
+        let hex: String = chars.by_ref().take(4).collect();
+        push_code_point(&mut cooked, &hex, 'u');
+      }
+      other => cooked.push(other),
+    }
+  }
+  Cow::Owned(cooked)
+}
+
+fn push_code_point(cooked: &mut String, hex: &str, prefix: char) {
+  match u32::from_str_radix(hex, 16).ok().and_then(char::from_u32) {
+    Some(c) => cooked.push(c),
+    // keep invalid escape as is
+    None => {
+      cooked.push('\\');
+      cooked.push(prefix);
+      cooked.push_str(hex);
+    }
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::language::{Language, Tsx};
+  use crate::matcher::Matcher;
+
+  fn template_kind() -> u16 {
+    Tsx
+      .get_ts_language()
+      .id_for_node_kind("template_string", true)
+  }
+
+  fn cooked() -> MatchOptions {
+    MatchOptions::default().cooked_kinds([template_kind()])
+  }
+
+  fn matches(pattern: &str, src: &str, opts: MatchOptions) -> bool {
+    let pattern = Pattern::new(pattern, Tsx).with_options(opts);
+    let grep = Tsx.ast_grep(src);
+    pattern.find_node(grep.root()).is_some()
+  }
+
+  fn opaque() -> MatchOptions {
+    let lang = Tsx.get_ts_language();
+    let kinds = ["string", "template_string"].map(|k| lang.id_for_node_kind(k, true));
+    MatchOptions::default().opaque_kinds(kinds)
+  }
+
+  #[test]
+  fn test_opaque_string() {
+    assert!(!matches("foo('a')", "foo('bbb')", MatchOptions::default()));
+    assert!(matches("foo('a')", "foo('bbb')", opaque()));
+    assert!(matches("foo('a')", "foo(\"\")", opaque()));
+    assert!(matches("foo(`a`)", "foo(`b${c}`)", opaque()));
+    assert!(matches("foo('a', $B)", "foo('bbb', c)", opaque()));
+    // kind must still match
+    assert!(!matches("foo('a')", "foo(`a`)", opaque()));
+    assert!(!matches("foo('a')", "foo(a)", opaque()));
+  }
+
+  fn capture(pattern: &str, src: &str, var: &str, opts: MatchOptions) -> String {
+    let pattern = Pattern::new(pattern, Tsx).with_options(opts);
+    let grep = Tsx.ast_grep(src);
+    let found = pattern.find_node(grep.root()).expect("should match");
+    let env = std::collections::HashMap::from(found.get_env().clone());
+    env[var].clone()
+  }
+
+  #[test]
+  fn test_ellipsis_respects_fields() {
+    let fields = || MatchOptions::default().ellipsis_respects_fields(true);
+    let (pattern, src) = ("if ($C) $$$REST", "if (x) foo(); else bar();");
+    let rest = capture(pattern, src, "REST", MatchOptions::default());
+    assert_eq!(rest, "[foo();, else bar();]");
+    // the else clause is in the alternative field, not consequence
+    assert_eq!(capture(pattern, src, "REST", fields()), "[foo();]");
+    // arguments have no field and are captured as usual
+    let args = capture("foo($$$ARGS)", "foo(a, b)", "ARGS", fields());
+    assert_eq!(args, "[a, ,, b]");
+  }
+
+  fn comments() -> MatchOptions {
+    let kind = Tsx.get_ts_language().id_for_node_kind("comment", true);
+    MatchOptions::default().comment_kinds([kind])
+  }
+
+  #[test]
+  fn test_comment_kinds() {
+    let src = "function f() {\n  // TODO: fix\n  foo(a, /* b */ b)\n}";
+    assert!(!matches("foo(a, b)", src, MatchOptions::default()));
+    assert!(matches("foo(a, b)", src, comments()));
+    let pattern = "function f() { foo($$$) }";
+    assert!(matches(pattern, src, comments()));
+    // comment goal is matched by text
+    let pattern = "function f() {\n  // TODO: fix\n  foo($$$)\n}";
+    assert!(matches(pattern, src, comments()));
+    let pattern = "function f() {\n  // TODO: later\n  foo($$$)\n}";
+    assert!(!matches(pattern, src, comments()));
+    assert!(matches("// TODO: fix", src, comments()));
+    assert!(!matches("// TODO: later", src, comments()));
+  }
+
+  #[test]
+  fn test_cook_escapes() {
+    assert_eq!(cook_escapes("a\\tb"), "a\tb");
+    assert_eq!(cook_escapes("a\\u0009b"), "a\tb");
+    assert_eq!(cook_escapes("a\\u{9}b"), "a\tb");
+    assert_eq!(cook_escapes("a\\x09b"), "a\tb");
+    assert_eq!(cook_escapes("\\`\\$"), "`$");
+    assert_eq!(cook_escapes("\\xZZ"), "\\xZZ");
+  }
+
+  #[test]
+  fn test_raw_template() {
+    assert!(matches("`a\\tb`", "`a\\tb`", MatchOptions::default()));
+    assert!(!matches("`a\\tb`", "`a\tb`", MatchOptions::default()));
+    assert!(!matches("`a\\tb`", "`a\\u0009b`", MatchOptions::default()));
+  }
+
+  #[test]
+  fn test_cooked_template() {
+    assert!(matches("`a\\tb`", "`a\\tb`", cooked()));
+    assert!(matches("`a\\tb`", "`a\tb`", cooked()));
+    assert!(matches("`a\\tb`", "`a\\u0009b`", cooked()));
+    assert!(!matches("`a\\tb`", "`a\\nb`", cooked()));
+    // other string kinds are still compared by raw text
+    assert!(!matches("'a\\tb'", "'a\\u0009b'", cooked()));
+  }
+
+  #[test]
+  fn test_cooked_template_with_meta_var() {
+    assert!(matches("`a\\tb${$A}`", "`a\\tb${c}`", cooked()));
+    assert!(!matches("`a\\tb${$A}`", "`a\tb${c}`", cooked()));
+  }
+
+  #[test]
+  fn test_fuzzy_leaf_distance() {
+    let matches = |src: &str, distance: Option<u32>| {
+      let opts = MatchOptions::default().fuzzy_leaf_distance(distance);
+      let pattern = Pattern::new("foo($A)", Tsx).with_options(opts);
+      pattern.find_node(Tsx.ast_grep(src).root()).is_some()
+    };
+    assert!(matches("fooo(x)", Some(1)));
+    assert!(matches("fo(x)", Some(1)));
+    assert!(!matches("fooo(x)", Some(0)));
+    assert!(!matches("fooo(x)", None));
+    assert!(!matches("foooo(x)", Some(1)));
+    // structure must match exactly
+    assert!(!matches("fooo.bar(x)", Some(1)));
+    assert_eq!(
+      capture(
+        "foo($A)",
+        "fooo(x)",
+        "A",
+        MatchOptions::default().fuzzy_leaf_distance(Some(1))
+      ),
+      "x"
+    );
+  }
+
+  #[test]
+  fn test_nfc_kinds() {
+    let identifier = Tsx.get_ts_language().id_for_node_kind("identifier", true);
+    let string_fragment = Tsx
+      .get_ts_language()
+      .id_for_node_kind("string_fragment", true);
+    let matches = |pattern: &str, src: &str, opts: MatchOptions| {
+      let pattern = Pattern::new(pattern, Tsx).with_options(opts);
+      pattern.find_node(Tsx.ast_grep(src).root()).is_some()
+    };
+    let nfc = || MatchOptions::default().nfc_kinds([identifier]);
+    // composed U+00E9 against decomposed e + U+0301
+    assert!(matches("caf\u{e9}($A)", "cafe\u{301}(x)", nfc()));
+    assert!(matches("cafe\u{301}($A)", "caf\u{e9}(x)", nfc()));
+    assert!(!matches(
+      "caf\u{e9}($A)",
+      "cafe\u{301}(x)",
+      MatchOptions::default()
+    ));
+    // string literals are not identifiers
+    assert!(!matches("f('caf\u{e9}')", "f('cafe\u{301}')", nfc()));
+    let all = MatchOptions::default().nfc_kinds([identifier, string_fragment]);
+    assert!(matches("f('caf\u{e9}')", "f('cafe\u{301}')", all));
+  }
+
+  #[test]
+  fn test_optional_terminators() {
+    let semi = Tsx.get_ts_language().id_for_node_kind(";", false);
+    let matches = |pattern: &str, src: &str, opts: MatchOptions| {
+      let pattern = Pattern::new(pattern, Tsx).with_options(opts);
+      pattern.find_node(Tsx.ast_grep(src).root()).is_some()
+    };
+    let optional = || MatchOptions::default().optional_terminators([semi]);
+    // goal with semicolon, candidate without
+    assert!(!matches(
+      "const a = 1;",
+      "const a = 1",
+      MatchOptions::default()
+    ));
+    assert!(matches("const a = 1;", "const a = 1", optional()));
+    assert!(matches("return $A;", "return 1", optional()));
+    assert!(matches("foo($A);", "foo(a)\nbar()", optional()));
+    // goal without semicolon, candidate with
+    assert!(matches(
+      "const a = 1",
+      "const a = 1;",
+      MatchOptions::default()
+    ));
+    assert!(matches("const a = 1", "const a = 1;", optional()));
+    assert!(matches("const a = 1;", "const a = 1;", optional()));
+    // other children are still compared
+    assert!(!matches("const a = 1;", "const a = 2", optional()));
+    assert!(!matches("for (;;) {}", "for (x;;) {}", optional()));
+  }
+
+  #[test]
+  fn test_max_capture_depth() {
+    let matches = |pattern: Pattern<Tsx>, src: &str, depth: Option<usize>| {
+      let opts = MatchOptions::default().max_capture_depth(depth);
+      let pattern = pattern.with_options(opts);
+      pattern.find_node(Tsx.ast_grep(src).root()).is_some()
+    };
+    let p = |src: &str| Pattern::new(src, Tsx);
+    // operands are direct children of the binary expression
+    assert!(matches(p("$A + $B"), "a + b", Some(1)));
+    // the argument is inside the arguments node
+    assert!(!matches(p("foo($A)"), "foo(a)", Some(1)));
+    assert!(matches(p("foo($A)"), "foo(a)", Some(2)));
+    assert!(matches(p("foo($$$A)"), "foo(a, b)", Some(2)));
+    assert!(!matches(p("foo($$$A)"), "foo(a, b)", Some(1)));
+    // a descendant capture is shallow or deep depending on the candidate
+    let deep = || p("const $F = () => $BODY").with_descendant("BODY", p("bar($X)"), usize::MAX);
+    // $X is 7 levels below the declaration in shallow and 9 in nested
+    let shallow = "const f = () => foo(bar(1))";
+    let nested = "const f = () => { if (a) { bar(1) } }";
+    assert!(matches(deep(), shallow, Some(7)));
+    assert!(!matches(deep(), nested, Some(7)));
+    assert!(matches(deep(), nested, Some(9)));
+    assert!(matches(deep(), nested, None));
+  }
+
+  #[test]
+  fn test_min_named() {
+    let matches = |src: &str, count: usize| {
+      let opts = MatchOptions::default().min_named("BODY", count);
+      let pattern = Pattern::new("if ($C) { $$$BODY }", Tsx).with_options(opts);
+      pattern.find_node(Tsx.ast_grep(src).root()).is_some()
+    };
+    assert!(matches("if (a) { b(); c() }", 2));
+    assert!(matches("if (a) { b(); c(); d() }", 2));
+    assert!(!matches("if (a) { b(); }", 2));
+    assert!(!matches("if (a) {}", 1));
+    assert!(matches("if (a) {}", 0));
+    // separators are not counted
+    let opts = MatchOptions::default().min_named("A", 3);
+    let pattern = Pattern::new("foo($$$A)", Tsx).with_options(opts);
+    assert!(pattern
+      .find_node(Tsx.ast_grep("foo(a, b)").root())
+      .is_none());
+    assert!(pattern
+      .find_node(Tsx.ast_grep("foo(a, b, c)").root())
+      .is_some());
+  }
+
+  #[test]
+  fn test_forbid_trivia_capture() {
+    let forbid = || comments().forbid_trivia_capture(true);
+    let src = "foo(/* a */ b)";
+    assert!(!matches("foo($A)", src, comments()));
+    assert_eq!(capture("foo($A)", src, "A", forbid()), "b");
+    // the comment is not captured even if nothing else can be
+    assert!(!matches("foo($A)", "foo(/* a */)", forbid()));
+    assert!(matches("foo($A)", "foo(/* a */)", MatchOptions::default()));
+    // unnamed meta variables refuse punctuation
+    assert_eq!(capture("return $$A", "return;", "A", comments()), ";");
+    assert!(!matches("return $$A", "return;", forbid()));
+    assert!(!matches("return $$_", "return;", forbid()));
+    assert_eq!(capture("return $$A", "return a;", "A", forbid()), "a");
+  }
+
+  #[test]
+  fn test_skip_error_nodes() {
+    let skip = || MatchOptions::default().skip_error_nodes(true);
+    // `c` is wrapped in an error node after `b`
+    let src = "foo(a, b c)";
+    assert!(!matches("foo($A, $B)", src, MatchOptions::default()));
+    assert_eq!(capture("foo($A, $B)", src, "B", skip()), "b");
+    // `%` is an error node between two commas
+    let src = "foo(a, %, b)";
+    assert_eq!(
+      capture("foo($A, $B, $C)", src, "B", MatchOptions::default()),
+      "%"
+    );
+    assert!(!matches("foo($A, $B, $C)", src, skip()));
+    assert_eq!(capture("foo($A, $B)", src, "B", skip()), "b");
+    // valid code is unaffected
+    assert_eq!(capture("foo($A, $B)", "foo(a, b)", "B", skip()), "b");
+  }
+
+  #[test]
+  fn test_transparent_kinds() {
+    let lang = Tsx.get_ts_language();
+    let kinds = ["statement_block", "expression_statement"].map(|k| lang.id_for_node_kind(k, true));
+    let transparent = || MatchOptions::default().transparent_kinds(kinds);
+    let pattern = "() => foo($A)";
+    let src = "x = () => { foo(1); }";
+    assert!(!matches(pattern, src, MatchOptions::default()));
+    assert_eq!(capture(pattern, src, "A", transparent()), "1");
+    // more than one significant child is not unwrapped
+    assert!(!matches(
+      pattern,
+      "x = () => { foo(1); bar() }",
+      transparent()
+    ));
+    // a meta variable binds the wrapper itself
+    let body = capture("() => $B", src, "B", transparent());
+    assert_eq!(body, "{ foo(1); }");
+    // the wrapper is reported as the matched node
+    let pattern = Pattern::new("foo($A)", Tsx).with_options(transparent());
+    let grep = Tsx.ast_grep("{ foo(1); }");
+    let block = grep.root().child(0).expect("should have block");
+    let found = pattern.match_node(block).expect("should match");
+    assert_eq!(found.text(), "{ foo(1); }");
+    assert_eq!(pattern.get_match_len(found.get_node().clone()), Some(11));
+  }
+
+  #[test]
+  fn test_nested_transparent_kinds() {
+    let lang = Tsx.get_ts_language();
+    let kinds = [
+      "parenthesized_expression",
+      "statement_block",
+      "expression_statement",
+    ];
+    let transparent =
+      || MatchOptions::default().transparent_kinds(kinds.map(|k| lang.id_for_node_kind(k, true)));
+    assert!(!matches("foo(x)", "foo(((x)))", MatchOptions::default()));
+    assert!(matches("foo(x)", "foo(((x)))", transparent()));
+    assert!(matches("foo(x + 1)", "foo(((x + 1)))", transparent()));
+    assert!(!matches("foo(x)", "foo(((y)))", transparent()));
+    // different wrapper kinds nest
+    assert!(matches("() => x", "f = () => { ((x)); }", transparent()));
+    // a wrapper matching the goal kind is not seen through
+    assert!(matches("foo((x))", "foo((x))", transparent()));
+    // wrapping deeper than the guard does not match
+    let wrapped = |depth| format!("foo({}x{})", "(".repeat(depth), ")".repeat(depth));
+    assert!(matches(
+      "foo(x)",
+      &wrapped(MAX_TRANSPARENT_DEPTH),
+      transparent()
+    ));
+    assert!(!matches(
+      "foo(x)",
+      &wrapped(MAX_TRANSPARENT_DEPTH + 1),
+      transparent()
+    ));
+  }
+
+  #[test]
+  fn test_kind_alias() {
+    // pretend a newer grammar renamed call_expression to new_expression
+    let lang = Tsx.get_ts_language();
+    let call = lang.id_for_node_kind("call_expression", true);
+    let new = lang.id_for_node_kind("new_expression", true);
+    let aliased = || MatchOptions::default().kind_alias(call, [new]);
+    let src = "x = new Foo(1)";
+    assert!(!matches("Foo($A)", src, MatchOptions::default()));
+    assert_eq!(capture("Foo($A)", src, "A", aliased()), "1");
+    // aliases are one way
+    let reversed = MatchOptions::default().kind_alias(new, [call]);
+    assert!(!matches("Foo($A)", src, reversed));
+    // the kind prefilter of find_all includes the aliases
+    let pattern = Pattern::new("Foo($A)", Tsx).with_options(aliased());
+    let grep = Tsx.ast_grep(src);
+    let found: Vec<_> = grep.root().find_all(pattern).map(|m| m.text()).collect();
+    assert_eq!(found, ["new Foo(1)"]);
+  }
+
+  #[test]
+  fn test_meta_var_alias() {
+    let opts = || MatchOptions::default().meta_var_alias("Y", "X");
+    let src = "foo(a, a)";
+    assert!(matches("foo($X, $Y)", "foo(a, b)", MatchOptions::default()));
+    assert!(!matches("foo($X, $Y)", "foo(a, b)", opts()));
+    assert!(matches("foo($X, $Y)", src, opts()));
+    // either name looks up the binding
+    let pattern = Pattern::new("foo($X, $Y)", Tsx).with_options(opts());
+    let grep = Tsx.ast_grep(src);
+    let found = grep.root().find(pattern).expect("should match");
+    let env = found.get_env();
+    assert_eq!(env.get_match("X").map(|n| n.text()), Some("a".into()));
+    assert_eq!(env.get_match("Y").map(|n| n.text()), Some("a".into()));
+    // multi captures are merged too
+    let opts = MatchOptions::default().meta_var_alias("B", "A");
+    assert!(matches(
+      "foo([$$$A], [$$$B])",
+      "foo([1, 2], [1, 2])",
+      opts.clone()
+    ));
+    assert!(!matches("foo([$$$A], [$$$B])", "foo([1, 2], [1])", opts));
+  }
+
+  #[test]
+  fn test_associative_operators() {
+    let kind = Tsx
+      .get_ts_language()
+      .id_for_node_kind("binary_expression", true);
+    let flatten = || MatchOptions::default().associative_operators(kind, &["+", "*"]);
+    // `c + a + b` is `(c + a) + b`, there is no `a + b` node
+    assert!(!matches("a + b", "x = c + a + b", MatchOptions::default()));
+    assert!(matches("a + b", "x = c + a + b", flatten()));
+    assert!(matches("b + c", "x = a + b + c + d", flatten()));
+    assert!(matches("a + b + c", "x = a + b + c + d", flatten()));
+    assert!(!matches("a + c", "x = a + b + c", flatten()));
+    // structural matches are kept
+    assert_eq!(capture("$A + $B", "x = c + a + b", "A", flatten()), "c + a");
+    // only chains of one associative operator are flattened
+    assert!(!matches("a - b", "x = c - a - b", flatten()));
+    assert!(!matches("a + b", "x = c * a + b", flatten()));
+    let pattern = Pattern::new("a + b", Tsx).with_options(flatten());
+    let grep = Tsx.ast_grep("x = c + a + b");
+    let found = grep.root().find(&pattern).expect("should match");
+    assert_eq!(found.text(), "c + a + b");
+    assert_eq!(pattern.get_match_len(found.get_node().clone()), Some(9));
+  }
+
+  #[test]
+  fn test_rename_map() {
+    let ident = Tsx.get_ts_language().id_for_node_kind("identifier", true);
+    let renamed = || MatchOptions::default().rename_map([ident], &[("oldName", "newName")]);
+    assert!(!matches("newName()", "oldName()", MatchOptions::default()));
+    assert!(matches("newName()", "oldName()", renamed()));
+    assert!(matches("newName($A)", "x = oldName(oldName)", renamed()));
+    // the old name is gone, other names are kept
+    assert!(!matches("oldName()", "oldName()", renamed()));
+    assert!(matches("other()", "other()", renamed()));
+    // only identifier leaves are renamed
+    assert!(!matches("a.newName()", "a.oldName()", renamed()));
+    assert!(!matches("'newName'", "'oldName'", renamed()));
+  }
+
+  #[test]
+  fn test_kind_class() {
+    let lang = Tsx.get_ts_language();
+    let ident = lang.id_for_node_kind("identifier", true);
+    let prop = lang.id_for_node_kind("property_identifier", true);
+    let class = || MatchOptions::default().kind_class([ident, prop]);
+    fn find(pattern: impl Matcher<Tsx>, src: &str) -> Vec<String> {
+      let grep = Tsx.ast_grep(src);
+      let found = grep.root().find_all(pattern).map(|m| m.text().to_string());
+      found.collect()
+    }
+    // an identifier pattern finds the property
+    let pattern = || Pattern::new("foo", Tsx);
+    assert!(find(pattern(), "a.foo").is_empty());
+    assert_eq!(find(pattern().with_options(class()), "a.foo"), ["foo"]);
+    // and a property pattern finds the identifier
+    let prop_pattern =
+      Pattern::contextual("a.foo", "property_identifier", Tsx).expect("should parse");
+    assert_eq!(find(prop_pattern.with_options(class()), "foo()"), ["foo"]);
+    // the text still has to match
+    assert!(find(Pattern::new("bar", Tsx).with_options(class()), "a.foo").is_empty());
+  }
+
+  fn numbers() -> MatchOptions {
+    let kind = Tsx.get_ts_language().id_for_node_kind("number", true);
+    MatchOptions::default().numeric_kinds([kind])
+  }
+
+  #[test]
+  fn test_numeric_kinds() {
+    assert!(!matches("foo(1)", "foo(0x1)", MatchOptions::default()));
+    assert!(matches("foo(1)", "foo(0x1)", numbers()));
+    assert!(matches("foo(1000)", "foo(1_000)", numbers()));
+    assert!(matches("foo(1)", "foo(1.0)", numbers()));
+    assert!(matches("foo(8)", "foo(0o10)", numbers()));
+    assert!(!matches("foo(2)", "foo(0x1)", numbers()));
+    // strings are not numbers
+    assert!(!matches("foo('1')", "foo('0x1')", numbers()));
+  }
+
+  #[test]
+  fn test_numeric_consistency() {
+    let pattern = "foo($A, $A)";
+    assert!(!matches(pattern, "foo(0x1, 1)", MatchOptions::default()));
+    assert!(matches(pattern, "foo(0x1, 1)", numbers()));
+    assert!(matches(pattern, "foo(a + 1.0, a + 1)", numbers()));
+    assert!(!matches(pattern, "foo(a + 1, b + 1)", numbers()));
+    assert!(!matches(pattern, "foo(0x1, 2)", numbers()));
+    assert!(!matches(
+      pattern,
+      "foo(9007199254740993, 9007199254740992)",
+      numbers()
+    ));
+    assert!(matches(
+      pattern,
+      "foo(9007199254740993, 9_007_199_254_740_993)",
+      numbers()
+    ));
+  }
+
+  #[test]
+  fn test_textual_consistency() {
+    let pattern = "f($A)($A)";
+    let textual = || MatchOptions::default().textual_consistency(true);
+    // structurally equal but formatted differently
+    assert!(matches(pattern, "f(a + b)(a+b)", MatchOptions::default()));
+    assert!(!matches(pattern, "f(a + b)(a+b)", textual()));
+    assert!(matches(pattern, "f(a + b)(a + b)", textual()));
+    assert!(!matches(
+      pattern,
+      "f(a + b)(a - b)",
+      MatchOptions::default()
+    ));
+  }
+
+  #[test]
+  fn test_distinct() {
+    let pattern = "memcpy($DST, $SRC, $N)";
+    let distinct = || MatchOptions::default().distinct("DST", "SRC");
+    // the self copy
+    assert!(matches(pattern, "memcpy(a, a, n)", MatchOptions::default()));
+    assert!(!matches(pattern, "memcpy(a, a, n)", distinct()));
+    assert!(!matches(pattern, "memcpy(p.x, p . x, n)", distinct()));
+    assert!(matches(pattern, "memcpy(a, b, n)", distinct()));
+    assert!(matches(pattern, "memcpy(a, b, a)", distinct()));
+    // multi meta variables
+    let multi = || MatchOptions::default().distinct("A", "B");
+    assert!(!matches("f($$$A)(x, $$$B)", "f(a, b)(x, a, b)", multi()));
+    assert!(matches("f($$$A)(x, $$$B)", "f(a, b)(x, a)", multi()));
+  }
+
+  #[test]
+  fn test_adjacent_ellipsis_consumes_one() {
+    let bind = |src: &str, opts: MatchOptions| {
+      let pattern = Pattern::new("foo($$$A, $$$B)", Tsx).with_options(opts);
+      let grep = Tsx.ast_grep(src);
+      let found = pattern.find_node(grep.root())?;
+      let env = std::collections::HashMap::from(found.get_env().clone());
+      Some((env["A"].clone(), env["B"].clone()))
+    };
+    let one = || MatchOptions::default().adjacent_ellipsis_consumes_one(true);
+    // merged by default
+    let merged = bind("foo(a, b)", MatchOptions::default());
+    assert_eq!(merged, Some(("[]".into(), "[a, ,, b]".into())));
+    // the separator after $$$A is not aligned, so $$$B captures it
+    assert_eq!(
+      bind("foo(a, b)", one()),
+      Some(("[a]".into(), "[,, b]".into()))
+    );
+    assert_eq!(bind("foo(a)", one()), Some(("[a]".into(), "[]".into())));
+    assert!(bind("foo()", MatchOptions::default()).is_some());
+    assert!(bind("foo()", one()).is_none());
+    assert!(!matches("foo($$$, $$$)", "foo()", one()));
+    assert!(matches("foo($$$, $$$, c)", "foo(a, b, c)", one()));
+  }
+
+  #[test]
+  fn test_bind_match() {
+    let find = |pattern: &str, src: &str, bind: bool| {
+      let opts = MatchOptions::default().bind_match(bind);
+      let pattern = Pattern::new(pattern, Tsx).with_options(opts);
+      let grep = Tsx.ast_grep(src);
+      let found = pattern.find_node(grep.root())?;
+      let matched = found.get_env().get_match(MATCH_VAR);
+      Some((
+        found.text().to_string(),
+        matched.map(|n| n.text().to_string()),
+      ))
+    };
+    let (text, matched) = find("foo($A)", "let a = foo(1 + 2)", true).expect("should match");
+    assert_eq!(text, "foo(1 + 2)");
+    assert_eq!(matched.as_deref(), Some("foo(1 + 2)"));
+    // opt in only
+    let (_, matched) = find("foo($A)", "foo(1)", false).expect("should match");
+    assert_eq!(matched, None);
+    // a pattern's own $MATCH must agree
+    assert!(find("foo($MATCH)", "foo(1)", true).is_none());
+    assert!(find("$MATCH", "foo(1)", true).is_some());
+  }
+
+  #[test]
+  fn test_forbid_trailing() {
+    let lang = Tsx.get_ts_language();
+    let kind = |k| lang.id_for_node_kind(k, true);
+    let callbacks = [kind("arrow_function"), kind("function_expression")];
+    let no_callback = || MatchOptions::default().forbid_trailing(kind("arguments"), callbacks);
+    assert!(matches("foo($A, $B)", "foo(a, cb)", no_callback()));
+    assert!(!matches("foo($A, $B)", "foo(a, () => 1)", no_callback()));
+    assert!(!matches(
+      "foo($$$ARGS)",
+      "foo(a, function () {})",
+      no_callback()
+    ));
+    assert!(!matches("foo($$$ARGS)", "foo(a, () => 1,)", no_callback()));
+    assert!(matches("foo($$$ARGS)", "foo(() => 1, a)", no_callback()));
+    assert!(matches("foo($$$ARGS)", "foo()", no_callback()));
+    assert!(matches(
+      "foo($A, $B)",
+      "foo(a, () => 1)",
+      MatchOptions::default()
+    ));
+  }
+
+  #[test]
+  fn test_trivia_override() {
+    fn comment_significant(kind_id: u16, skippable: bool) -> bool {
+      let comment = Tsx.get_ts_language().id_for_node_kind("comment", true);
+      skippable && kind_id != comment
+    }
+    let lang = Tsx.get_ts_language();
+    let comments =
+      || MatchOptions::default().comment_kinds([lang.id_for_node_kind("comment", true)]);
+    let src = "foo(a, /* note */ b)";
+    assert!(matches("foo(a, b)", src, comments()));
+    let significant = comments().trivia_override(Some(comment_significant));
+    assert!(!matches("foo(a, b)", src, significant));
+    // other trivia keep the default
+    let significant = comments().trivia_override(Some(comment_significant));
+    assert!(matches("foo(a, b)", "foo(a, b,)", significant));
+    assert!(matches("foo(a, b)", src, comments().trivia_override(None)));
+  }
+
+  #[test]
+  fn test_max_trivia_skip() {
+    let comment = Tsx.get_ts_language().id_for_node_kind("comment", true);
+    let bounded = |max| {
+      MatchOptions::default()
+        .comment_kinds([comment])
+        .max_trivia_skip(Some(max))
+    };
+    let src = "foo(a, /* 1 */ /* 2 */ b)";
+    assert!(matches("foo(a, b)", src, bounded(2)));
+    assert!(!matches("foo(a, b)", src, bounded(1)));
+    // the bound is per goal, not per node
+    assert!(matches(
+      "foo(a, b)",
+      "foo(/* 1 */ a, /* 2 */ b)",
+      bounded(1)
+    ));
+    // an ellipsis consumes trivia without skipping
+    assert!(matches("foo($$$)", src, bounded(0)));
+    // the end of a match is bounded the same way
+    let match_len = |opts| {
+      let pattern = Pattern::new("foo(a, b)", Tsx).with_options(opts);
+      let grep = Tsx.ast_grep(src);
+      let call = grep.root().find("foo($$$)").expect("should find");
+      pattern.get_match_len(call.get_node().clone())
+    };
+    assert_eq!(match_len(bounded(2)), Some(src.len()));
+    assert_eq!(match_len(bounded(1)), None);
+  }
+
+  #[test]
+  fn test_ignore_fields() {
+    let decorators = || MatchOptions::default().ignore_fields(&Tsx, &["decorator"]);
+    assert!(!matches(
+      "class A {}",
+      "@dec class A {}",
+      MatchOptions::default()
+    ));
+    assert!(matches("class A {}", "@dec class A {}", decorators()));
+    assert!(matches("class $C {}", "@a @b class A {}", decorators()));
+    assert!(!matches("class B {}", "@dec class A {}", decorators()));
+    // unknown names are dropped
+    let unknown = MatchOptions::default().ignore_fields(&Tsx, &["no_such_field"]);
+    assert!(!matches("class A {}", "@dec class A {}", unknown));
+  }
+
+  #[test]
+  fn test_normalize_number() {
+    assert_eq!(normalize_number("0x1F").as_deref(), Some("31"));
+    assert_eq!(normalize_number("0b101").as_deref(), Some("5"));
+    assert_eq!(normalize_number("1e3").as_deref(), Some("1000"));
+    assert_eq!(normalize_number("1.50").as_deref(), Some("1.5"));
+    assert_eq!(normalize_number("abc"), None);
+    // integers above 2^53 are not rounded
+    assert_eq!(
+      normalize_number("9007199254740993").as_deref(),
+      Some("9007199254740993")
+    );
+    assert_eq!(
+      normalize_number("0x20000000000001").as_deref(),
+      Some("9007199254740993")
+    );
+  }
+
+  #[test]
+  #[cfg(feature = "globset")]
+  fn test_text_glob() {
+    let glob = |name: &str, glob: &str| MatchOptions::default().text_glob(name, glob).unwrap();
+    let pattern = "function $F() {}";
+    let src = "function helper() {} function test_add() {}";
+    assert_eq!(capture(pattern, src, "F", glob("F", "test_*")), "test_add");
+    assert!(!matches(pattern, src, glob("F", "spec_*")));
+    let src = "function add_test() {} function helper() {}";
+    assert_eq!(capture(pattern, src, "F", glob("F", "*_test")), "add_test");
+    assert!(!matches(pattern, src, glob("F", "*_spec")));
+    // other variables are not constrained
+    assert_eq!(capture(pattern, src, "F", glob("G", "*_spec")), "add_test");
+    let err = MatchOptions::default().text_glob("F", "[unclosed");
+    assert!(matches!(err, Err(PatternError::InvalidGlob { .. })));
+  }
+
+  #[test]
+  fn test_string_quote() {
+    let calls = |quote| {
+      let opts = MatchOptions::default().string_quote("S", quote);
+      let pattern = Pattern::new("foo($S)", Tsx).with_options(opts);
+      let grep = Tsx.ast_grep("foo('a'); foo(\"b\"); foo(c); foo(\"x\" + 'y'); foo(`d`)");
+      let found = grep.root().find_all(&pattern);
+      found.map(|m| m.text().to_string()).collect::<Vec<_>>()
+    };
+    assert_eq!(calls('"'), ["foo(\"b\")"]);
+    assert_eq!(calls('\''), ["foo('a')"]);
+    assert_eq!(calls('`'), ["foo(`d`)"]);
+    // other variables are not constrained
+    let opts = MatchOptions::default().string_quote("T", '"');
+    assert_eq!(capture("foo($S)", "foo('a')", "S", opts), "'a'");
+  }
+
+  const SHADOWED: &str = "let x = 0;\nrun(x, function (x) { return x });";
+
+  // a stub resolver: identifiers in the function refer to its parameter, others to the outer `let`
+  fn resolve_shadowed(kind_id: u16, range: Range<usize>) -> Option<usize> {
+    let identifier = Tsx.get_ts_language().id_for_node_kind("identifier", true);
+    let function = SHADOWED.find("function").expect("should have function");
+    (kind_id == identifier).then_some(usize::from(range.start > function))
+  }
+
+  #[test]
+  fn test_identity_resolver() {
+    let identity = || MatchOptions::default().identity_resolver(Some(resolve_shadowed));
+    let outer = "run($A, function ($P) { return $A })";
+    assert!(matches(outer, SHADOWED, MatchOptions::default()));
+    assert!(!matches(outer, SHADOWED, identity()));
+    let param = "run($A, function ($P) { return $P })";
+    assert_eq!(capture(param, SHADOWED, "P", identity()), "x");
+    // non-references are compared syntactically
+    let src = "run(1, function (x) { return 1 })";
+    assert!(matches(
+      "run($A, function ($P) { return $A })",
+      src,
+      identity()
+    ));
+  }
+
+  #[test]
+  fn test_within_distance() {
+    assert!(within_distance("kitten", "sitting", 3));
+    assert!(!within_distance("kitten", "sitting", 2));
+    assert!(within_distance("", "ab", 2));
+    assert!(within_distance("añb", "ab", 1));
+  }
+
+  #[test]
+  fn test_exact_children() {
+    let matches = |pattern: &str, src: &str, exact: bool| {
+      let opts = MatchOptions::default().exact_children(exact);
+      let pattern = Pattern::new(pattern, Tsx).with_options(opts);
+      pattern.find_node(Tsx.ast_grep(src).root()).is_some()
+    };
+    assert!(matches("let $A = $B", "let a = 1, b", false));
+    assert!(!matches("let $A = $B", "let a = 1, b", true));
+    assert!(matches("let $A = $B", "let a = 1", true));
+    assert!(matches("if ($A) $B", "if (a) b; else c", false));
+    assert!(!matches("if ($A) $B", "if (a) b; else c", true));
+    assert!(matches("let $A = $B, $$$", "let a = 1, b", true));
+    // a closing token already forbids extra arguments
+    assert!(!matches("foo($A)", "foo(a, b)", false));
+    assert!(!matches("foo($A)", "foo(a, b)", true));
+  }
+
+  #[test]
+  fn test_block_kinds() {
+    let block = Tsx
+      .get_ts_language()
+      .id_for_node_kind("statement_block", true);
+    let pattern = "const $F = () => $BODY";
+    let src = "const foo = () => { a(); // c\n b() }";
+    let opts = MatchOptions::default().block_kinds([block]);
+    let pattern = Pattern::new(pattern, Tsx).with_options(opts);
+    let grep = Tsx.ast_grep(src);
+    let found = pattern.find_node(grep.root()).expect("should match");
+    let env = found.get_env();
+    let body = env.get_match("BODY").expect("should capture block");
+    assert_eq!(body.kind(), "statement_block");
+    assert_eq!(body.range(), 18..src.len());
+    let inner = env.get_multiple_matches("BODY.body");
+    let inner: Vec<_> = inner.iter().map(|n| n.text()).collect();
+    assert_eq!(inner, ["a();", "// c", "b()"]);
+    // only blocks of the given kinds
+    assert!(env.get_labels("F.body").is_none());
+    let plain = Pattern::new("const $F = () => $BODY", Tsx);
+    let found = plain.find_node(grep.root()).expect("should match");
+    assert!(found.get_env().get_labels("BODY.body").is_none());
+  }
+}
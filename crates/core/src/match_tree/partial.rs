@@ -0,0 +1,199 @@
+use super::trace::describe_goal;
+use super::{match_node_non_recursive, try_get_ellipsis_mode, MatchOptions};
+use crate::meta_var::{rollback_env, MetaVarEnv, MetaVariable};
+use crate::{Doc, Language, Node, Pattern};
+
+use std::borrow::Cow;
+
+/// How much of a goal a candidate matches, see [`match_partial`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PartialMatch {
+  /// number of goal leaves matched
+  pub matched: usize,
+  /// number of goal leaves, i.e. meta variables and terminals other than punctuation
+  pub total: usize,
+  /// the outermost sub-goals that did not match, described like `MatchTrace::goal`
+  pub failed: Vec<String>,
+}
+
+impl PartialMatch {
+  /// The fraction of goal leaves matched, 1.0 for a goal without leaves.
+  pub fn fraction(&self) -> f64 {
+    if self.total == 0 {
+      1.0
+    } else {
+      self.matched as f64 / self.total as f64
+    }
+  }
+  /// Whether every goal leaf matched.
+  pub fn is_complete(&self) -> bool {
+    self.matched == self.total
+  }
+}
+
+/// Scores how completely candidate matches goal instead of stopping at the first failure.
+/// A sub-goal that matches as a whole scores all its leaves. An internal goal of the candidate's kind
+/// that does not is scored child by child: non-punctuation goal children are aligned in order
+/// with the candidate's named children, an ellipsis skips to the first candidate its next goal matches.
+/// Other sub-goals fail with all their leaves. Ellipses, optional goals and repeated groups are not scored.
+/// Use it to rank candidate locations by closeness, see [`PartialMatch::fraction`].
+pub fn match_partial<D: Doc>(goal: &Pattern<D::Lang>, candidate: Node<D>) -> PartialMatch {
+  let mut partial = PartialMatch {
+    matched: 0,
+    total: 0,
+    failed: vec![],
+  };
+  let lang = candidate.lang().clone();
+  let mut scorer = Scorer {
+    env: Cow::Owned(MetaVarEnv::new()),
+    opts: MatchOptions::default(),
+    lang: &lang,
+  };
+  scorer.score(goal, Some(candidate), &mut partial);
+  partial
+}
+
+struct Scorer<'t, 'l, D: Doc> {
+  env: Cow<'l, MetaVarEnv<'t, D>>,
+  opts: MatchOptions,
+  lang: &'l D::Lang,
+}
+
+impl<'t, 'l, D: Doc> Scorer<'t, 'l, D> {
+  fn score(&mut self, goal: &Pattern<D::Lang>, cand: Option<Node<'t, D>>, out: &mut PartialMatch) {
+    let leaves = count_leaves(goal);
+    if leaves == 0 {
+      return;
+    }
+    if let Some(cand) = &cand {
+      if self.matches(goal, cand) {
+        out.matched += leaves;
+        out.total += leaves;
+        return;
+      }
+      if let Pattern::Internal {
+        kind_id, children, ..
+      } = goal
+      {
+        if self.opts.kind_matches(*kind_id, cand.kind_id()) {
+          self.score_children(children, cand, out);
+          return;
+        }
+      }
+    }
+    out.total += leaves;
+    out.failed.push(describe_goal(goal, self.lang));
+  }
+
+  fn score_children(
+    &mut self,
+    goals: &[Pattern<D::Lang>],
+    cand: &Node<'t, D>,
+    out: &mut PartialMatch,
+  ) {
+    let goals: Vec<_> = goals.iter().filter(|g| !g.is_trivial()).collect();
+    let cands: Vec<_> = cand
+      .children()
+      .filter(|c| !self.opts.is_skippable(c) || c.field_id_in_parent().is_some())
+      .collect();
+    let mut next = 0;
+    for (i, goal) in goals.iter().enumerate() {
+      if try_get_ellipsis_mode(goal).is_ok() {
+        let rest = &goals[i + 1..];
+        let Some(following) = rest.first() else {
+          next = cands.len();
+          continue;
+        };
+        // leave enough candidates for the rest if the following goal matches none
+        let found = (next..cands.len()).find(|&j| self.dry_run(following, &cands[j]));
+        let aligned_to_end = cands.len().saturating_sub(rest.len()).max(next);
+        next = found.unwrap_or(aligned_to_end);
+        continue;
+      }
+      if matches!(goal, Pattern::Optional(_) | Pattern::Repeat(_)) {
+        if next < cands.len() && self.dry_run(goal, &cands[next]) {
+          next += 1;
+        }
+        continue;
+      }
+      self.score(goal, cands.get(next).cloned(), out);
+      next += 1;
+    }
+  }
+
+  /// Matches and keeps the bindings, so later goals see them.
+  fn matches(&mut self, goal: &Pattern<D::Lang>, cand: &Node<'t, D>) -> bool {
+    let checkpoint = self.env.checkpoint();
+    let matched = match_node_non_recursive(goal, cand.clone(), &mut self.env, &self.opts, None);
+    if matched.is_none() {
+      rollback_env(&mut self.env, checkpoint);
+    }
+    matched.is_some()
+  }
+
+  fn dry_run(&mut self, goal: &Pattern<D::Lang>, cand: &Node<'t, D>) -> bool {
+    let checkpoint = self.env.checkpoint();
+    let matched = match_node_non_recursive(goal, cand.clone(), &mut self.env, &self.opts, None);
+    rollback_env(&mut self.env, checkpoint);
+    matched.is_some()
+  }
+}
+
+fn count_leaves<L: Language>(goal: &Pattern<L>) -> usize {
+  match goal {
+    Pattern::Terminal { .. } => usize::from(!goal.is_trivial()),
+    Pattern::MetaVar { meta_var, .. } => usize::from(!matches!(
+      meta_var,
+      MetaVariable::Multiple
+        | MetaVariable::MultiCapture(_)
+        | MetaVariable::LineContiguousCapture(_)
+    )),
+    Pattern::Internal { children, .. } => children.iter().map(count_leaves).sum(),
+    Pattern::Descendant { .. } => 1,
+    Pattern::Optional(_) | Pattern::Repeat(_) => 0,
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::language::{Language, Tsx};
+
+  fn partial(pattern: &str, src: &str) -> PartialMatch {
+    let goal = Pattern::new(pattern, Tsx);
+    let grep = Tsx.ast_grep(src);
+    let cand = grep.root().find("foo($$$)").expect("should find call");
+    match_partial(&goal, cand.get_node().clone())
+  }
+
+  #[test]
+  fn test_near_miss_score() {
+    let found = partial("foo($A, 2)", "foo(x, 3)");
+    assert_eq!((found.matched, found.total), (2, 3));
+    assert_eq!(found.failed, ["`2`"]);
+    assert!(!found.is_complete());
+    assert!((found.fraction() - 2.0 / 3.0).abs() < f64::EPSILON);
+  }
+
+  #[test]
+  fn test_complete_match() {
+    let found = partial("foo($A, $$$)", "foo(x, 3)");
+    assert_eq!((found.matched, found.total), (2, 2));
+    assert!(found.is_complete() && found.failed.is_empty());
+  }
+
+  #[test]
+  fn test_every_failure_reported() {
+    // scoring goes on after the first failure, and bindings are kept across goals
+    let found = partial("foo($A, 1, $A, bar(2))", "foo(x, 2, y, bar(2))");
+    assert_eq!((found.matched, found.total), (4, 6));
+    assert_eq!(found.failed, ["`1`", "$A"]);
+    // an ellipsis skips to the next goal that matches
+    let found = partial("foo($$$, 1, 2)", "foo(a, b, 1, 3)");
+    assert_eq!((found.matched, found.total), (2, 3));
+    // missing and mismatched kinds fail as a whole
+    let found = partial("foo(a.b, c)", "foo(d)");
+    assert_eq!((found.matched, found.total), (1, 4));
+    assert_eq!(found.failed, ["member_expression", "`c`"]);
+  }
+}
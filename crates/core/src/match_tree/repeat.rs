@@ -0,0 +1,160 @@
+use super::{match_end_non_recursive, match_node_non_recursive, MatchOptions, Validator};
+use crate::meta_var::MetaVarEnv;
+use crate::{Doc, Node, Pattern};
+
+use std::borrow::Cow;
+
+/// Matches [`Pattern::Repeat`] as many times as possible from the start of `cands`.
+/// Captures of every repetition are appended to lists, bound in env as multi meta variables.
+/// Returns the number of candidates consumed, zero if the group does not match at all.
+pub(super) fn match_repeat<'tree, D: Doc>(
+  group: &[Pattern<D::Lang>],
+  cands: &[Node<'tree, D>],
+  env: &mut Cow<MetaVarEnv<'tree, D>>,
+  opts: &MatchOptions,
+  validator: Validator<'_, 'tree, D>,
+) -> Option<usize> {
+  let mut captured: Vec<_> = group
+    .iter()
+    .flat_map(Pattern::meta_var_names)
+    .map(|name| (name, vec![]))
+    .collect();
+  let mut consumed = 0;
+  loop {
+    // group meta variables are local to the repetition
+    let mut local = Cow::Owned(MetaVarEnv::new());
+    let matched = match_group_once(group, &cands[consumed..], opts, |goal, cand| {
+      match_node_non_recursive(goal, cand, &mut local, opts, validator).is_some()
+    });
+    let Some(len) = matched else {
+      break;
+    };
+    for (name, nodes) in &mut captured {
+      nodes.extend(local.get_match(name).cloned());
+      nodes.extend(local.get_multiple_matches(name));
+    }
+    consumed += len;
+  }
+  for (name, nodes) in captured {
+    env.to_mut().insert_multi(&name, nodes)?;
+  }
+  Some(consumed)
+}
+
+/// Like [`match_repeat`] but only counts the candidates consumed, for `match_end_non_recursive`.
+pub(super) fn repeat_len<D: Doc>(
+  group: &[Pattern<D::Lang>],
+  cands: &[Node<D>],
+  opts: &MatchOptions,
+) -> usize {
+  let mut consumed = 0;
+  while let Some(len) = match_group_once(group, &cands[consumed..], opts, |goal, cand| {
+    match_end_non_recursive(goal, cand, opts).is_some()
+  }) {
+    consumed += len;
+  }
+  consumed
+}
+
+/// Matches the group goals in order against the start of `cands`, skipping candidates that can be skipped,
+/// e.g. the comma separating repetitions. Returns the number of candidates consumed, None if a goal fails.
+fn match_group_once<'t, D: Doc>(
+  group: &[Pattern<D::Lang>],
+  cands: &[Node<'t, D>],
+  opts: &MatchOptions,
+  mut matches: impl FnMut(&Pattern<D::Lang>, Node<'t, D>) -> bool,
+) -> Option<usize> {
+  // an empty group would repeat forever
+  if group.is_empty() {
+    return None;
+  }
+  let mut consumed = 0;
+  for goal in group {
+    loop {
+      let cand = cands.get(consumed)?;
+      consumed += 1;
+      if matches(goal, cand.clone()) {
+        break;
+      }
+      if !opts.is_skippable(cand) {
+        return None;
+      }
+    }
+  }
+  Some(consumed)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::language::{Language, Tsx};
+  use crate::Matcher;
+  use std::collections::HashMap;
+
+  fn pair_repeat() -> Pattern<Tsx> {
+    let pair = Pattern::contextual("({ $A: $B })", "pair", Tsx).expect("should parse pair");
+    Pattern::new("({ $$$PAIRS })", Tsx).with_repeat("PAIRS", vec![pair])
+  }
+
+  fn bindings(pattern: &Pattern<Tsx>, src: &str) -> Option<HashMap<String, String>> {
+    let grep = Tsx.ast_grep(src);
+    let found = pattern.find_node(grep.root())?;
+    Some(HashMap::from(found.get_env().clone()))
+  }
+
+  #[test]
+  fn test_repeated_pairs() {
+    let env = bindings(&pair_repeat(), "x = ({ a: 1, b: 2, c: 3 })").expect("should match");
+    assert_eq!(env["A"], "[a, b, c]");
+    assert_eq!(env["B"], "[1, 2, 3]");
+    // trailing comma is skipped
+    let env = bindings(&pair_repeat(), "x = ({ a: 1, b: 2, })").expect("should match");
+    assert_eq!(env["A"], "[a, b]");
+    let env = bindings(&pair_repeat(), "x = ({})").expect("should match");
+    assert_eq!(env["A"], "[]");
+    assert_eq!(env["B"], "[]");
+  }
+
+  #[test]
+  fn test_repeat_stops_at_mismatch() {
+    // a spread element is not a pair, nothing can consume it
+    assert!(bindings(&pair_repeat(), "x = ({ a: 1, ...b })").is_none());
+    let pair = Pattern::contextual("({ $A: $B })", "pair", Tsx).expect("should parse pair");
+    let pattern = Pattern::new("({ $$$PAIRS, ...$S })", Tsx).with_repeat("PAIRS", vec![pair]);
+    let env = bindings(&pattern, "x = ({ a: 1, b: 2, ...c })").expect("should match");
+    assert_eq!(env["A"], "[a, b]");
+    assert_eq!(env["S"], "c");
+  }
+
+  #[test]
+  fn test_repeated_sequence() {
+    // two sibling goals per repetition
+    let group = Pattern::new("f($K, $V)", Tsx);
+    let Pattern::Internal { children, .. } = group else {
+      panic!("call should be internal");
+    };
+    let Some(Pattern::Internal { children: args, .. }) = children.into_iter().nth(1) else {
+      panic!("arguments should be internal");
+    };
+    // `(`, $K, `,`, $V, `)` without the parentheses
+    let group = args[1..args.len() - 1].to_vec();
+    let pattern = Pattern::new("pairs($$$KV)", Tsx).with_repeat("KV", group);
+    let env = bindings(&pattern, "pairs(a, 1, b, 2)").expect("should match");
+    assert_eq!(env["K"], "[a, b]");
+    assert_eq!(env["V"], "[1, 2]");
+    // an odd argument is left over
+    assert!(bindings(&pattern, "pairs(a, 1, b)").is_none());
+  }
+
+  #[test]
+  fn test_meta_vars() {
+    let vars = pair_repeat().meta_vars();
+    assert_eq!(
+      vars,
+      [
+        crate::meta_var::MetaVariable::MultiCapture("A".into()),
+        crate::meta_var::MetaVariable::MultiCapture("B".into()),
+      ]
+    );
+  }
+}
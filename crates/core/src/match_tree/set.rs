@@ -0,0 +1,323 @@
+use super::{
+  match_node_non_recursive, match_nodes_non_recursive, try_get_ellipsis_mode, MatchOptions,
+  Validator,
+};
+use crate::meta_var::{rollback_env, MetaVarEnv};
+use crate::{Doc, Node, Pattern};
+
+use std::borrow::Cow;
+
+/// Returns None if set matching does not apply to the goal, otherwise returns whether
+/// goal's named children match candidate's named children as a set.
+/// Each goal child must match a distinct candidate child, in any order.
+/// For exhaustive kinds, unmatched candidate children are only allowed if goal has an ellipsis.
+/// A named ellipsis captures them in source order.
+pub(super) fn match_set<'tree, D: Doc>(
+  goal: &Pattern<D::Lang>,
+  candidate: &Node<'tree, D>,
+  env: &mut Cow<MetaVarEnv<'tree, D>>,
+  opts: &MatchOptions,
+  validator: Validator<'_, 'tree, D>,
+) -> Option<bool> {
+  let Pattern::Internal {
+    kind_id, children, ..
+  } = goal
+  else {
+    return None;
+  };
+  let exhaustive = opts.exhaustive_set_kinds.contains(kind_id);
+  if !exhaustive && !opts.unordered_kinds.contains(kind_id) {
+    return None;
+  }
+  if *kind_id != candidate.kind_id() {
+    return Some(false);
+  }
+  let mut goals = vec![];
+  let mut rest = None;
+  for child in children {
+    if let Ok(name) = try_get_ellipsis_mode(child) {
+      rest = Some(name);
+    } else if !unwrap_optional(child).is_trivial() {
+      goals.push(child);
+    }
+  }
+  let cands: Vec<_> = candidate.children().filter(|n| n.is_named()).collect();
+  let allow_extra = rest.is_some() || !exhaustive;
+  if !counts_match(&goals, cands.len(), allow_extra) {
+    return Some(false);
+  }
+  let checkpoint = env.checkpoint();
+  let mut used = vec![false; cands.len()];
+  if !assign(&goals, &cands, &mut used, allow_extra, env, opts, validator) {
+    return Some(false);
+  }
+  if let Some(Some(name)) = rest {
+    let unused = cands
+      .into_iter()
+      .zip(used)
+      .filter_map(|(n, used)| (!used).then_some(n))
+      .collect();
+    if env.to_mut().insert_multi(&name, unused).is_none() {
+      rollback_env(env, checkpoint);
+      return Some(false);
+    }
+  }
+  Some(true)
+}
+
+/// Returns None if the goal is not an element of `unordered_attributes`,
+/// otherwise returns whether goal's attributes match candidate's as a set and the other children match in order.
+pub(super) fn match_attributes<'tree, D: Doc>(
+  goal: &Pattern<D::Lang>,
+  candidate: &Node<'tree, D>,
+  env: &mut Cow<MetaVarEnv<'tree, D>>,
+  opts: &MatchOptions,
+  validator: Validator<'_, 'tree, D>,
+) -> Option<bool> {
+  let Pattern::Internal {
+    kind_id, children, ..
+  } = goal
+  else {
+    return None;
+  };
+  if !opts.attribute_element_kinds.contains(kind_id) {
+    return None;
+  }
+  if *kind_id != candidate.kind_id() {
+    return Some(false);
+  }
+  let is_attribute = |kind: u16| opts.attribute_kinds.contains(&kind);
+  let mut goals = vec![];
+  let mut positional = vec![];
+  let mut rest = None;
+  for child in children {
+    match unwrap_optional(child) {
+      Pattern::Internal { kind_id, .. } | Pattern::Terminal { kind_id, .. }
+        if is_attribute(*kind_id) =>
+      {
+        goals.push(child)
+      }
+      _ => match try_get_ellipsis_mode(child) {
+        Ok(name) => rest = Some(name),
+        Err(_) => positional.push(child),
+      },
+    }
+  }
+  let (cands, others): (Vec<_>, Vec<_>) = candidate
+    .children()
+    .partition(|n| is_attribute(n.kind_id()));
+  let allow_extra = rest.is_some() || !opts.exact_attributes;
+  if !counts_match(&goals, cands.len(), allow_extra) {
+    return Some(false);
+  }
+  let checkpoint = env.checkpoint();
+  if !positional.is_empty()
+    && match_nodes_non_recursive(positional, others.into_iter(), env, opts, validator).is_none()
+  {
+    rollback_env(env, checkpoint);
+    return Some(false);
+  }
+  let mut used = vec![false; cands.len()];
+  if !assign(&goals, &cands, &mut used, allow_extra, env, opts, validator) {
+    rollback_env(env, checkpoint);
+    return Some(false);
+  }
+  if let Some(Some(name)) = rest {
+    let unused = cands
+      .into_iter()
+      .zip(used)
+      .filter_map(|(n, used)| (!used).then_some(n))
+      .collect();
+    if env.to_mut().insert_multi(&name, unused).is_none() {
+      rollback_env(env, checkpoint);
+      return Some(false);
+    }
+  }
+  Some(true)
+}
+
+fn unwrap_optional<L: crate::Language>(goal: &Pattern<L>) -> &Pattern<L> {
+  match goal {
+    Pattern::Optional(p) => p,
+    p => p,
+  }
+}
+
+/// Whether the number of candidates can satisfy the goals. Optional goals may match no candidate.
+fn counts_match<L: crate::Language>(
+  goals: &[&Pattern<L>],
+  cands: usize,
+  allow_extra: bool,
+) -> bool {
+  let required = goals
+    .iter()
+    .filter(|g| !matches!(g, Pattern::Optional(_)))
+    .count();
+  required <= cands && (allow_extra || cands <= goals.len())
+}
+
+/// Backtrack to assign every goal a distinct candidate. Bindings of failed tries are rolled back.
+/// An optional goal is skipped if no candidate is left for it.
+/// Unless `allow_extra`, every candidate must be assigned.
+#[allow(clippy::too_many_arguments)]
+fn assign<'tree, D: Doc>(
+  goals: &[&Pattern<D::Lang>],
+  cands: &[Node<'tree, D>],
+  used: &mut [bool],
+  allow_extra: bool,
+  env: &mut Cow<MetaVarEnv<'tree, D>>,
+  opts: &MatchOptions,
+  validator: Validator<'_, 'tree, D>,
+) -> bool {
+  let Some((goal, rest)) = goals.split_first() else {
+    return allow_extra || used.iter().all(|u| *u);
+  };
+  for (i, cand) in cands.iter().enumerate() {
+    if used[i] {
+      continue;
+    }
+    let checkpoint = env.checkpoint();
+    if match_node_non_recursive(goal, cand.clone(), env, opts, validator).is_some() {
+      used[i] = true;
+      if assign(rest, cands, used, allow_extra, env, opts, validator) {
+        return true;
+      }
+      used[i] = false;
+    }
+    rollback_env(env, checkpoint);
+  }
+  matches!(goal, Pattern::Optional(_))
+    && assign(rest, cands, used, allow_extra, env, opts, validator)
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::language::{Language, Tsx};
+  use crate::matcher::Matcher;
+
+  fn exhaustive() -> MatchOptions {
+    let lang = Tsx.get_ts_language();
+    let kinds = ["object", "jsx_self_closing_element"].map(|k| lang.id_for_node_kind(k, true));
+    MatchOptions::default().exhaustive_set_kinds(kinds)
+  }
+
+  fn unordered() -> MatchOptions {
+    let lang = Tsx.get_ts_language();
+    let kinds = ["object", "jsx_self_closing_element"].map(|k| lang.id_for_node_kind(k, true));
+    MatchOptions::default().unordered_kinds(kinds)
+  }
+
+  fn attributes(exact: bool) -> MatchOptions {
+    let lang = Tsx.get_ts_language();
+    let elements = ["jsx_self_closing_element", "jsx_opening_element"];
+    let elements = elements.map(|k| lang.id_for_node_kind(k, true));
+    let attributes = ["jsx_attribute", "jsx_expression"].map(|k| lang.id_for_node_kind(k, true));
+    MatchOptions::default()
+      .unordered_attributes(elements, attributes)
+      .exact_attributes(exact)
+  }
+
+  fn find_with(
+    pattern: &str,
+    src: &str,
+    opts: MatchOptions,
+  ) -> Option<std::collections::HashMap<String, String>> {
+    let pattern = Pattern::new(pattern, Tsx).with_options(opts);
+    let grep = Tsx.ast_grep(src);
+    let found = pattern.find_node(grep.root())?;
+    Some(found.get_env().clone().into())
+  }
+
+  fn find(pattern: &str, src: &str) -> Option<std::collections::HashMap<String, String>> {
+    find_with(pattern, src, exhaustive())
+  }
+
+  #[test]
+  fn test_exact_keys() {
+    let env = find("a = { a: $A, b: $B }", "a = { a: 1, b: 2 }").expect("should match");
+    assert_eq!(env["A"], "1");
+    assert_eq!(env["B"], "2");
+    let env = find("a = { a: $A, b: $B }", "a = { b: 2, a: 1 }").expect("should match");
+    assert_eq!(env["A"], "1");
+    assert!(find("a = { a: $A, b: $B }", "a = { a: 1, b: 2, c: 3 }").is_none());
+    assert!(find("a = { a: $A, b: $B }", "a = { a: 1 }").is_none());
+    assert!(find("a = { a: $A, b: $B }", "a = { a: 1, c: 2 }").is_none());
+  }
+
+  #[test]
+  fn test_backtrack_assignment() {
+    // $K: 1 would take a: 1 first, leaving no candidate for a: $V
+    let env = find("x = { $K: 1, a: $V }", "x = { a: 1, b: 1 }").expect("should match");
+    assert_eq!(env["K"], "b");
+    assert_eq!(env["V"], "1");
+    assert!(find("x = { $K: $V, $K: $V }", "x = { a: 1, b: 1 }").is_none());
+  }
+
+  #[test]
+  fn test_ellipsis_allows_extra() {
+    assert!(find("a = { a: $A, $$$ }", "a = { b: 2, a: 1, c: 3 }").is_some());
+    let env = find("a = { a: $A, $$$REST }", "a = { b: 2, a: 1, c: 3 }").expect("should match");
+    assert_eq!(env["REST"], "[b: 2, c: 3]");
+  }
+
+  #[test]
+  fn test_jsx_attributes() {
+    assert!(find("<A b={1} c='d' />", "<A c='d' b={1} />").is_some());
+    assert!(find("<A b={1} />", "<A c='d' b={1} />").is_none());
+    assert!(find("<A b={1} />", "<B b={1} />").is_none());
+  }
+
+  #[test]
+  fn test_unordered() {
+    let pattern = "x = { a: $X, b: $Y }";
+    for src in ["x = { a: 1, b: 2 }", "x = { b: 2, a: 1 }"] {
+      let env = find_with(pattern, src, unordered()).expect("should match");
+      assert_eq!(env["X"], "1");
+      assert_eq!(env["Y"], "2");
+    }
+    // positional matching fails on reordered properties
+    assert!(find_with(pattern, "x = { b: 2, a: 1 }", MatchOptions::default()).is_none());
+    // extra property is allowed in unordered mode but not in exhaustive mode
+    let extra = "x = { c: 3, b: 2, a: 1 }";
+    assert!(find_with(pattern, extra, unordered()).is_some());
+    assert!(find_with(pattern, extra, exhaustive()).is_none());
+    assert!(find_with(pattern, "x = { a: 1, c: 2 }", unordered()).is_none());
+  }
+
+  #[test]
+  fn test_reordered_attributes() {
+    let pattern = "<A b={$B} c='d' />";
+    let env = find_with(pattern, "<A c='d' b={1} />", attributes(false)).expect("should match");
+    assert_eq!(env["B"], "1");
+    assert!(find_with(pattern, "<A c='d' b={1} />", MatchOptions::default()).is_none());
+    let env = find_with(
+      "<div b={$B} c='d'>text</div>",
+      "<div c='d' b={1}>text</div>",
+      attributes(true),
+    )
+    .expect("should match opening element");
+    assert_eq!(env["B"], "1");
+    assert!(find_with(pattern, "<A c='e' b={1} />", attributes(false)).is_none());
+    assert!(find_with(pattern, "<A b={1} />", attributes(false)).is_none());
+  }
+
+  #[test]
+  fn test_extra_attributes() {
+    let pattern = "<A b={$B} />";
+    let src = "<A c='d' b={1} {...props} />";
+    let env = find_with(pattern, src, attributes(false)).expect("should match");
+    assert_eq!(env["B"], "1");
+    assert!(find_with(pattern, src, attributes(true)).is_none());
+    let env = find_with("<A b={$B} $$$REST />", src, attributes(true)).expect("should match");
+    assert_eq!(env["REST"], "[c='d', {...props}]");
+  }
+
+  #[test]
+  fn test_element_name_stays_positional() {
+    let env =
+      find_with("<$T b={1} />", "<A c='d' b={1} />", attributes(false)).expect("should match");
+    assert_eq!(env["T"], "A");
+    assert!(find_with("<B b={1} />", "<A b={1} />", attributes(false)).is_none());
+  }
+}
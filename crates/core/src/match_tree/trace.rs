@@ -0,0 +1,434 @@
+use crate::meta_var::MetaVariable;
+use crate::{Doc, Language, Node, Pattern};
+
+use std::fmt;
+
+#[cfg(feature = "trace")]
+pub(super) use enabled::{enter, exit, is_enabled};
+#[cfg(feature = "trace")]
+pub use enabled::{near_misses, trace_match, NearMiss};
+
+/// A nested record of the decisions made when matching one goal node against one candidate.
+/// Every nested comparison, e.g. a goal child against a candidate child, is a `Child` event.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MatchTrace {
+  /// `$A`-like meta variable, terminal text or the kind of an internal goal node
+  pub goal: String,
+  /// the candidate's kind and text
+  pub candidate: String,
+  pub events: Vec<TraceEvent>,
+  pub matched: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TraceEvent {
+  /// goal and candidate have different kinds
+  KindMismatch,
+  /// terminal text is compared
+  TextCompared { equal: bool },
+  /// result is decided by a MatchOptions mode
+  Option { name: &'static str },
+  /// candidate has fewer children than the goal requires
+  TooFewChildren,
+  /// candidate's leading children cannot match the goal's first child
+  FirstKindMismatch,
+  /// unnamed candidate is skipped because the goal did not match it
+  SkipTrivial { candidate: String },
+  /// ellipsis consumes one candidate
+  EllipsisConsume { candidate: String },
+  /// ellipsis at the end consumes all remaining candidates
+  EllipsisRest,
+  /// meta variable is bound
+  Bind { name: String, text: String },
+  /// meta variable is already bound to different text
+  BindConflict { name: String },
+  /// a constraint of the meta variable, e.g. a validator, text glob or string quote, rejects the candidate
+  Rejected { name: String },
+  /// named meta variable does not match an unnamed candidate
+  UnnamedCandidate,
+  /// bindings made by the failed attempt are undone
+  Rollback,
+  /// optional goal does not match the candidate and is skipped
+  OptionalAbsent,
+  /// a nested comparison
+  Child(MatchTrace),
+}
+
+/// Record an event in the current frame. `event` is only evaluated when tracing.
+#[cfg(feature = "trace")]
+#[inline]
+pub(super) fn record(event: impl FnOnce() -> TraceEvent) {
+  if is_enabled() {
+    enabled::push(event());
+  }
+}
+
+// Without the `trace` feature nothing is recorded and the checks compile out of matching.
+#[cfg(not(feature = "trace"))]
+#[inline(always)]
+pub(super) fn is_enabled() -> bool {
+  false
+}
+
+#[cfg(not(feature = "trace"))]
+#[inline(always)]
+pub(super) fn record(_event: impl FnOnce() -> TraceEvent) {}
+
+pub(super) fn describe_node<D: Doc>(node: &Node<D>) -> String {
+  format!("{} `{}`", node.kind(), node.text())
+}
+
+pub(super) fn describe_goal<L: Language>(goal: &Pattern<L>, lang: &L) -> String {
+  match goal {
+    Pattern::Terminal { text, .. } => format!("`{text}`"),
+    Pattern::Internal { kind_id, .. } => {
+      let ts_lang = lang.get_ts_language();
+      let kind = ts_lang.node_kind_for_id(*kind_id);
+      kind.map(|k| k.to_string()).unwrap_or_default()
+    }
+    Pattern::Optional(p) => format!("{}?", describe_goal(p, lang)),
+    Pattern::MetaVar { meta_var, .. } => describe_meta_var(meta_var),
+    Pattern::Descendant {
+      meta_var, inner, ..
+    } => {
+      let inner = describe_goal(inner, lang);
+      format!("{} <<< {inner}", describe_meta_var(meta_var))
+    }
+    Pattern::Repeat(group) => {
+      let group: Vec<_> = group.iter().map(|g| describe_goal(g, lang)).collect();
+      format!("({})*", group.join(" "))
+    }
+  }
+}
+
+fn describe_meta_var(meta_var: &MetaVariable) -> String {
+  match meta_var {
+    MetaVariable::Capture(name, true) => format!("${name}"),
+    MetaVariable::Capture(name, false) => format!("$${name}"),
+    MetaVariable::Dropped(true) => "$_".into(),
+    MetaVariable::Dropped(false) => "$$_".into(),
+    MetaVariable::Multiple => "$$$".into(),
+    MetaVariable::MultiCapture(name) => format!("$$${name}"),
+    MetaVariable::LineContiguousCapture(name) => format!("$$$${name}"),
+  }
+}
+
+#[cfg(feature = "trace")]
+mod enabled {
+  use super::super::{match_node_non_recursive, MatchOptions};
+  use super::{describe_goal, describe_node, MatchTrace, TraceEvent};
+  use crate::meta_var::MetaVarEnv;
+  use crate::{Doc, Node, Pattern};
+
+  use std::borrow::Cow;
+  use std::cell::{Cell, RefCell};
+
+  thread_local! {
+    static ENABLED: Cell<bool> = const { Cell::new(false) };
+    pub(super) static FRAMES: RefCell<Vec<MatchTrace>> = const { RefCell::new(Vec::new()) };
+  }
+
+  /// Match goal against candidate once, like `match_node_non_recursive`, and record every decision.
+  pub fn trace_match<D: Doc>(goal: &Pattern<D::Lang>, candidate: Node<D>) -> MatchTrace {
+    let _guard = TraceGuard::start();
+    let mut env = Cow::Owned(MetaVarEnv::new());
+    match_node_non_recursive(goal, candidate, &mut env, &MatchOptions::default(), None);
+    let mut root = FRAMES.with(|f| f.borrow_mut().pop()).expect("root frame");
+    match root.events.pop() {
+      Some(TraceEvent::Child(trace)) => trace,
+      _ => unreachable!("match attempt must record one frame"),
+    }
+  }
+
+  /// A candidate of the same kind as the goal that failed to match deeper, see [`near_misses`].
+  #[derive(Clone)]
+  pub struct NearMiss<'t, D: Doc> {
+    pub node: Node<'t, D>,
+    pub trace: MatchTrace,
+  }
+
+  impl<'t, D: Doc> NearMiss<'t, D> {
+    /// The innermost goal whose comparison failed, i.e. the first failing sub-goal.
+    /// It is the goal itself if all its children matched, e.g. the candidate has extra children.
+    pub fn failure(&self) -> &MatchTrace {
+      self.trace.innermost_failure()
+    }
+    /// Number of goal nodes matched before the failure, the higher the closer.
+    pub fn score(&self) -> usize {
+      self.trace.matched_count()
+    }
+  }
+
+  /// Traces every node under `root`, in pre-order, whose kind is the same as the goal's but which does not match.
+  /// Returns them ranked by [`NearMiss::score`], nodes of equal score in the order they are found.
+  /// A meta variable goal has no kind, so it has no near miss.
+  pub fn near_misses<'t, D: Doc>(
+    goal: &Pattern<D::Lang>,
+    root: Node<'t, D>,
+  ) -> Vec<NearMiss<'t, D>> {
+    let kind = match goal {
+      Pattern::Terminal { kind_id, .. } | Pattern::Internal { kind_id, .. } => *kind_id,
+      Pattern::MetaVar { .. }
+      | Pattern::Optional(_)
+      | Pattern::Descendant { .. }
+      | Pattern::Repeat(_) => return vec![],
+    };
+    let mut found: Vec<_> = root
+      .dfs()
+      .filter(|n| n.kind_id() == kind)
+      .filter_map(|node| {
+        let trace = trace_match(goal, node.clone());
+        (!trace.matched).then_some(NearMiss { node, trace })
+      })
+      .collect();
+    found.sort_by_key(|miss| std::cmp::Reverse(miss.score()));
+    found
+  }
+
+  /// Resets tracing state even if matching panics.
+  struct TraceGuard;
+  impl TraceGuard {
+    fn start() -> Self {
+      ENABLED.with(|e| e.set(true));
+      FRAMES.with(|f| {
+        let root = MatchTrace {
+          goal: String::new(),
+          candidate: String::new(),
+          events: vec![],
+          matched: false,
+        };
+        *f.borrow_mut() = vec![root];
+      });
+      Self
+    }
+  }
+  impl Drop for TraceGuard {
+    fn drop(&mut self) {
+      ENABLED.with(|e| e.set(false));
+      FRAMES.with(|f| f.borrow_mut().clear());
+    }
+  }
+
+  #[inline]
+  pub fn is_enabled() -> bool {
+    ENABLED.with(Cell::get)
+  }
+
+  pub fn push(event: TraceEvent) {
+    FRAMES.with(|f| {
+      if let Some(frame) = f.borrow_mut().last_mut() {
+        frame.events.push(event);
+      }
+    });
+  }
+
+  pub fn enter<D: Doc>(goal: &Pattern<D::Lang>, candidate: &Node<D>) {
+    let frame = MatchTrace {
+      goal: describe_goal(goal, candidate.lang()),
+      candidate: describe_node(candidate),
+      events: vec![],
+      matched: false,
+    };
+    FRAMES.with(|f| f.borrow_mut().push(frame));
+  }
+
+  pub fn exit(matched: bool) {
+    FRAMES.with(|f| {
+      let mut frames = f.borrow_mut();
+      let Some(mut frame) = frames.pop() else {
+        return;
+      };
+      frame.matched = matched;
+      if let Some(parent) = frames.last_mut() {
+        parent.events.push(TraceEvent::Child(frame));
+      }
+    });
+  }
+}
+
+#[cfg(feature = "trace")]
+impl MatchTrace {
+  fn children(&self) -> impl Iterator<Item = &MatchTrace> {
+    self.events.iter().filter_map(|e| match e {
+      TraceEvent::Child(trace) => Some(trace),
+      _ => None,
+    })
+  }
+
+  /// Follows the last failed comparison down to the innermost one.
+  /// Matching stops at the goal that fails, so earlier failed comparisons,
+  /// e.g. skipped trivial candidates, did not cause the failure.
+  fn innermost_failure(&self) -> &MatchTrace {
+    match self.children().last() {
+      Some(child) if !child.matched => child.innermost_failure(),
+      _ => self,
+    }
+  }
+
+  fn matched_count(&self) -> usize {
+    self
+      .children()
+      .map(|c| if c.matched { 1 } else { c.matched_count() })
+      .sum()
+  }
+}
+
+impl MatchTrace {
+  fn fmt_indented(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+    let indent = "  ".repeat(depth);
+    let result = if self.matched { "matched" } else { "failed" };
+    writeln!(f, "{indent}{} vs {}: {result}", self.goal, self.candidate)?;
+    for event in &self.events {
+      if let TraceEvent::Child(child) = event {
+        child.fmt_indented(f, depth + 1)?;
+      } else {
+        writeln!(f, "{indent}  {event}")?;
+      }
+    }
+    Ok(())
+  }
+}
+
+impl fmt::Display for MatchTrace {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    self.fmt_indented(f, 0)
+  }
+}
+
+impl fmt::Display for TraceEvent {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    use TraceEvent as E;
+    match self {
+      E::KindMismatch => write!(f, "kind mismatch"),
+      E::TextCompared { equal: true } => write!(f, "text equal"),
+      E::TextCompared { equal: false } => write!(f, "text differs"),
+      E::Option { name } => write!(f, "decided by {name} option"),
+      E::TooFewChildren => write!(f, "too few children"),
+      E::FirstKindMismatch => write!(f, "first child kind mismatch"),
+      E::SkipTrivial { candidate } => write!(f, "skip {candidate}"),
+      E::EllipsisConsume { candidate } => write!(f, "ellipsis consumes {candidate}"),
+      E::EllipsisRest => write!(f, "ellipsis consumes the rest"),
+      E::Bind { name, text } => write!(f, "bind {name} = `{text}`"),
+      E::BindConflict { name } => write!(f, "{name} conflicts with its binding"),
+      E::Rejected { name } => write!(f, "constraint of {name} rejects the candidate"),
+      E::UnnamedCandidate => write!(f, "named meta variable rejects unnamed node"),
+      E::Rollback => write!(f, "rollback bindings"),
+      E::OptionalAbsent => write!(f, "optional goal is absent"),
+      E::Child(trace) => write!(f, "{trace}"),
+    }
+  }
+}
+
+#[cfg(all(test, feature = "trace"))]
+mod test {
+  use super::enabled::FRAMES;
+  use super::*;
+  use crate::language::Tsx;
+
+  fn trace(pattern: &str, src: &str) -> MatchTrace {
+    let goal = Pattern::new(pattern, Tsx);
+    let grep = Tsx.ast_grep(src);
+    let cand = grep.root().find("foo($$$)").expect("should find call");
+    trace_match(&goal, cand.get_node().clone())
+  }
+
+  #[test]
+  fn test_trace_match() {
+    let trace = trace("foo($A, $$$B)", "foo(a, b, c)");
+    assert!(trace.matched);
+    let rendered = trace.to_string();
+    let expected = "\
+call_expression vs call_expression `foo(a, b, c)`: matched
+  `foo` vs identifier `foo`: matched
+    text equal
+  arguments vs arguments `(a, b, c)`: matched
+    `(` vs ( `(`: matched
+      text equal
+    $A vs identifier `a`: matched
+      bind $A = `a`
+    `,` vs , `,`: matched
+      text equal
+    ellipsis consumes the rest
+    bind $$$B = `b , c`
+";
+    assert_eq!(rendered, expected);
+  }
+
+  #[test]
+  fn test_trace_failure() {
+    let trace = trace("foo($A, $B)", "foo()");
+    assert!(!trace.matched);
+    let arguments = trace.events.iter().find_map(|e| match e {
+      TraceEvent::Child(t) if t.goal == "arguments" => Some(t),
+      _ => None,
+    });
+    let arguments = arguments.expect("should trace arguments");
+    assert_eq!(arguments.events, [TraceEvent::TooFewChildren]);
+    let trace = self::trace("bar($A)", "foo(a)");
+    assert!(trace.to_string().contains("text differs"));
+  }
+
+  #[test]
+  fn test_trace_conflict() {
+    let trace = trace("foo($A, $A)", "foo(a, b)");
+    assert!(!trace.matched);
+    let rendered = trace.to_string();
+    assert!(rendered.contains("bind $A = `a`"), "{rendered}");
+    assert!(
+      rendered.contains("$A conflicts with its binding"),
+      "{rendered}"
+    );
+    assert!(rendered.contains("rollback bindings"), "{rendered}");
+  }
+
+  #[test]
+  fn test_trace_skip_and_consume() {
+    let rendered = trace("foo($$$A, c)", "foo(a, b, c)").to_string();
+    assert!(
+      rendered.contains("ellipsis consumes identifier `a`"),
+      "{rendered}"
+    );
+    assert!(
+      rendered.contains("ellipsis consumes identifier `b`"),
+      "{rendered}"
+    );
+    assert!(rendered.contains("bind $$$A = `a , b`"), "{rendered}");
+    let rendered = trace("foo(a, b)", "foo(a, b)").to_string();
+    assert!(!rendered.contains("skip"), "{rendered}");
+    let rendered = trace("foo(a b)", "foo(a, b)").to_string();
+    assert!(rendered.contains("skip , `,`"), "{rendered}");
+  }
+
+  #[test]
+  fn test_near_misses() {
+    let goal = Pattern::new("foo($A, 1)", Tsx);
+    let src = "bar(a, 1); foo(b, 2); foo(c, 1); foo(d); foo(e, 1, 3)";
+    let grep = Tsx.ast_grep(src);
+    let misses = near_misses(&goal, grep.root());
+    let found: Vec<_> = misses
+      .iter()
+      .map(|m| (m.node.text().to_string(), m.failure().goal.clone()))
+      .collect();
+    assert_eq!(
+      found,
+      [
+        // `)` is compared with the extra `,`
+        ("foo(e, 1, 3)".to_string(), "`)`".to_string()),
+        ("foo(b, 2)".into(), "`1`".into()),
+        ("foo(d)".into(), "arguments".into()),
+        ("bar(a, 1)".into(), "`foo`".into()),
+      ]
+    );
+    assert!(misses[0].score() > misses[3].score());
+    assert_eq!(misses[2].failure().events, [TraceEvent::TooFewChildren]);
+    let goal = Pattern::new("$A", Tsx);
+    assert!(near_misses(&goal, grep.root()).is_empty());
+  }
+
+  #[test]
+  fn test_tracing_is_off_after_trace() {
+    trace("foo($A)", "foo(a)");
+    assert!(!is_enabled());
+    FRAMES.with(|f| assert!(f.borrow().is_empty()));
+  }
+}
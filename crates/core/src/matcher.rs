@@ -4,23 +4,51 @@
 //! * Pattern: matches against a tree-sitter node based on its tree structure.
 //! * KindMatcher: matches a node based on its `kind`
 //! * RegexMatcher: matches a node based on its textual content using regex.
+//! * CommentMatcher: matches a sub-pattern against the words of a comment node.
+//!
+//! `Pattern::from_pair` synthesizes a pattern from two example snippets.
 
+mod anti_unify;
+mod comment;
+mod incremental;
 mod kind;
+mod kind_index;
+mod literal;
 mod node_match;
+#[cfg(feature = "rayon")]
+mod parallel;
 mod pattern;
+mod pattern_cache;
+mod runner;
+#[cfg(feature = "serde")]
+mod serialize;
 #[cfg(feature = "regex")]
 mod text;
 
 use crate::meta_var::MetaVarEnv;
-use crate::traversal::Pre;
+use crate::traversal::{Pre, Traversal};
 use crate::{Doc, Language, Node};
 
 use bit_set::BitSet;
 use std::borrow::Cow;
 
+#[cfg(feature = "trace")]
+pub use crate::match_tree::NearMiss;
+pub use crate::match_tree::{
+  does_node_match_exactly_explain, match_node_list, match_partial, nodes_equal_with_options,
+  nodes_structurally_equal, BacktrackLimitExceeded, EqualityOptions, ExactMismatch,
+  IdentityResolver, MatchOptions, MatchTrace, PartialMatch, TraceEvent, MATCH_VAR,
+  MAX_TRANSPARENT_DEPTH,
+};
+pub use comment::CommentMatcher;
+pub use incremental::MatchCache;
 pub use kind::{KindMatcher, KindMatcherError};
+pub use kind_index::KindIndex;
 pub use node_match::NodeMatch;
-pub use pattern::{Pattern, PatternError};
+pub(crate) use pattern::capture_counts;
+pub use pattern::{ConfiguredPattern, Pattern, PatternError};
+pub use pattern_cache::PATTERN_CACHE_CAPACITY;
+pub use runner::PatternRunner;
 #[cfg(feature = "regex")]
 pub use text::{RegexMatcher, RegexMatcherError};
 
@@ -129,13 +157,72 @@ pub struct FindAllNodes<'tree, D: Doc, M: Matcher<D::Lang>> {
   // e.g. for pattern Some($A) with replacement $A, Some(Some(1)) will cause panic
   dfs: Pre<'tree, D>,
   matcher: M,
+  /// number of nodes taken from dfs so far
+  visited: usize,
+  /// stop after visiting this many nodes, None for no limit
+  max_visited: Option<usize>,
+  /// kinds whose descendants are not visited, the nodes themselves still are
+  stop_kinds: BitSet,
+  /// decides whether a node and its descendants are visited
+  should_enter: Option<EnterFn<'tree, D>>,
+  /// kinds present in each subtree, to skip subtrees without potential kinds
+  kind_index: Option<&'tree KindIndex>,
 }
 
+type EnterFn<'tree, D> = Box<dyn Fn(&Node<'tree, D>) -> bool + 'tree>;
+
 impl<'tree, D: Doc, M: Matcher<D::Lang>> FindAllNodes<'tree, D, M> {
   pub fn new(matcher: M, node: Node<'tree, D>) -> Self {
     Self {
       dfs: node.dfs(),
       matcher,
+      visited: 0,
+      max_visited: None,
+      stop_kinds: BitSet::new(),
+      should_enter: None,
+      kind_index: None,
+    }
+  }
+
+  /// Does not descend into nodes of the given kinds below the starting node, e.g. nested functions,
+  /// so only matches reachable without crossing them are found. The nodes themselves can still match.
+  pub fn stop_descent_at_kinds<I: IntoIterator<Item = u16>>(mut self, kinds: I) -> Self {
+    self.stop_kinds.extend(kinds.into_iter().map(usize::from));
+    self
+  }
+
+  /// Consults `should_enter` before visiting each node, the starting node included. A node it
+  /// rejects is neither matched nor descended into, so callers can prune regions decided at walk time,
+  /// e.g. every node after a `// @generated` marker.
+  pub fn should_enter<F>(mut self, should_enter: F) -> Self
+  where
+    F: Fn(&Node<'tree, D>) -> bool + 'tree,
+  {
+    self.should_enter = Some(Box::new(should_enter));
+    self
+  }
+
+  /// Skips the subtrees that `index` shows have no node of the matcher's `potential_kinds`,
+  /// instead of visiting each of their nodes. See [`KindIndex`].
+  pub fn kind_index(mut self, index: &'tree KindIndex) -> Self {
+    self.kind_index = Some(index);
+    self
+  }
+
+  /// Stops the search after visiting `max` nodes, counting nodes skipped by `potential_kinds`.
+  pub fn max_nodes_visited(self, max: Option<usize>) -> Self {
+    Self {
+      max_visited: max,
+      ..self
+    }
+  }
+
+  /// Whether the search stopped because the budget ran out before the tree was fully visited.
+  /// Call it after the iterator returns None, since it peeks at the next node of the traversal.
+  pub fn budget_exceeded(&mut self) -> bool {
+    match self.max_visited {
+      Some(max) => self.visited >= max && self.dfs.next().is_some(),
+      None => false,
     }
   }
 }
@@ -144,7 +231,29 @@ impl<'tree, D: Doc, M: Matcher<D::Lang>> Iterator for FindAllNodes<'tree, D, M>
   type Item = NodeMatch<'tree, D>;
   fn next(&mut self) -> Option<Self::Item> {
     let kinds = self.matcher.potential_kinds();
-    for cand in self.dfs.by_ref() {
+    loop {
+      if matches!(self.max_visited, Some(max) if self.visited >= max) {
+        return None;
+      }
+      let depth = self.dfs.get_current_depth();
+      let cand = self.dfs.next()?;
+      self.visited += 1;
+      if matches!(&self.should_enter, Some(enter) if !enter(&cand)) {
+        self.dfs.calibrate_for_match(Some(depth));
+        continue;
+      }
+      if let (Some(index), Some(k)) = (self.kind_index, &kinds) {
+        if !index.may_contain(&cand, k) {
+          self.dfs.calibrate_for_match(Some(depth));
+          continue;
+        }
+      }
+      // most searches stop nowhere, skip looking up the kind for them
+      let stops = !self.stop_kinds.is_empty();
+      if depth > 0 && stops && self.stop_kinds.contains(cand.kind_id().into()) {
+        // skip the children the traversal has just stepped into
+        self.dfs.calibrate_for_match(Some(depth));
+      }
       if let Some(k) = &kinds {
         if !k.contains(cand.kind_id().into()) {
           continue;
@@ -154,7 +263,30 @@ impl<'tree, D: Doc, M: Matcher<D::Lang>> Iterator for FindAllNodes<'tree, D, M>
         return Some(matched);
       }
     }
-    None
+  }
+}
+
+/// Matches found by [`Node::find_all_with_budget`].
+pub enum FindAllResult<'tree, D: Doc> {
+  /// The whole tree was visited.
+  Complete(Vec<NodeMatch<'tree, D>>),
+  /// The budget ran out, these are the matches found before it.
+  Partial(Vec<NodeMatch<'tree, D>>),
+}
+
+impl<'tree, D: Doc> FindAllResult<'tree, D> {
+  pub fn is_partial(&self) -> bool {
+    matches!(self, Self::Partial(_))
+  }
+  pub fn matches(&self) -> &[NodeMatch<'tree, D>] {
+    match self {
+      Self::Complete(m) | Self::Partial(m) => m,
+    }
+  }
+  pub fn into_matches(self) -> Vec<NodeMatch<'tree, D>> {
+    match self {
+      Self::Complete(m) | Self::Partial(m) => m,
+    }
   }
 }
 
@@ -0,0 +1,147 @@
+use super::pattern::{convert_node_to_pattern, first_kind_of, is_single_node};
+use super::{Pattern, PatternError};
+use crate::match_tree::does_node_match_exactly;
+use crate::meta_var::MetaVariable;
+use crate::{Doc, Language, Node, Root, StrDoc};
+
+/// Divergent pairs seen so far, by the texts of both sides and the meta variable bound to them.
+type Divergences = Vec<(String, String, String)>;
+
+impl<L: Language> Pattern<L> {
+  /// Synthesizes the most specific pattern matching both snippets by anti-unification:
+  /// structure the snippets share is kept and every point where they diverge becomes a meta variable,
+  /// so `foo(1)` and `foo(2)` give `foo($A)`. Subtrees diverge if they are of different kinds or
+  /// have different numbers of children. Divergences with the same texts on both sides share one
+  /// meta variable, so `foo(1, 1)` and `foo(2, 2)` give `foo($A, $A)`. Meta variables are named
+  /// `A` to `Z`, then `A1`, `B1` and so on. A divergence in unnamed nodes, e.g. `+` and `-`, becomes `$$A`.
+  /// Snippets are plain code and must be single nodes like the source of [`Pattern::try_new`].
+  pub fn from_pair(a: &str, b: &str, lang: L) -> Result<Self, PatternError> {
+    let a_root = parse_single(a, lang.clone())?;
+    let b_root = parse_single(b, lang)?;
+    let a = Self::single_matcher(&a_root);
+    let b = Self::single_matcher(&b_root);
+    let lang = a.lang().clone();
+    Ok(anti_unify(a, b, None, &mut vec![]).with_root_lang(lang))
+  }
+}
+
+fn parse_single<L: Language>(src: &str, lang: L) -> Result<Root<StrDoc<L>>, PatternError> {
+  let root = Root::<StrDoc<L>>::try_new(src, lang)?;
+  let node = root.root();
+  if node.inner.child_count() == 0 {
+    return Err(PatternError::NoContent(src.into()));
+  }
+  if !is_single_node(&node.inner) {
+    return Err(PatternError::MultipleNode(src.into()));
+  }
+  Ok(root)
+}
+
+fn anti_unify<D: Doc>(
+  a: Node<D>,
+  b: Node<D>,
+  field_id: Option<u16>,
+  seen: &mut Divergences,
+) -> Pattern<D::Lang> {
+  if does_node_match_exactly(&a, &b) {
+    return convert_node_to_pattern(a, None, field_id);
+  }
+  let child_count = a.inner.child_count();
+  if a.kind_id() != b.kind_id() || a.is_leaf() || child_count != b.inner.child_count() {
+    let meta_var = generalize(&a, &b, seen);
+    return Pattern::MetaVar {
+      meta_var,
+      kind: None,
+    };
+  }
+  // both sides have the same shape, field ids are read from `a`
+  let mut cursor = a.inner.walk();
+  cursor.goto_first_child();
+  let mut children = Vec::with_capacity(child_count as usize);
+  for b_child in b.children() {
+    let a_child = Node {
+      inner: cursor.node(),
+      root: a.root,
+    };
+    children.push(anti_unify(a_child, b_child, cursor.field_id(), seen));
+    cursor.goto_next_sibling();
+  }
+  let first_kind = first_kind_of(&children);
+  Pattern::Internal {
+    kind_id: a.kind_id(),
+    children,
+    first_kind,
+    lang: None,
+  }
+}
+
+fn generalize<D: Doc>(a: &Node<D>, b: &Node<D>, seen: &mut Divergences) -> MetaVariable {
+  let (a_text, b_text) = (a.text(), b.text());
+  let found = seen.iter().find(|(x, y, _)| *x == a_text && *y == b_text);
+  let name = match found {
+    Some((_, _, name)) => name.clone(),
+    None => {
+      let name = var_name(seen.len());
+      seen.push((a_text.into(), b_text.into(), name.clone()));
+      name
+    }
+  };
+  MetaVariable::Capture(name, a.is_named() && b.is_named())
+}
+
+fn var_name(index: usize) -> String {
+  let letter = char::from(b'A' + (index % 26) as u8);
+  match index / 26 {
+    0 => letter.to_string(),
+    round => format!("{letter}{round}"),
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::language::Tsx;
+
+  fn synthesize(a: &str, b: &str) -> Pattern<Tsx> {
+    Pattern::from_pair(a, b, Tsx).expect("should synthesize")
+  }
+
+  fn matches(pattern: &Pattern<Tsx>, src: &str) -> bool {
+    let grep = Tsx.ast_grep(src);
+    let found = grep.root().find(pattern);
+    found.map_or(false, |m| m.text() == src)
+  }
+
+  #[test]
+  fn test_from_pair() {
+    let pattern = synthesize("foo(1)", "foo(2)");
+    assert_eq!(pattern.meta_var_names(), ["A"]);
+    let expected = Pattern::new("foo($A)", Tsx);
+    assert_eq!(format!("{pattern:?}"), format!("{expected:?}"));
+    assert!(matches(&pattern, "foo(1)"));
+    assert!(matches(&pattern, "foo(2)"));
+    assert!(matches(&pattern, "foo(bar + 1)"));
+    assert!(!matches(&pattern, "bar(1)"));
+    assert!(!matches(&pattern, "foo(1, 2)"));
+  }
+
+  #[test]
+  fn test_shared_divergence() {
+    let pattern = synthesize("foo(1, x, 1)", "foo(2, y, 2)");
+    assert_eq!(pattern.meta_var_names(), ["A", "B"]);
+    assert!(matches(&pattern, "foo(3, z, 3)"));
+    assert!(!matches(&pattern, "foo(3, z, 4)"));
+    // different kinds and child counts diverge as a whole
+    let pattern = synthesize("a.b(c)", "d(e, f)");
+    assert!(matches(&pattern, "x.y(1, 2, 3)"));
+    let pattern = synthesize("a + b", "a - b");
+    assert!(matches(&pattern, "a * b"));
+    assert!(!matches(&pattern, "a * c"));
+  }
+
+  #[test]
+  fn test_from_pair_error() {
+    assert!(Pattern::from_pair("a; b", "c", Tsx).is_err());
+    assert!(Pattern::from_pair("", "c", Tsx).is_err());
+  }
+}
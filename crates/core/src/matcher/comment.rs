@@ -0,0 +1,198 @@
+use super::{KindMatcher, KindMatcherError, Matcher};
+
+use crate::meta_var::{MetaVarEnv, MetaVariable};
+use crate::source::Content;
+use crate::{Doc, Language, Node};
+
+use std::borrow::Cow;
+
+use bit_set::BitSet;
+
+/// Matches a comment node by running a lightweight sub-pattern against the comment text.
+/// The text is split into words on whitespace and comment markers like `//`, `/**`, `*` and `*/` are dropped.
+/// In the sub-pattern, `$NAME` captures one word and `$$$NAME` captures the words up to the next
+/// word of the sub-pattern, never crossing a line; a trailing `$$$NAME` captures the rest of the line.
+/// Other words must appear verbatim. The sub-pattern can start at any word of the comment.
+/// Captures are bound as transformed variables since they are not nodes, see `MetaVarEnv::get_transformed`.
+#[derive(Clone)]
+pub struct CommentMatcher<L: Language> {
+  kind: KindMatcher<L>,
+  words: Vec<SubWord>,
+}
+
+#[derive(Clone, Debug)]
+enum SubWord {
+  Literal(String),
+  Capture(Option<String>),
+  MultiCapture(Option<String>),
+}
+
+impl<L: Language> CommentMatcher<L> {
+  /// Matches `sub_pattern` inside nodes of `comment_kind`, e.g. `comment` for most grammars.
+  pub fn try_new(comment_kind: &str, sub_pattern: &str, lang: L) -> Result<Self, KindMatcherError> {
+    let kind = KindMatcher::try_new(comment_kind, lang.clone())?;
+    let words = sub_pattern
+      .split_whitespace()
+      .map(|word| match lang.extract_meta_var(word) {
+        Some(MetaVariable::Capture(name, _)) => SubWord::Capture(Some(name)),
+        Some(MetaVariable::Dropped(_)) => SubWord::Capture(None),
+        Some(MetaVariable::MultiCapture(name) | MetaVariable::LineContiguousCapture(name)) => {
+          SubWord::MultiCapture(Some(name))
+        }
+        Some(MetaVariable::Multiple) => SubWord::MultiCapture(None),
+        None => SubWord::Literal(word.to_string()),
+      })
+      .collect();
+    Ok(Self { kind, words })
+  }
+}
+
+/// A word of the comment text with the line it is on.
+type Word<'a> = (usize, &'a str);
+
+fn comment_words(text: &str) -> Vec<Word<'_>> {
+  let mut words = vec![];
+  for (line, content) in text.lines().enumerate() {
+    for word in content.split_whitespace() {
+      let word = word.strip_suffix("*/").unwrap_or(word);
+      // markers like `//`, `/**` and the leading `*` of block comment lines
+      if word.chars().all(|c| c == '/' || c == '*') {
+        continue;
+      }
+      words.push((line, word));
+    }
+  }
+  words
+}
+
+fn match_words<'p>(
+  goals: &'p [SubWord],
+  words: &[Word<'_>],
+  bound: &mut Vec<(&'p str, String)>,
+) -> bool {
+  let Some((goal, rest)) = goals.split_first() else {
+    return true;
+  };
+  let checkpoint = bound.len();
+  match goal {
+    SubWord::Literal(text) => {
+      matches!(words.first(), Some((_, w)) if w == text) && match_words(rest, &words[1..], bound)
+    }
+    SubWord::Capture(name) => {
+      let Some((_, word)) = words.first() else {
+        return false;
+      };
+      if let Some(name) = name {
+        if !bind(bound, name, word.to_string()) {
+          return false;
+        }
+      }
+      match_words(rest, &words[1..], bound) || {
+        bound.truncate(checkpoint);
+        false
+      }
+    }
+    SubWord::MultiCapture(name) => {
+      let line = words.first().map(|w| w.0);
+      let same_line = words.iter().take_while(|w| Some(w.0) == line).count();
+      // a trailing ellipsis takes the rest of the line, others take as few words as possible
+      let lens: Box<dyn Iterator<Item = usize>> = if rest.is_empty() {
+        Box::new(std::iter::once(same_line))
+      } else {
+        Box::new(0..=same_line)
+      };
+      for len in lens {
+        if let Some(name) = name {
+          let text: Vec<_> = words[..len].iter().map(|w| w.1).collect();
+          if !bind(bound, name, text.join(" ")) {
+            continue;
+          }
+        }
+        if match_words(rest, &words[len..], bound) {
+          return true;
+        }
+        bound.truncate(checkpoint);
+      }
+      false
+    }
+  }
+}
+
+/// Binds name to text unless it is already bound to a different text.
+fn bind<'p>(bound: &mut Vec<(&'p str, String)>, name: &'p str, text: String) -> bool {
+  match bound.iter().find(|(n, _)| *n == name) {
+    Some((_, existing)) => *existing == text,
+    None => {
+      bound.push((name, text));
+      true
+    }
+  }
+}
+
+impl<L: Language> Matcher<L> for CommentMatcher<L> {
+  fn match_node_with_env<'tree, D: Doc<Lang = L>>(
+    &self,
+    node: Node<'tree, D>,
+    env: &mut Cow<MetaVarEnv<'tree, D>>,
+  ) -> Option<Node<'tree, D>> {
+    let node = self.kind.match_node_with_env(node, env)?;
+    let text = node.text();
+    let words = comment_words(&text);
+    let mut bound = vec![];
+    let found =
+      (0..=words.len()).any(|start| match_words(&self.words, &words[start..], &mut bound));
+    if !found {
+      return None;
+    }
+    for (name, text) in bound {
+      let text = <D::Source as Content>::decode_str(&text).into_owned();
+      env.to_mut().insert_transformation(name, text);
+    }
+    Some(node)
+  }
+
+  fn potential_kinds(&self) -> Option<BitSet> {
+    self.kind.potential_kinds()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::language::Tsx;
+  use std::collections::HashMap;
+
+  fn find_in_comment(sub_pattern: &str, src: &str) -> Option<HashMap<String, String>> {
+    let matcher = CommentMatcher::try_new("comment", sub_pattern, Tsx).expect("should be valid");
+    let grep = Tsx.ast_grep(src);
+    let found = grep.root().find(matcher)?;
+    Some(HashMap::from(found.get_env().clone()))
+  }
+
+  #[test]
+  fn test_deprecated_tag() {
+    let src = "/**\n * Adds numbers.\n * @deprecated use bar instead\n * @param a first\n */\nfunction foo(a) {}";
+    let env = find_in_comment("@deprecated $$$MSG", src).expect("should match");
+    assert_eq!(env["MSG"], "use bar instead");
+    let env = find_in_comment("@param $NAME $$$DOC", src).expect("should match");
+    assert_eq!(env["NAME"], "a");
+    assert_eq!(env["DOC"], "first");
+    assert!(find_in_comment("@returns $$$DOC", src).is_none());
+  }
+
+  #[test]
+  fn test_line_comment() {
+    let env = find_in_comment("TODO: $WHO $$$", "// TODO: alice fix it").expect("should match");
+    assert_eq!(env["WHO"], "alice");
+    // words must be whitespace separated
+    assert!(find_in_comment("TODO($WHO):", "// TODO(alice): fix it").is_none());
+    let env = find_in_comment("TODO: $$$REST", "/* TODO: a b*/").expect("should match");
+    assert_eq!(env["REST"], "a b");
+  }
+
+  #[test]
+  fn test_not_comment() {
+    assert!(find_in_comment("@deprecated", "let a = '@deprecated'").is_none());
+    assert!(CommentMatcher::try_new("not_a_kind", "@deprecated", Tsx).is_err());
+  }
+}
@@ -0,0 +1,135 @@
+use super::{Matcher, Pattern};
+use crate::{Doc, Language, Node};
+
+use std::cmp::Reverse;
+use std::ops::Range;
+
+/// Byte ranges of a pattern's matches in a tree, kept across edits of the source.
+/// Pass it to [`Pattern::rematch_changed`] after each edit so only the edited part of the tree
+/// is matched again. Start with an empty cache and the whole source as the changed range.
+#[derive(Clone, Debug, Default)]
+pub struct MatchCache {
+  ranges: Vec<Range<usize>>,
+}
+
+impl MatchCache {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Ranges of matched nodes, in the same pre-order as `find_all`.
+  pub fn ranges(&self) -> &[Range<usize>] {
+    &self.ranges
+  }
+
+  /// Updates cached ranges for an edit that replaced `deleted_length` bytes at `position`
+  /// with `inserted_length` bytes, like the fields of [`crate::source::Edit`].
+  /// Ranges touching the deleted bytes are dropped and ranges after them are shifted.
+  pub fn apply_edit(&mut self, position: usize, deleted_length: usize, inserted_length: usize) {
+    let deleted = position..position + deleted_length;
+    self.ranges.retain(|r| !touches(r, &deleted));
+    for r in &mut self.ranges {
+      if r.start > deleted.end {
+        r.start = r.start - deleted_length + inserted_length;
+        r.end = r.end - deleted_length + inserted_length;
+      }
+    }
+  }
+}
+
+/// Whether two ranges overlap or share an endpoint.
+/// A node ending right where text is inserted can still change, e.g. `foo` becoming `foo()`.
+fn touches(a: &Range<usize>, b: &Range<usize>) -> bool {
+  a.start <= b.end && b.start <= a.end
+}
+
+impl<L: Language> Pattern<L> {
+  /// Matches the pattern again only at nodes under `root` whose range touches `changed`,
+  /// a byte range in the edited source, and reuses the cached matches everywhere else.
+  /// The cache must already reflect the edit, see [`MatchCache::apply_edit`].
+  ///
+  /// Subtrees outside `changed` are not visited. So `changed` must cover every node
+  /// the parser restructured, e.g. the union of tree-sitter's `Tree::changed_ranges`
+  /// and the inserted text. Returns the number of nodes matched again.
+  pub fn rematch_changed<D: Doc<Lang = L>>(
+    &self,
+    root: Node<D>,
+    changed: Range<usize>,
+    cache: &mut MatchCache,
+  ) -> usize {
+    cache.ranges.retain(|r| !touches(r, &changed));
+    let mut evaluated = 0;
+    let mut stack = vec![root];
+    while let Some(node) = stack.pop() {
+      if !touches(&node.range(), &changed) {
+        continue;
+      }
+      evaluated += 1;
+      if let Some(matched) = self.match_node(node.clone()) {
+        cache.ranges.push(matched.range());
+      }
+      stack.extend(node.children());
+    }
+    cache.ranges.sort_by_key(|r| (r.start, Reverse(r.end)));
+    evaluated
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::language::Tsx;
+  use crate::source::Edit;
+
+  fn find_all_ranges<D: Doc<Lang = Tsx>>(
+    pattern: &Pattern<Tsx>,
+    root: Node<D>,
+  ) -> Vec<Range<usize>> {
+    root.find_all(pattern).map(|m| m.range()).collect()
+  }
+
+  #[test]
+  fn test_rematch_one_subtree() {
+    let src = "function a() { foo(1); foo(2) }\nfunction b() { foo(3); bar(4) }\nfoo(5)";
+    let mut grep = Tsx.ast_grep(src);
+    let pattern = Pattern::new("foo($A)", Tsx);
+    let mut cache = MatchCache::new();
+    let total = pattern.rematch_changed(grep.root(), 0..src.len(), &mut cache);
+    assert_eq!(cache.ranges(), find_all_ranges(&pattern, grep.root()));
+    assert_eq!(cache.ranges().len(), 4);
+
+    // rename bar to foo inside function b only
+    let position = src.find("bar").expect("should find bar");
+    let edit = Edit {
+      position,
+      deleted_length: 3,
+      inserted_text: "foo".into(),
+    };
+    grep.edit(edit).expect("should edit");
+    cache.apply_edit(position, 3, 3);
+    let evaluated = pattern.rematch_changed(grep.root(), position..position + 3, &mut cache);
+    assert_eq!(cache.ranges(), find_all_ranges(&pattern, grep.root()));
+    assert_eq!(cache.ranges().len(), 5);
+    assert!(evaluated < total / 2);
+  }
+
+  #[test]
+  fn test_rematch_shifts_later_matches() {
+    let src = "foo(1); let x = 1; foo(2)";
+    let mut grep = Tsx.ast_grep(src);
+    let pattern = Pattern::new("foo($A)", Tsx);
+    let mut cache = MatchCache::new();
+    pattern.rematch_changed(grep.root(), 0..src.len(), &mut cache);
+    let position = src.find("= 1").expect("should find x") + 2;
+    let edit = Edit {
+      position,
+      deleted_length: 1,
+      inserted_text: "100".into(),
+    };
+    grep.edit(edit).expect("should edit");
+    cache.apply_edit(position, 1, 3);
+    pattern.rematch_changed(grep.root(), position..position + 3, &mut cache);
+    assert_eq!(cache.ranges(), [0..6, 21..27]);
+    assert_eq!(cache.ranges(), find_all_ranges(&pattern, grep.root()));
+  }
+}
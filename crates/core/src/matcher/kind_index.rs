@@ -0,0 +1,91 @@
+use crate::{Doc, Node};
+
+use bit_set::BitSet;
+
+/// Start bytes of the nodes of each kind in a tree, built once with a full walk and shared by
+/// searches over that tree, e.g. every rule run on a file. A subtree covers the bytes from its start
+/// to its end, so it can contain a kind only if a node of that kind starts there.
+/// This lets [`Node::find_all_indexed`] skip whole subtrees where the pattern's `potential_kinds` never occur.
+/// The check is conservative: a node starting at the subtree's edge, e.g. its parent, may keep a subtree
+/// that has no such kind, but a subtree with a match is never skipped.
+pub struct KindIndex {
+  /// start bytes of the nodes of each kind id, ascending since the walk is pre-order
+  starts: Vec<Vec<usize>>,
+}
+
+impl KindIndex {
+  /// Indexes `node` and its descendants. Searches using the index must start at `node` or below it,
+  /// in the same tree without edits since the index was built.
+  pub fn new<D: Doc>(node: &Node<D>) -> Self {
+    let mut starts: Vec<Vec<usize>> = vec![];
+    for n in node.dfs() {
+      let kind = usize::from(n.kind_id());
+      if starts.len() <= kind {
+        starts.resize_with(kind + 1, Vec::new);
+      }
+      starts[kind].push(n.range().start);
+    }
+    Self { starts }
+  }
+
+  /// Whether `node` or a descendant may have one of `kinds`.
+  pub fn may_contain<D: Doc>(&self, node: &Node<D>, kinds: &BitSet) -> bool {
+    let range = node.range();
+    kinds.iter().any(|kind| {
+      let Some(starts) = self.starts.get(kind) else {
+        return false;
+      };
+      let first = starts.partition_point(|s| *s < range.start);
+      // inclusive end, a zero-width descendant may start where the node ends
+      matches!(starts.get(first), Some(s) if *s <= range.end)
+    })
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::language::{Language, Tsx};
+  use crate::matcher::{FindAllNodes, NodeMatch};
+  use crate::Pattern;
+
+  #[test]
+  fn test_find_all_indexed() {
+    let src = "function a() { foo(1); if (x) { bar(new A()) } }\nclass B { m() { return [new C(2)] } }\nlet y = z + 1;";
+    let grep = Tsx.ast_grep(src);
+    let root = grep.root();
+    let index = KindIndex::new(&root);
+    for pattern in [
+      "new $C($$$)",
+      "$F($A)",
+      "$A + $B",
+      "not_found",
+      "let $A = $B",
+    ] {
+      let texts = |found: &mut dyn Iterator<Item = NodeMatch<_>>| {
+        found.map(|m| m.text().to_string()).collect::<Vec<_>>()
+      };
+      let expected = texts(&mut root.find_all(pattern));
+      assert_eq!(texts(&mut root.find_all_indexed(pattern, &index)), expected);
+    }
+    // a search below the indexed node
+    let class = root.find("class $B { $$$ }").expect("should find");
+    let found: Vec<_> = class.find_all_indexed("new $C($$$)", &index).collect();
+    assert_eq!(found.len(), 1);
+  }
+
+  #[test]
+  fn test_skip_subtrees() {
+    let src = "function a() { foo(1); bar(2) }\nfunction b() { baz(new C()) }";
+    let grep = Tsx.ast_grep(src);
+    let root = grep.root();
+    let index = KindIndex::new(&root);
+    let pattern = Pattern::new("new $C()", Tsx);
+    let mut all = FindAllNodes::new(&pattern, root.clone());
+    let mut indexed = FindAllNodes::new(&pattern, root.clone()).kind_index(&index);
+    assert_eq!(all.by_ref().count(), 1);
+    assert_eq!(indexed.by_ref().count(), 1);
+    // the body of `a` is never entered
+    assert!(indexed.visited < all.visited / 2);
+  }
+}
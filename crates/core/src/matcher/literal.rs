@@ -0,0 +1,249 @@
+use super::NodeMatch;
+use crate::Doc;
+
+/// Reads captures as typed values following TSX literal syntax.
+/// Each method reads the text of the single meta variable `$name` and returns None
+/// if it is not bound or its text is not a literal of that type.
+/// A leading `-` is accepted for numbers since `-1` is captured as a unary expression.
+impl<'tree, D: Doc> NodeMatch<'tree, D> {
+  /// Parses an integer literal like `42`, `-7`, `1_000`, `0xff`, `0o17`, `0b101` or the BigInt `10n`.
+  /// Fractions, exponents and values out of the `i64` range are rejected.
+  pub fn capture_i64(&self, name: &str) -> Option<i64> {
+    parse_i64(&self.get(name)?.text())
+  }
+  /// Parses a number literal like `1.5`, `-2e3`, `.5` or any integer literal accepted by `capture_i64`
+  /// except BigInt. `Infinity` and `NaN` are identifiers, not literals, and are rejected.
+  pub fn capture_f64(&self, name: &str) -> Option<f64> {
+    parse_f64(&self.get(name)?.text())
+  }
+  /// Parses `true` or `false`.
+  pub fn capture_bool(&self, name: &str) -> Option<bool> {
+    match &*self.get(name)?.text() {
+      "true" => Some(true),
+      "false" => Some(false),
+      _ => None,
+    }
+  }
+  /// Decodes a single, double quoted or template string literal, e.g. `'a\n'` to `a` and a newline.
+  /// Templates with substitutions like `` `${a}` `` are rejected, so are invalid escapes.
+  pub fn capture_string_literal(&self, name: &str) -> Option<String> {
+    decode_string(&self.get(name)?.text())
+  }
+}
+
+fn split_sign(text: &str) -> (bool, &str) {
+  match text.strip_prefix('-') {
+    Some(rest) => (true, rest.trim_start()),
+    None => (false, text),
+  }
+}
+
+/// Removes `_` separators, which must sit between two digits.
+fn strip_separators(digits: &str) -> Option<String> {
+  if digits.starts_with('_') || digits.ends_with('_') || digits.contains("__") {
+    return None;
+  }
+  Some(digits.replace('_', ""))
+}
+
+fn radix_of(text: &str) -> (u32, &str) {
+  let prefix = text.get(..2).map(str::to_ascii_lowercase);
+  match prefix.as_deref() {
+    Some("0x") => (16, &text[2..]),
+    Some("0o") => (8, &text[2..]),
+    Some("0b") => (2, &text[2..]),
+    _ => (10, text),
+  }
+}
+
+fn parse_i64(text: &str) -> Option<i64> {
+  let (negative, text) = split_sign(text);
+  let text = text.strip_suffix('n').unwrap_or(text);
+  let (radix, digits) = radix_of(text);
+  let digits = strip_separators(digits)?;
+  if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+    return None;
+  }
+  // parse with the sign so that i64::MIN is in range
+  let signed = if negative {
+    format!("-{digits}")
+  } else {
+    digits
+  };
+  i64::from_str_radix(&signed, radix).ok()
+}
+
+fn parse_f64(text: &str) -> Option<f64> {
+  let (negative, text) = split_sign(text);
+  let (radix, digits) = radix_of(text);
+  let value = if radix == 10 {
+    let digits = strip_separators(digits)?;
+    // Rust also accepts `inf` and `nan`, which are not number literals
+    let is_literal = digits
+      .chars()
+      .all(|c| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '+' | '-'));
+    if !is_literal || !digits.starts_with(|c: char| c.is_ascii_digit() || c == '.') {
+      return None;
+    }
+    digits.parse::<f64>().ok()?
+  } else {
+    parse_i64(text)? as f64
+  };
+  Some(if negative { -value } else { value })
+}
+
+fn decode_string(text: &str) -> Option<String> {
+  let quote = text.chars().next()?;
+  if !matches!(quote, '\'' | '"' | '`') || text.len() < 2 || !text.ends_with(quote) {
+    return None;
+  }
+  let body = &text[1..text.len() - 1];
+  if quote == '`' && body.contains("${") {
+    return None;
+  }
+  let mut decoded = String::new();
+  let mut chars = body.chars().peekable();
+  while let Some(c) = chars.next() {
+    if c != '\\' {
+      decoded.push(c);
+      continue;
+    }
+    let c = match chars.next()? {
+      'n' => '\n',
+      't' => '\t',
+      'r' => '\r',
+      'b' => '\u{8}',
+      'f' => '\u{c}',
+      'v' => '\u{b}',
+      '0' if !chars.peek().map_or(false, char::is_ascii_digit) => '\0',
+      'x' => {
+        let hex: String = chars.by_ref().take(2).collect();
+        char::from(
+          u8::from_str_radix(&hex, 16)
+            .ok()
+            .filter(|_| hex.len() == 2)?,
+        )
+      }
+      'u' => decode_unicode(&mut chars)?,
+      // line continuation
+      '\n' => continue,
+      '\r' => {
+        chars.next_if_eq(&'\n');
+        continue;
+      }
+      // legacy octal escapes
+      c if c.is_ascii_digit() => return None,
+      c => c,
+    };
+    decoded.push(c);
+  }
+  Some(decoded)
+}
+
+type Chars<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+
+/// Decodes the code point after `\u`, joining a surrogate pair written as two escapes.
+fn decode_unicode(chars: &mut Chars) -> Option<char> {
+  let unit = code_unit(chars)?;
+  if !(0xd800..0xdc00).contains(&unit) {
+    return char::from_u32(unit);
+  }
+  // a high surrogate must be followed by an escaped low surrogate
+  if chars.next()? != '\\' || chars.next()? != 'u' {
+    return None;
+  }
+  let low = code_unit(chars)?;
+  if !(0xdc00..0xe000).contains(&low) {
+    return None;
+  }
+  char::from_u32(0x10000 + ((unit - 0xd800) << 10) + (low - 0xdc00))
+}
+
+/// Reads `XXXX` or `{X...}` as hex.
+fn code_unit(chars: &mut Chars) -> Option<u32> {
+  let hex: String = if chars.next_if_eq(&'{').is_some() {
+    let hex = chars.by_ref().take_while(|c| *c != '}').collect();
+    hex
+  } else {
+    let hex: String = chars.by_ref().take(4).collect();
+    (hex.len() == 4).then_some(hex)?
+  };
+  if hex.is_empty() || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+    return None;
+  }
+  u32::from_str_radix(&hex, 16).ok()
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::language::{Language, Tsx};
+  use crate::StrDoc;
+
+  fn with_capture<T>(src: &str, read: impl Fn(&NodeMatch<StrDoc<Tsx>>) -> T) -> T {
+    let grep = Tsx.ast_grep(format!("let a = {src}"));
+    let found = grep.root().find("let a = $V").expect("should match");
+    read(&found)
+  }
+
+  #[test]
+  fn test_capture_i64() {
+    let read = |src| with_capture(src, |m| m.capture_i64("V"));
+    assert_eq!(read("42"), Some(42));
+    assert_eq!(read("-7"), Some(-7));
+    assert_eq!(read("1_000"), Some(1000));
+    assert_eq!(read("0xff"), Some(255));
+    assert_eq!(read("0o17"), Some(15));
+    assert_eq!(read("0B101"), Some(5));
+    assert_eq!(read("10n"), Some(10));
+    assert_eq!(read("-9223372036854775808"), Some(i64::MIN));
+    assert_eq!(read("9223372036854775808"), None);
+    assert_eq!(read("1.5"), None);
+    assert_eq!(read("'1'"), None);
+    assert_eq!(read("x"), None);
+    assert_eq!(with_capture("1", |m| m.capture_i64("W")), None);
+  }
+
+  #[test]
+  fn test_capture_f64() {
+    let read = |src| with_capture(src, |m| m.capture_f64("V"));
+    assert_eq!(read("1.5"), Some(1.5));
+    assert_eq!(read("-2e3"), Some(-2000.0));
+    assert_eq!(read(".5"), Some(0.5));
+    assert_eq!(read("1_000.25"), Some(1000.25));
+    assert_eq!(read("0x10"), Some(16.0));
+    assert_eq!(read("3"), Some(3.0));
+    assert_eq!(read("Infinity"), None);
+    assert_eq!(read("NaN"), None);
+    assert_eq!(read("10n"), None);
+    assert_eq!(read("'1.5'"), None);
+  }
+
+  #[test]
+  fn test_capture_bool() {
+    let read = |src| with_capture(src, |m| m.capture_bool("V"));
+    assert_eq!(read("true"), Some(true));
+    assert_eq!(read("false"), Some(false));
+    assert_eq!(read("'true'"), None);
+    assert_eq!(read("1"), None);
+  }
+
+  #[test]
+  fn test_capture_string_literal() {
+    let read = |src| with_capture(src, |m| m.capture_string_literal("V"));
+    assert_eq!(read("'abc'").as_deref(), Some("abc"));
+    assert_eq!(read(r#""a\"b\\c""#).as_deref(), Some("a\"b\\c"));
+    assert_eq!(read(r"'\n\t\0'").as_deref(), Some("\n\t\0"));
+    assert_eq!(read(r"'\x41B\u{43}'").as_deref(), Some("ABC"));
+    assert_eq!(read(r"'\uD83D\uDE00'").as_deref(), Some("😀"));
+    assert_eq!(read("'a\\\nb'").as_deref(), Some("ab"));
+    assert_eq!(read(r"'\q'").as_deref(), Some("q"));
+    assert_eq!(read("`tpl`").as_deref(), Some("tpl"));
+    assert_eq!(read("`${x}`"), None);
+    assert_eq!(read(r"'\uD83D'"), None);
+    assert_eq!(read("42"), None);
+    // invalid escapes are parse errors in TSX but may come from other grammars
+    assert_eq!(decode_string(r"'\xZZ'"), None);
+    assert_eq!(decode_string(r"'\u{}'"), None);
+  }
+}
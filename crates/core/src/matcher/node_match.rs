@@ -1,11 +1,12 @@
-use super::Matcher;
-use crate::meta_var::MetaVarEnv;
+use super::{Matcher, Pattern};
+use crate::meta_var::{MetaVarEnv, MetaVariable};
 use crate::replacer::Replacer;
 use crate::source::Edit;
-use crate::{Doc, Node};
+use crate::{Doc, Language, Node, StrDoc};
 
 use std::borrow::Borrow;
 use std::ops::Deref;
+use std::ops::Range;
 
 /// Represents the matched node with populated MetaVarEnv.
 /// It derefs to the Node so you can use it as a Node.
@@ -29,6 +30,122 @@ impl<'tree, D: Doc> NodeMatch<'tree, D> {
   pub fn get_env_mut(&mut self) -> &mut MetaVarEnv<'tree, D> {
     &mut self.1
   }
+  /// Returns how many named nodes each anonymous ellipsis `$$$` consumed, see [`MetaVarEnv::ellipsis_counts`].
+  pub fn ellipsis_counts(&self) -> &[usize] {
+    self.1.ellipsis_counts()
+  }
+  /// Returns which alternative of [`Pattern::with_fallbacks`] matched, 0 for the primary pattern and
+  /// `i + 1` for the fallback at index `i`. None for other matchers. It is [`MetaVarEnv::variant_index`].
+  pub fn variant_index(&self) -> Option<usize> {
+    self.1.variant_index()
+  }
+  /// Returns the node captured by the single meta variable `$name`, e.g. to read its kind or walk from it.
+  /// The node borrows the same tree as the match. It is [`MetaVarEnv::get_match`].
+  pub fn get(&self, name: &str) -> Option<&Node<'tree, D>> {
+    self.1.get_match(name)
+  }
+  /// Returns the nodes captured by the multi meta variable `$$$name`, empty if it is not bound.
+  /// It is [`MetaVarEnv::get_multiple_matches`].
+  pub fn get_multi(&self, name: &str) -> Vec<Node<'tree, D>> {
+    self.1.get_multiple_matches(name)
+  }
+  /// Returns the meta variables declared in `pattern` that this match left unbound, e.g. `$B` of
+  /// `let $A = $B` with `B` optional matching `let a`. Names are in pattern order without duplicates.
+  /// A multi meta variable capturing no node is bound.
+  pub fn unbound_metavars(&self, pattern: &Pattern<D::Lang>) -> Vec<String> {
+    let mut unbound: Vec<String> = vec![];
+    for name in pattern.meta_var_names() {
+      let bound = self.1.get_match(&name).is_some() || self.1.get_labels(&name).is_some();
+      if !bound && !unbound.contains(&name) {
+        unbound.push(name);
+      }
+    }
+    unbound
+  }
+  /// Returns the identifiers referenced in the capture `name`, single or multi, in source order without
+  /// duplicates, e.g. `a` and `b` for `a + b.c`. It is a syntactic walk, not scope analysis: every node
+  /// of `identifier_kinds` counts. In TSX passing only the `identifier` kind leaves out property names
+  /// like `c` in `b.c` and object keys, which are `property_identifier`. Identifiers declared inside the
+  /// capture, e.g. arrow function parameters, are listed too. Returns an empty Vec if `name` is not bound.
+  pub fn free_identifiers(&self, name: &str, identifier_kinds: &[u16]) -> Vec<String> {
+    let nodes = match self.1.get_match(name) {
+      Some(node) => vec![node.clone()],
+      None => self.1.get_multiple_matches(name),
+    };
+    let mut found: Vec<String> = vec![];
+    for node in nodes.iter().flat_map(Node::dfs) {
+      if !identifier_kinds.contains(&node.kind_id()) {
+        continue;
+      }
+      let text = node.text();
+      if !found.iter().any(|f| *f == text) {
+        found.push(text.into_owned());
+      }
+    }
+    found
+  }
+  /// Returns whether `sub` matches no node strictly within the matched node, the negation of [`Node::has`].
+  /// Combine it with `has` for rules like a function that calls `foo` but never `bar`.
+  pub fn none_within<M: Matcher<D::Lang>>(&self, sub: M) -> bool {
+    !self.0.has(sub)
+  }
+  /// Returns the lowest common ancestor of all nodes captured by single and multi meta variables,
+  /// a captured node counting as its own ancestor. It is usually the matched node but can be deeper,
+  /// e.g. the arguments of `foo` for `foo($A, $B)`, which bounds an edit touching only the captures.
+  /// Returns None if nothing is captured.
+  pub fn captures_lca(&self) -> Option<Node<'tree, D>> {
+    let mut captured = self.1.get_matched_variables().flat_map(|var| match var {
+      MetaVariable::Capture(name, _) => self.1.get_match(&name).cloned().into_iter().collect(),
+      MetaVariable::MultiCapture(name) | MetaVariable::LineContiguousCapture(name) => {
+        self.1.get_multiple_matches(&name)
+      }
+      _ => vec![],
+    });
+    let mut lca = captured.next()?;
+    for node in captured {
+      let contains = |ancestor: &Node<'tree, D>| {
+        let id = ancestor.node_id();
+        node.node_id() == id || node.ancestors().any(|a| a.node_id() == id)
+      };
+      while !contains(&lca) {
+        lca = lca.parent()?;
+      }
+    }
+    Some(lca)
+  }
+  /// Returns whether the matched statement ends with a terminator token of the language, e.g. `;`
+  /// in `x = 1;` but not in `x = 1` where TSX inserts the semicolon automatically.
+  /// If the match is the only child of its statement, e.g. the expression `x = 1` of an expression
+  /// statement, that statement is checked. Zero-width tokens inserted by error recovery do not count.
+  pub fn has_terminator(&self) -> bool {
+    let terminators = self.0.lang().terminator_tokens();
+    let ends_with_terminator = |node: &Node<'tree, D>| {
+      let Some(last) = node.children().last() else {
+        return false;
+      };
+      !last.is_named() && !last.range().is_empty() && terminators.contains(&&*last.text())
+    };
+    if ends_with_terminator(&self.0) {
+      return true;
+    }
+    let Some(parent) = self.0.parent() else {
+      return false;
+    };
+    let mut named = parent.children().filter(Node::is_named);
+    let only_child =
+      matches!(named.next(), Some(n) if n.node_id() == self.0.node_id()) && named.next().is_none();
+    only_child && ends_with_terminator(&parent)
+  }
+  /// Returns the name of the field the matched node is assigned to in its parent, if any.
+  /// For example, `foo($$$)` in `const a = foo()` is the `value` of the declarator.
+  /// It is computed on each call by walking the parent's children, not during matching.
+  pub fn field_name(&self) -> Option<String> {
+    let field_id = self.0.field_id_in_parent()?;
+    let lang = self.0.lang().get_ts_language();
+    lang
+      .field_name_for_id(field_id)
+      .map(|name| name.into_owned())
+  }
   /// # Safety
   /// should only called for readopting nodes
   pub(crate) unsafe fn get_node_mut(&mut self) -> &mut Node<'tree, D> {
@@ -36,6 +153,116 @@ impl<'tree, D: Doc> NodeMatch<'tree, D> {
   }
 }
 
+impl<'tree, L: Language> NodeMatch<'tree, StrDoc<L>> {
+  /// Returns the token stream of the capture `name`, single or multi, for rewrites that must keep its
+  /// formatting exactly. Entries are leaf tokens and the source between them, e.g. whitespace,
+  /// each with its byte range, in source order. Concatenating the texts gives the exact source the
+  /// capture spans. Comments are leaves of the tree and appear as tokens.
+  /// Nothing is recorded while matching, the stream is computed from the tree on each call.
+  pub fn capture_tokens(&self, name: &str) -> Option<Vec<(Range<usize>, &'tree str)>> {
+    let nodes = match self.1.get_match(name) {
+      Some(node) => vec![node.clone()],
+      None => self.1.get_labels(name)?.clone(),
+    };
+    let src = self.0.root().get_text();
+    let mut tokens = vec![];
+    let mut last_end = None;
+    for leaf in nodes.iter().flat_map(Node::dfs).filter(Node::is_leaf) {
+      let range = leaf.range();
+      if range.is_empty() {
+        // MISSING nodes inserted by error recovery have no source
+        continue;
+      }
+      let end = range.end.min(src.len());
+      match last_end {
+        Some(last) if last < range.start => {
+          tokens.push((last..range.start, &src[last..range.start]))
+        }
+        _ => {}
+      }
+      tokens.push((range.start..end, &src[range.start..end]));
+      last_end = Some(end);
+    }
+    Some(tokens)
+  }
+}
+
+impl<'tree, D: Doc> NodeMatch<'tree, D> {
+  /// Computes an id that identifies this match across runs, for example to suppress findings
+  /// already reported. `pattern_key` is the caller's key for the pattern, e.g. its source or rule id.
+  ///
+  /// The id combines `pattern_key`, the signature of the enclosing scope and the match's position
+  /// relative to that scope. The enclosing scope is the nearest ancestor with a `name` field,
+  /// e.g. a function or class declaration, or the root if there is none.
+  /// Its signature is the scope's kind and name plus the node kinds from the scope down to the match.
+  /// The relative position is the number of nodes with the same kind and text preceding the match in the scope.
+  ///
+  /// So the id is kept when code outside the scope is edited, when the scope is moved,
+  /// and when unrelated code inside the scope changes. It changes when the matched text
+  /// or the scope name changes, or when an identical node is inserted before the match in the scope.
+  /// The id is computed with FNV-1a and does not depend on the platform or Rust version.
+  pub fn stable_id(&self, pattern_key: &str) -> u64 {
+    let node = self.get_node();
+    let scope = node
+      .ancestors()
+      .find(|n| n.field("name").is_some())
+      .or_else(|| node.ancestors().last());
+    let mut hash = StableHash::new();
+    hash.write_str(pattern_key);
+    let text = node.text();
+    let Some(scope) = scope else {
+      // the match is the root itself
+      hash.write_str(&node.kind());
+      return hash.finish();
+    };
+    hash.write_str(&scope.kind());
+    if let Some(name) = scope.field("name") {
+      hash.write_str(&name.text());
+    }
+    let path: Vec<_> = node
+      .ancestors()
+      .take_while(|n| n.node_id() != scope.node_id())
+      .collect();
+    for ancestor in path.iter().rev() {
+      hash.write_str(&ancestor.kind());
+    }
+    hash.write_str(&node.kind());
+    let preceding = scope
+      .dfs()
+      .take_while(|n| n.node_id() != node.node_id())
+      .filter(|n| n.kind_id() == node.kind_id() && n.text() == text)
+      .count();
+    hash.write_usize(preceding);
+    hash.finish()
+  }
+}
+
+/// FNV-1a hash whose output is stable across runs, unlike std's `DefaultHasher`.
+struct StableHash(u64);
+
+impl StableHash {
+  fn new() -> Self {
+    Self(0xcbf2_9ce4_8422_2325)
+  }
+  fn write(&mut self, bytes: &[u8]) {
+    for b in bytes {
+      self.0 ^= u64::from(*b);
+      self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+    }
+  }
+  fn write_str(&mut self, s: &str) {
+    // length prefix avoids ambiguity between adjacent strings
+    self.write_usize(s.len());
+    self.write(s.as_bytes());
+  }
+  fn write_usize(&mut self, n: usize) {
+    self.write(&(n as u64).to_le_bytes());
+  }
+  fn finish(&self) -> u64 {
+    self.0
+  }
+}
+
 impl<'tree, D: Doc> NodeMatch<'tree, D> {
   pub fn replace_by<R: Replacer<D>>(&self, replacer: R) -> Edit<D::Source> {
     let range = self.range();
@@ -49,6 +276,37 @@ impl<'tree, D: Doc> NodeMatch<'tree, D> {
     }
   }
 
+  /// Returns an edit deleting the matched node with its separator, e.g. removing an argument or
+  /// an array element without leaving a dangling comma. A separator is a sibling `,` or `;` token.
+  /// A node after a separator is deleted with the separator and the space before it, so `b` in `f(a, b)`
+  /// gives `f(a)`. A node followed by a separator, e.g. the first argument, is deleted with the separator
+  /// and the space up to the next sibling, so `a` gives `f(b)`. Without separators, e.g. statements,
+  /// the space before the node is deleted, or the space after it if the node is first among named siblings.
+  pub fn delete_edit(&self) -> Edit<D::Source> {
+    let node = &self.0;
+    let range = node.range();
+    let prev = node.prev();
+    let next = node.next();
+    let (start, end) = if let Some(sep) = prev.as_ref().filter(|n| is_separator(n)) {
+      let before = sep.prev().map_or(sep.range().start, |n| n.range().end);
+      (before, range.end)
+    } else if let Some(sep) = next.as_ref().filter(|n| is_separator(n)) {
+      let after = sep.next().map_or(sep.range().end, |n| n.range().start);
+      (range.start, after)
+    } else if let Some(prev) = prev.filter(Node::is_named) {
+      (prev.range().end, range.end)
+    } else if let Some(next) = next.filter(Node::is_named) {
+      (range.start, next.range().start)
+    } else {
+      (range.start, range.end)
+    };
+    Edit {
+      position: start,
+      deleted_length: end - start,
+      inserted_text: Vec::new(),
+    }
+  }
+
   #[doc(hidden)]
   pub fn make_edit<M, R>(&self, matcher: &M, replacer: &R) -> Edit<D::Source>
   where
@@ -65,6 +323,10 @@ impl<'tree, D: Doc> NodeMatch<'tree, D> {
   }
 }
 
+fn is_separator<D: Doc>(node: &Node<D>) -> bool {
+  !node.is_named() && matches!(&*node.text(), "," | ";")
+}
+
 impl<'tree, D: Doc> From<Node<'tree, D>> for NodeMatch<'tree, D> {
   fn from(node: Node<'tree, D>) -> Self {
     Self(node, MetaVarEnv::new())
@@ -97,7 +359,7 @@ impl<'tree, D: Doc> Borrow<Node<'tree, D>> for NodeMatch<'tree, D> {
 mod test {
   use super::*;
   use crate::language::Tsx;
-  use crate::{Language, StrDoc};
+  use crate::StrDoc;
 
   fn use_node<L: Language>(n: &Node<StrDoc<L>>) -> String {
     n.text().to_string()
@@ -131,6 +393,117 @@ mod test {
     assert_eq!(node.text(), "a");
   }
 
+  #[test]
+  fn test_ellipsis_counts() {
+    let counts = |pattern: &str, src: &str| {
+      let root = Tsx.ast_grep(src);
+      let found = root.root().find(pattern).expect("should find");
+      found.ellipsis_counts().to_vec()
+    };
+    assert_eq!(counts("foo($$$, x)", "foo(a, b, x)"), [2]);
+    assert_eq!(counts("foo($$$, x, $$$)", "foo(a, b, x, c)"), [2, 1]);
+    assert_eq!(counts("foo($$$)", "foo()"), [0]);
+    // named ellipses are captured instead
+    assert_eq!(counts("foo($$$A, x, $$$)", "foo(a, x, c)"), [1]);
+    // nested ellipses are counted once, in pattern order
+    assert_eq!(
+      counts("foo($$$, bar($$$))", "foo(a, b, bar(c, d, e))"),
+      [2, 3]
+    );
+    assert_eq!(
+      counts("foo($$$, bar($$$), $$$)", "foo(a, bar(c, d, e), z)"),
+      [1, 3, 1]
+    );
+    assert_eq!(counts("foo(bar($$$), $$$)", "foo(bar(c), y, z)"), [1, 2]);
+  }
+
+  #[test]
+  fn test_field_name() {
+    let field = |pattern: &str, src: &str| {
+      let root = Tsx.ast_grep(src);
+      let found = root.root().find(pattern).expect("should find");
+      found.field_name()
+    };
+    assert_eq!(field("foo($$$)", "const a = foo(1)"), Some("value".into()));
+    assert_eq!(field("a.b", "a.b(c)"), Some("function".into()));
+    assert_eq!(field("c", "a.b(c)"), None);
+    assert_eq!(field("foo($$$)", "foo(1)"), None);
+  }
+
+  #[test]
+  fn test_get_captures() {
+    let root = Tsx.ast_grep("foo(a.b, 1, 'c'); foo(d)");
+    let found: Vec<_> = root.root().find_all("foo($A, $$$REST)").collect();
+    assert_eq!(found.len(), 2);
+    assert_eq!(
+      found[1].get("A").map(|n| n.kind()),
+      Some("identifier".into())
+    );
+    assert!(found[1].get_multi("REST").is_empty());
+    let a = found[0].get("A").expect("should capture");
+    assert_eq!(a.kind(), "member_expression");
+    assert_eq!(a.field("property").map(|n| n.text()), Some("b".into()));
+    assert!(found[0].get("REST").is_none());
+    assert!(found[0].get_multi("B").is_empty());
+    // captured nodes borrow the tree, not the match
+    let rest = found[0].get_multi("REST");
+    drop(found);
+    let kinds: Vec<_> = rest.iter().map(|n| n.kind()).collect();
+    assert_eq!(kinds, ["number", ",", "string"]);
+  }
+
+  #[test]
+  fn test_unbound_metavars() {
+    let pattern = Pattern::new("let $A = $B", Tsx).with_optional("B");
+    let root = Tsx.ast_grep("let a; let b = 1");
+    let found: Vec<_> = root.root().find_all(&pattern).collect();
+    assert_eq!(found.len(), 2);
+    assert_eq!(found[0].unbound_metavars(&pattern), ["B"]);
+    assert!(found[1].unbound_metavars(&pattern).is_empty());
+    // an empty multi capture is bound
+    let pattern = Pattern::new("foo($A, $$$REST)", Tsx);
+    let found = Tsx
+      .ast_grep("foo(a)")
+      .root()
+      .find(&pattern)
+      .map(|m| m.unbound_metavars(&pattern));
+    assert_eq!(found, Some(vec![]));
+  }
+
+  #[test]
+  fn test_none_within() {
+    let src = "function a() { foo() }\nfunction b() { foo(); bar() }\nfunction c() { bar() }";
+    let root = Tsx.ast_grep(src);
+    let found: Vec<_> = root
+      .root()
+      .find_all("function $F() { $$$ }")
+      .filter(|m| m.has("foo()") && m.none_within("bar()"))
+      .map(|m| m.get("F").expect("should bind").text().to_string())
+      .collect();
+    assert_eq!(found, ["a"]);
+    // the matched node itself is not within
+    let call = root.root().find("bar()").expect("should find");
+    assert!(call.none_within("bar()"));
+  }
+
+  #[test]
+  fn test_free_identifiers() {
+    let lang = Tsx.get_ts_language();
+    let identifier = [lang.id_for_node_kind("identifier", true)];
+    let root = Tsx.ast_grep("x = a + b.c");
+    let found = root.root().find("$X = $E").expect("should match");
+    assert_eq!(found.free_identifiers("E", &identifier), ["a", "b"]);
+    assert_eq!(found.free_identifiers("X", &identifier), ["x"]);
+    assert!(found.free_identifiers("NONE", &identifier).is_empty());
+    let root = Tsx.ast_grep("f(a.b(a), { k: c }, 1)");
+    let found = root.root().find("f($$$ARGS)").expect("should match");
+    assert_eq!(found.free_identifiers("ARGS", &identifier), ["a", "c"]);
+    // property names count when their kind is given
+    let property = lang.id_for_node_kind("property_identifier", true);
+    let kinds = [identifier[0], property];
+    assert_eq!(found.free_identifiers("ARGS", &kinds), ["a", "b", "k", "c"]);
+  }
+
   #[test]
   fn test_replace_by() {
     let root = Tsx.ast_grep("var a = 1");
@@ -140,4 +513,122 @@ mod test {
     assert_eq!(fixed.deleted_length, 9);
     assert_eq!(fixed.inserted_text, "var b = a".as_bytes());
   }
+
+  fn delete(src: &str, pattern: &str) -> String {
+    let root = Tsx.ast_grep(src);
+    let found = root.root().find(pattern).expect("should find");
+    let edit = found.delete_edit();
+    let mut deleted = src.to_string();
+    deleted.replace_range(edit.position..edit.position + edit.deleted_length, "");
+    deleted
+  }
+
+  #[test]
+  fn test_delete_edit() {
+    let src = "foo(a, b, c)";
+    assert_eq!(delete(src, "a"), "foo(b, c)");
+    assert_eq!(delete(src, "b"), "foo(a, c)");
+    assert_eq!(delete(src, "c"), "foo(a, b)");
+    assert_eq!(delete("foo(a)", "a"), "foo()");
+    assert_eq!(delete("foo(a , b,)", "b"), "foo(a,)");
+    assert_eq!(delete("foo(a,\n  b)", "a"), "foo(b)");
+    assert_eq!(delete("let x = [1, 2]", "2"), "let x = [1]");
+    // statements own their semicolon, the space around is deleted
+    let src = "a();\nb();\nc();";
+    assert_eq!(delete(src, "a();"), "b();\nc();");
+    assert_eq!(delete(src, "b();"), "a();\nc();");
+    assert_eq!(delete(src, "c();"), "a();\nb();");
+  }
+
+  #[test]
+  fn test_has_terminator() {
+    let terminated = |src: &str, pattern: &str| {
+      let grep = Tsx.ast_grep(src);
+      let found = grep.root().find(pattern).expect("should match");
+      found.has_terminator()
+    };
+    assert!(terminated("x = 1;", "x = 1"));
+    assert!(!terminated("x = 1", "x = 1"));
+    assert!(!terminated("x = 1\ny = 2", "x = 1"));
+    assert!(terminated("function f() { return 1; }", "return $A"));
+    assert!(!terminated("function f() { return 1 }", "return $A"));
+    // the argument is not the only child of its statement
+    assert!(!terminated("f(x);", "x"));
+    assert!(!terminated("if (a) {}", "if ($A) {}"));
+  }
+
+  #[test]
+  fn test_captures_lca() {
+    let lca = |pattern: &str, src: &str| {
+      let grep = Tsx.ast_grep(src);
+      let found = grep.root().find(pattern).expect("should match");
+      found
+        .captures_lca()
+        .map(|n| (n.kind().to_string(), n.text().to_string()))
+    };
+    let parent = lca("$A + $B", "x = a + b").expect("should capture");
+    assert_eq!(parent, ("binary_expression".into(), "a + b".into()));
+    let args = lca("foo($A, $$$REST)", "foo(a, b, c)").expect("should capture");
+    assert_eq!(args, ("arguments".into(), "(a, b, c)".into()));
+    // a single capture is its own ancestor
+    let single = lca("foo($A)", "foo(bar(1))").expect("should capture");
+    assert_eq!(single, ("call_expression".into(), "bar(1)".into()));
+    assert!(lca("foo()", "foo()").is_none());
+  }
+
+  #[test]
+  fn test_capture_tokens() {
+    let src = "foo(a /* c */ +\n  b,\n  bar( 1 ))";
+    let root = Tsx.ast_grep(src);
+    let found = root.root().find("foo($A, $B)").expect("should match");
+    let tokens = found.capture_tokens("A").expect("should capture");
+    let texts: Vec<_> = tokens.iter().map(|t| t.1).collect();
+    assert_eq!(texts, ["a", " ", "/* c */", " ", "+", "\n  ", "b"]);
+    for (range, text) in &tokens {
+      assert_eq!(&src[range.clone()], *text);
+    }
+    // the token stream reproduces the capture exactly
+    let a = found.get("A").expect("should capture");
+    assert_eq!(texts.concat(), a.text());
+    let b = found.capture_tokens("B").expect("should capture");
+    assert_eq!(b.iter().map(|t| t.1).collect::<String>(), "bar( 1 )");
+    let found = root.root().find("foo($$$ARGS)").expect("should match");
+    let args = found.capture_tokens("ARGS").expect("should capture");
+    let args: String = args.iter().map(|t| t.1).collect();
+    assert_eq!(args, "a /* c */ +\n  b,\n  bar( 1 )");
+    assert!(found.capture_tokens("C").is_none());
+  }
+
+  fn stable_ids(src: &str, pattern: &str) -> Vec<u64> {
+    let root = Tsx.ast_grep(src);
+    let matches: Vec<_> = root.root().find_all(pattern).collect();
+    matches.iter().map(|m| m.stable_id(pattern)).collect()
+  }
+
+  #[test]
+  fn test_stable_id() {
+    let src = "function foo() { log(a); log(a); }\nfunction bar() { log(a) }";
+    let ids = stable_ids(src, "log($A)");
+    assert_eq!(ids.len(), 3);
+    // identical matches in different positions or scopes are distinguished
+    assert_ne!(ids[0], ids[1]);
+    assert_ne!(ids[0], ids[2]);
+    assert_eq!(ids, stable_ids(src, "log($A)"));
+    let root = Tsx.ast_grep(src);
+    let m = root.root().find("log($A)").expect("should find");
+    assert_ne!(m.stable_id("log($B)"), ids[0]);
+  }
+
+  #[test]
+  fn test_stable_id_survives_unrelated_edit() {
+    let src = "function foo() { log(a); log(a); }\nfunction bar() { log(a) }";
+    let ids = stable_ids(src, "log($A)");
+    // edit another scope, add code before the scope and inside the scope
+    let edited = "let x = 1;\nfunction bar() { log(a); let y = 2 }\nfunction foo() { let z = 3; log(a); log(a); }";
+    let edited_ids = stable_ids(edited, "log($A)");
+    assert_eq!(edited_ids, vec![ids[2], ids[0], ids[1]]);
+    // renaming the scope changes the id
+    let renamed = "function baz() { log(a); log(a); }\nfunction bar() { log(a) }";
+    assert_ne!(stable_ids(renamed, "log($A)")[0], ids[0]);
+  }
 }
@@ -0,0 +1,132 @@
+use super::{Matcher, NodeMatch, Pattern};
+use crate::source::Content;
+use crate::{Doc, Language, Node};
+
+use bit_set::BitSet;
+use rayon::prelude::*;
+
+/// Subtrees spanning fewer bytes are searched on one thread, splitting them costs more than it saves.
+const SPLIT_LEN: usize = 4096;
+
+/// Nodes and matches moved between rayon's threads.
+/// Nodes are `!Send` only because they hold raw tree-sitter pointers. They are read-only views of
+/// the tree owned by a `Root` borrowed for `'r`. tree-sitter trees are `Sync` and `D: Sync` makes the
+/// rest of the root shareable, so nodes can be used on any thread while the root is borrowed.
+struct Sendable<T>(T);
+
+// SAFETY: see `Sendable`
+unsafe impl<D: Doc + Sync> Send for Sendable<Node<'_, D>> {}
+// SAFETY: see `Sendable`, besides nodes an env only owns strings and the content of transformations
+unsafe impl<D> Send for Sendable<Vec<NodeMatch<'_, D>>>
+where
+  D: Doc + Sync,
+  <D::Source as Content>::Underlying: Send,
+{
+}
+
+impl<L: Language + Sync> Pattern<L> {
+  /// Finds all matches under `node` like `node.find_all(pattern)` on rayon's thread pool.
+  /// Subtrees longer than a few kilobytes are split among their children recursively,
+  /// so a file with a single large function is searched in parallel too.
+  ///
+  /// Results are in the same pre-order as the sequential `find_all`, regardless of scheduling.
+  pub fn find_all_parallel<'r, D>(&self, node: Node<'r, D>) -> Vec<NodeMatch<'r, D>>
+  where
+    D: Doc<Lang = L> + Sync,
+    <D::Source as Content>::Underlying: Send,
+  {
+    let kinds = self.potential_kinds();
+    self.search_parallel(node, kinds.as_ref())
+  }
+
+  fn search_parallel<'r, D>(
+    &self,
+    node: Node<'r, D>,
+    kinds: Option<&BitSet>,
+  ) -> Vec<NodeMatch<'r, D>>
+  where
+    D: Doc<Lang = L> + Sync,
+    <D::Source as Content>::Underlying: Send,
+  {
+    let may_match = |n: &Node<D>| kinds.map_or(true, |k| k.contains(n.kind_id().into()));
+    if node.range().len() < SPLIT_LEN {
+      let found = node.dfs().filter(may_match);
+      return found.filter_map(|n| self.match_node(n)).collect();
+    }
+    let mut ret: Vec<_> = Some(node.clone())
+      .filter(may_match)
+      .and_then(|n| self.match_node(n))
+      .into_iter()
+      .collect();
+    let children: Vec<_> = node.children().map(Sendable).collect();
+    let found: Vec<_> = children
+      .into_par_iter()
+      .map(|child| Sendable(self.search_parallel(child.0, kinds)))
+      .collect();
+    ret.extend(found.into_iter().flat_map(|f| f.0));
+    ret
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::language::Tsx;
+
+  fn ranges<D: Doc>(matches: &[NodeMatch<D>]) -> Vec<(std::ops::Range<usize>, String)> {
+    matches
+      .iter()
+      .map(|m| {
+        let a = m.get_env().get_match("A").map(|n| n.text().to_string());
+        (m.range(), a.unwrap_or_default())
+      })
+      .collect()
+  }
+
+  #[test]
+  fn test_parallel_same_as_sequential() {
+    let src = r"
+      foo(a);
+      function bar() { foo(b); if (c) { foo(foo(d)) } }
+      class A { m() { return foo(e) } }
+      foo(f);
+    ";
+    let grep = Tsx.ast_grep(src);
+    let pattern = Pattern::new("foo($A)", Tsx);
+    let sequential: Vec<_> = grep.root().find_all(&pattern).collect();
+    let parallel = pattern.find_all_parallel(grep.root());
+    assert_eq!(sequential.len(), 6);
+    assert_eq!(ranges(&sequential), ranges(&parallel));
+  }
+
+  #[test]
+  fn test_parallel_large_subtree() {
+    // one top-level function longer than SPLIT_LEN, split among its statements
+    let calls: String = (0..2000).map(|i| format!("foo(a{i}); ")).collect();
+    let src = format!("function big() {{ {calls} if (x) {{ foo(foo(b)) }} }}\nfoo(c);");
+    assert!(src.len() > 4 * SPLIT_LEN);
+    let grep = Tsx.ast_grep(&src);
+    let pattern = Pattern::new("foo($A)", Tsx);
+    let sequential: Vec<_> = grep.root().find_all(&pattern).collect();
+    let parallel = pattern.find_all_parallel(grep.root());
+    assert_eq!(sequential.len(), 2003);
+    assert_eq!(ranges(&sequential), ranges(&parallel));
+  }
+
+  #[test]
+  fn test_parallel_sub_node() {
+    let grep = Tsx.ast_grep("foo(a); function bar() { foo(b); foo(c) }");
+    let pattern = Pattern::new("foo($A)", Tsx);
+    let func = grep
+      .root()
+      .find("function bar() { $$$ }")
+      .expect("should find");
+    let found = pattern.find_all_parallel(func.get_node().clone());
+    let vars: Vec<_> = ranges(&found).into_iter().map(|r| r.1).collect();
+    assert_eq!(vars, ["b", "c"]);
+    // node itself can match
+    let call = grep.root().find("foo(a)").expect("should find");
+    let found = pattern.find_all_parallel(call.get_node().clone());
+    assert_eq!(found.len(), 1);
+  }
+}
@@ -1,7 +1,14 @@
 use crate::language::Language;
-use crate::match_tree::{extract_var_from_node, match_end_non_recursive, match_node_non_recursive};
-use crate::matcher::{KindMatcher, KindMatcherError, Matcher};
-use crate::meta_var::{MetaVarEnv, MetaVariable};
+use crate::match_tree::{
+  extract_var_from_node, match_all_bindings, match_all_bindings_with_options,
+  match_end_non_recursive, match_node_non_recursive, match_partial, match_span_non_recursive,
+  BacktrackLimitExceeded, MatchOptions, PartialMatch, STANDARD_OPTIONS,
+};
+#[cfg(feature = "trace")]
+use crate::match_tree::{near_misses, trace_match, MatchTrace, NearMiss};
+use crate::matcher::{KindMatcher, KindMatcherError, Matcher, NodeMatch};
+use crate::meta_var::{rollback_env, MetaVarEnv, MetaVariable};
+use crate::ops::{All, Any, Fallbacks};
 use crate::source::TSParseError;
 use crate::{Doc, Node, Root, StrDoc};
 
@@ -9,8 +16,8 @@ use bit_set::BitSet;
 use thiserror::Error;
 
 use std::borrow::Cow;
-use std::collections::HashSet;
-use std::marker::PhantomData;
+use std::collections::{HashMap, HashSet};
+use std::ops::{ControlFlow, Range};
 
 #[derive(Clone)]
 pub enum Pattern<L: Language> {
@@ -23,22 +30,45 @@ pub enum Pattern<L: Language> {
     text: String,
     is_named: bool,
     kind_id: u16,
+    /// field the leaf is assigned to in its parent, e.g. the `operator` of a binary expression
+    field_id: Option<u16>,
   },
   /// Non-Terminal Syntax Nodes are called Internal
   Internal {
     kind_id: u16,
     children: Vec<Pattern<L>>,
-    lang: PhantomData<L>,
+    /// kind id the first candidate child must have to match the first goal child.
+    /// None if the first goal child can match any kind, e.g. meta variable.
+    /// It is only used for fast rejection. Matching children is the source of truth.
+    first_kind: Option<u16>,
+    /// language of the pattern root, None for nested nodes.
+    /// Debug builds check the candidate's language against it once per match.
+    lang: Option<L>,
   },
+  /// A child that matches whether or not the candidate has it, see [`Pattern::with_optional`].
+  Optional(Box<Pattern<L>>),
+  /// A meta variable whose candidate must contain a match of `inner`, see [`Pattern::with_descendant`].
+  Descendant {
+    meta_var: MetaVariable,
+    inner: Box<Pattern<L>>,
+    max_depth: usize,
+  },
+  /// Sibling goals matched zero or more times in a row, see [`Pattern::with_repeat`].
+  Repeat(Vec<Pattern<L>>),
 }
 
 impl<'r, D: Doc> From<Node<'r, D>> for Pattern<D::Lang> {
   fn from(node: Node<'r, D>) -> Self {
-    convert_node_to_pattern(node, None)
+    let lang = node.lang().clone();
+    convert_node_to_pattern(node, None, None).with_root_lang(lang)
   }
 }
 
-fn convert_node_to_pattern<D: Doc>(node: Node<D>, kind: Option<u16>) -> Pattern<D::Lang> {
+pub(super) fn convert_node_to_pattern<D: Doc>(
+  node: Node<D>,
+  kind: Option<u16>,
+  field_id: Option<u16>,
+) -> Pattern<D::Lang> {
   if let Some(meta_var) = extract_var_from_node(&node) {
     Pattern::MetaVar { meta_var, kind }
   } else if node.is_leaf() {
@@ -46,16 +76,41 @@ fn convert_node_to_pattern<D: Doc>(node: Node<D>, kind: Option<u16>) -> Pattern<
       text: node.text().to_string(),
       is_named: node.is_named(),
       kind_id: node.kind_id(),
+      field_id,
     }
   } else {
+    // walk with a cursor to read each child's field in one pass
+    let mut cursor = node.inner.walk();
+    cursor.goto_first_child();
+    let mut children = Vec::with_capacity(node.inner.child_count() as usize);
+    for _ in 0..node.inner.child_count() {
+      let child = Node {
+        inner: cursor.node(),
+        root: node.root,
+      };
+      children.push(convert_node_to_pattern(child, None, cursor.field_id()));
+      cursor.goto_next_sibling();
+    }
+    let first_kind = first_kind_of(&children);
     Pattern::Internal {
       kind_id: node.kind_id(),
-      children: node.children().map(Pattern::from).collect(),
-      lang: PhantomData,
+      children,
+      first_kind,
+      lang: None,
     }
   }
 }
 
+pub(super) fn first_kind_of<L: Language>(children: &[Pattern<L>]) -> Option<u16> {
+  children.first().and_then(|c| match c {
+    Pattern::Terminal { kind_id, .. } | Pattern::Internal { kind_id, .. } => Some(*kind_id),
+    Pattern::MetaVar { .. }
+    | Pattern::Optional(_)
+    | Pattern::Descendant { .. }
+    | Pattern::Repeat(_) => None,
+  })
+}
+
 #[derive(Debug, Error)]
 pub enum PatternError {
   #[error("Tree-Sitter fails to parse the pattern.")]
@@ -68,10 +123,14 @@ pub enum PatternError {
   InvalidKind(#[from] KindMatcherError),
   #[error("Fails to create Contextual pattern: selector `{selector}` matches no node in the context `{context}`.")]
   NoSelectorInContext { context: String, selector: String },
+  #[error("Meta variable `{0}` is used as both a single and a multiple meta variable, e.g. `$A` and `$$$A`.")]
+  ConflictingMetaVar(String),
+  #[error("Glob `{glob}` for the captured text is invalid: {reason}")]
+  InvalidGlob { glob: String, reason: String },
 }
 
 #[inline]
-fn is_single_node(n: &tree_sitter::Node) -> bool {
+pub(super) fn is_single_node(n: &tree_sitter::Node) -> bool {
   match n.child_count() {
     1 => true,
     2 => {
@@ -88,10 +147,34 @@ impl<L: Language> Pattern<L> {
     Self::new(src, lang)
   }
 
+  /// Stores the language at the pattern root. Nested nodes do not carry it.
+  pub(super) fn with_root_lang(mut self, root_lang: L) -> Self {
+    if let Self::Internal { lang, .. } = &mut self {
+      *lang = Some(root_lang);
+    }
+    self
+  }
+
+  /// Kind ids of different grammars do not align, so matching a candidate from another language
+  /// would silently fail. Debug builds panic instead. Patterns whose root is a token are not checked.
+  fn debug_check_lang<D: Doc<Lang = L>>(&self, candidate: &Node<D>) {
+    if let Self::Internal {
+      lang: Some(lang), ..
+    } = self
+    {
+      debug_assert!(
+        lang.get_ts_language() == candidate.lang().get_ts_language(),
+        "pattern and candidate must be parsed by the same language"
+      );
+    }
+  }
+
   pub fn fixed_string(&self) -> Cow<str> {
     match self {
       Self::Terminal { text, .. } => Cow::Borrowed(text),
-      Self::MetaVar { .. } => Cow::Borrowed(""),
+      Self::MetaVar { .. } | Self::Optional(_) | Self::Repeat(_) => Cow::Borrowed(""),
+      // the descendant's text is part of the candidate's
+      Self::Descendant { inner, .. } => inner.fixed_string(),
       Self::Internal { children, .. } => {
         children
           .iter()
@@ -107,6 +190,204 @@ impl<L: Language> Pattern<L> {
     }
   }
 
+  /// Turn named leaf nodes whose text is `wildcard`, e.g. `_`, into anonymous single node wildcards.
+  /// A wildcard matches exactly one named node of any kind and binds nothing, like `$_`.
+  /// For example, `foo(_, $B)` with wildcard `_` matches `foo(x, y)` and only captures `$B`.
+  pub fn with_wildcard(self, wildcard: &str) -> Self {
+    match self {
+      Self::Terminal {
+        text,
+        is_named: true,
+        ..
+      } if text == wildcard => Self::MetaVar {
+        meta_var: MetaVariable::Dropped(true),
+        kind: None,
+      },
+      Self::Internal {
+        kind_id,
+        children,
+        lang,
+        ..
+      } => {
+        let children: Vec<_> = children
+          .into_iter()
+          .map(|c| c.with_wildcard(wildcard))
+          .collect();
+        Self::Internal {
+          kind_id,
+          first_kind: first_kind_of(&children),
+          children,
+          lang,
+        }
+      }
+      Self::Optional(p) => Self::Optional(Box::new(p.with_wildcard(wildcard))),
+      Self::Descendant {
+        meta_var,
+        inner,
+        max_depth,
+      } => Self::Descendant {
+        meta_var,
+        inner: Box::new(inner.with_wildcard(wildcard)),
+        max_depth,
+      },
+      p => p,
+    }
+  }
+
+  /// Mark the meta variable `$name` optional, together with its separating unnamed nodes.
+  /// An optional child matches if the candidate has it and is skipped otherwise,
+  /// so `let $A = $B` with optional `B` matches both `let a = 1` and `let a`.
+  /// `$B` is absent from the env if it is skipped.
+  pub fn with_optional(self, name: &str) -> Self {
+    let Self::Internal {
+      kind_id,
+      children,
+      lang,
+      ..
+    } = self
+    else {
+      return self;
+    };
+    let mut children: Vec<_> = children
+      .into_iter()
+      .map(|c| c.with_optional(name))
+      .collect();
+    let is_target = |p: &Self| match p {
+      Self::MetaVar { meta_var, .. } => meta_var_name(meta_var) == Some(name),
+      _ => false,
+    };
+    if let Some(i) = children.iter().position(is_target) {
+      // the separator goes with the child, before it unless the child leads the list
+      let before = children[..i]
+        .iter()
+        .rev()
+        .take_while(|c| c.is_trivial())
+        .count();
+      let after = children[i + 1..]
+        .iter()
+        .take_while(|c| c.is_trivial())
+        .count();
+      let (start, end) = if before < i {
+        (i - before, i)
+      } else if i + after + 1 < children.len() {
+        (i, i + after)
+      } else {
+        (i, i)
+      };
+      for child in &mut children[start..=end] {
+        let inner = std::mem::replace(child, Self::dummy());
+        *child = Self::Optional(Box::new(inner));
+      }
+    }
+    Self::Internal {
+      kind_id,
+      first_kind: first_kind_of(&children),
+      children,
+      lang,
+    }
+  }
+
+  /// Require the node captured by `$name` to contain a match of `inner`, at most `max_depth` levels below it.
+  /// It embeds a bounded descendant search in the pattern. For example, `const $F = () => $BODY`
+  /// with descendant `foo($X)` for `BODY` matches an arrow function calling `foo` anywhere in its body.
+  /// `$name` binds the outer node and meta variables of `inner` bind inside it.
+  /// The first descendant in pre-order that matches is used. The node itself is not a descendant.
+  pub fn with_descendant(self, name: &str, inner: Self, max_depth: usize) -> Self {
+    match self {
+      Self::MetaVar { meta_var, .. } if meta_var_name(&meta_var) == Some(name) => {
+        Self::Descendant {
+          meta_var,
+          inner: Box::new(inner),
+          max_depth,
+        }
+      }
+      Self::Internal {
+        kind_id,
+        children,
+        lang,
+        ..
+      } => {
+        let children: Vec<_> = children
+          .into_iter()
+          .map(|c| c.with_descendant(name, inner.clone(), max_depth))
+          .collect();
+        Self::Internal {
+          kind_id,
+          first_kind: first_kind_of(&children),
+          children,
+          lang,
+        }
+      }
+      Self::Optional(p) => Self::Optional(Box::new(p.with_descendant(name, inner, max_depth))),
+      p => p,
+    }
+  }
+
+  /// Replace the multi meta variable `$$$name` with `group`, sibling goals repeated zero or more times.
+  /// Each repetition matches the group goals in order, skipping unnamed candidates like commas around them.
+  /// Captures of the group accumulate over repetitions: `$A` in the group binds the list of
+  /// every repetition's `$A`, like a multi meta variable. So `({ $$$PAIRS })` with group `$A: $B`
+  /// matches `({ a: 1, b: 2 })` and binds `$A` to `a, b` and `$B` to `1, 2`.
+  /// Group meta variables are local to each repetition, so `$A` may differ between repetitions.
+  /// Repetitions are greedy and never backtracked, and the group cannot contain an ellipsis.
+  pub fn with_repeat(self, name: &str, group: Vec<Self>) -> Self {
+    match self {
+      Self::MetaVar {
+        meta_var: MetaVariable::MultiCapture(n) | MetaVariable::LineContiguousCapture(n),
+        ..
+      } if n == name => Self::Repeat(group),
+      Self::Internal {
+        kind_id,
+        children,
+        lang,
+        ..
+      } => {
+        let children: Vec<_> = children
+          .into_iter()
+          .map(|c| c.with_repeat(name, group.clone()))
+          .collect();
+        Self::Internal {
+          kind_id,
+          first_kind: first_kind_of(&children),
+          children,
+          lang,
+        }
+      }
+      p => p,
+    }
+  }
+
+  /// A matcher trying `patterns` in order at the same node, returning the first match.
+  /// Captures come from the alternative that matched, bindings of failed alternatives are rolled back.
+  /// It is an [`Any`] of patterns, so candidates are prefiltered by the union of their kinds.
+  pub fn any_of(patterns: Vec<Self>) -> Any<L, Self> {
+    Any::new(patterns)
+  }
+
+  /// Like [`Pattern::any_of`] for a construct with several common forms, e.g. a function declaration
+  /// and an arrow function: `primary` is tried first, then each of `fallbacks` in order at the same node.
+  /// The match records which form matched, see [`NodeMatch::variant_index`]: 0 for `primary`
+  /// and `i + 1` for `fallbacks[i]`. It is a [`Fallbacks`] of patterns.
+  pub fn with_fallbacks(primary: Self, fallbacks: Vec<Self>) -> Fallbacks<L, Self> {
+    Fallbacks::new(primary, fallbacks)
+  }
+
+  /// A matcher requiring every pattern to match the same node, with captures merged into one env.
+  /// A variable captured by several patterns must bind the same code in each, as checked for
+  /// `$A` appearing twice in one pattern, otherwise the match fails like merging envs with
+  /// `MergePolicy::RejectConflicts`. Bindings of a failed match are rolled back. It is an [`All`] of patterns.
+  pub fn all_of(patterns: Vec<Self>) -> All<L, Self> {
+    All::new(patterns)
+  }
+
+  /// Placeholder used when moving a child out of a pattern.
+  fn dummy() -> Self {
+    Self::MetaVar {
+      meta_var: MetaVariable::Multiple,
+      kind: None,
+    }
+  }
+
   pub fn has_error(&self) -> bool {
     let kind = match self {
       Pattern::Terminal { kind_id, .. } => *kind_id,
@@ -116,14 +397,36 @@ impl<L: Language> Pattern<L> {
         ..
       } => *kind_id,
       Pattern::MetaVar { kind: None, .. } => return false,
+      Pattern::Optional(p) => return p.has_error(),
+      Pattern::Descendant { inner, .. } => return inner.has_error(),
+      Pattern::Repeat(group) => return group.iter().any(Self::has_error),
     };
     KindMatcher::<L>::from_id(kind).is_error_matcher()
   }
 
-  // for skipping trivial nodes in goal after ellipsis
+  /// Whether the pattern always matches exactly one node: a terminal, a single meta variable like `$A`
+  /// or `$$_`, or a meta variable whose descendant is also matched, see [`Pattern::with_descendant`].
+  /// Internal patterns match a subtree, and ellipses, optional and repeated goals may match zero or many nodes.
+  pub fn is_single_node(&self) -> bool {
+    match self {
+      Pattern::Terminal { .. } | Pattern::Descendant { .. } => true,
+      Pattern::MetaVar { meta_var, .. } => {
+        matches!(
+          meta_var,
+          MetaVariable::Capture(..) | MetaVariable::Dropped(_)
+        )
+      }
+      Pattern::Internal { .. } | Pattern::Optional(_) | Pattern::Repeat(_) => false,
+    }
+  }
+
+  // for skipping trivial nodes in goal after ellipsis.
+  // an unnamed leaf assigned to a field, e.g. the operator in `$$$A + b`, is a constraint and not trivial
   pub fn is_trivial(&self) -> bool {
     match self {
-      Pattern::Terminal { is_named, .. } => !*is_named,
+      Pattern::Terminal {
+        is_named, field_id, ..
+      } => !*is_named && field_id.is_none(),
       _ => false,
     }
   }
@@ -135,18 +438,213 @@ impl<L: Language> Pattern<L> {
     collect_vars(self, &mut vars);
     vars
   }
+
+  /// Kind ids of the nodes the pattern root can match, e.g. to index patterns by kind and
+  /// prefilter candidates before matching. Returns None if the root is a meta variable
+  /// matching any kind. It is [`Matcher::potential_kinds`] as a `HashSet`.
+  pub fn potential_kind_ids(&self) -> Option<HashSet<u16>> {
+    let kinds = Matcher::<L>::potential_kinds(self)?;
+    Some(kinds.iter().map(|k| k as u16).collect())
+  }
+
+  /// Names of the capturing meta variables in the pattern, in order of first appearance.
+  /// Anonymous ones like `$_` and `$$$` are excluded, so `foo($A, $$$B, $_)` gives `["A", "B"]`.
+  pub fn meta_var_names(&self) -> Vec<String> {
+    self
+      .meta_vars()
+      .into_iter()
+      .filter_map(|mv| meta_var_name(&mv).map(String::from))
+      .collect()
+  }
+
+  /// Like [`Pattern::meta_var_names`] but returns the meta variables, which tell single captures
+  /// like `MetaVariable::Capture` from multiple ones like `MetaVariable::MultiCapture`.
+  pub fn meta_vars(&self) -> Vec<MetaVariable> {
+    let mut vars = vec![];
+    collect_meta_vars(self, &mut vars);
+    vars
+  }
+}
+
+/// Upper bounds of single and multiple bindings one match inserts, one per capturing occurrence.
+pub(crate) fn capture_counts<L: Language>(p: &Pattern<L>) -> (usize, usize) {
+  let count = |meta_var: &MetaVariable| match meta_var {
+    MetaVariable::Capture(..) => (1, 0),
+    MetaVariable::MultiCapture(_) | MetaVariable::LineContiguousCapture(_) => (0, 1),
+    MetaVariable::Dropped(_) | MetaVariable::Multiple => (0, 0),
+  };
+  match p {
+    Pattern::MetaVar { meta_var, .. } => count(meta_var),
+    Pattern::Terminal { .. } => (0, 0),
+    Pattern::Internal { children, .. } => children
+      .iter()
+      .map(capture_counts)
+      .fold((0, 0), |(single, multi), (s, m)| (single + s, multi + m)),
+    Pattern::Optional(p) => capture_counts(p),
+    Pattern::Descendant {
+      meta_var, inner, ..
+    } => {
+      let (single, multi) = capture_counts(inner);
+      let (s, m) = count(meta_var);
+      (single + s, multi + m)
+    }
+    // every capture of the group is bound as a list
+    Pattern::Repeat(group) => {
+      let all = group.iter().map(capture_counts);
+      (0, all.map(|(s, m)| s + m).sum())
+    }
+  }
 }
 
 fn meta_var_name(meta_var: &MetaVariable) -> Option<&str> {
   use MetaVariable as MV;
   match meta_var {
     MV::Capture(name, _) => Some(name),
-    MV::MultiCapture(name) => Some(name),
+    MV::MultiCapture(name) | MV::LineContiguousCapture(name) => Some(name),
     MV::Dropped(_) => None,
     MV::Multiple => None,
   }
 }
 
+/// Returns true if a meta variable name occurs more than once, e.g. `$A == $A`.
+fn has_repeated_var<'p, L: Language>(p: &'p Pattern<L>, vars: &mut HashSet<&'p str>) -> bool {
+  match p {
+    Pattern::MetaVar { meta_var, .. } => {
+      matches!(meta_var_name(meta_var), Some(n) if !vars.insert(n))
+    }
+    Pattern::Terminal { .. } => false,
+    Pattern::Internal { children, .. } => children.iter().any(|c| has_repeated_var(c, vars)),
+    Pattern::Optional(p) => has_repeated_var(p, vars),
+    Pattern::Descendant {
+      meta_var, inner, ..
+    } => {
+      matches!(meta_var_name(meta_var), Some(n) if !vars.insert(n)) || has_repeated_var(inner, vars)
+    }
+    Pattern::Repeat(group) => group.iter().any(|g| has_repeated_var(g, vars)),
+  }
+}
+
+/// Turn every capturing meta variable into its anonymous counterpart.
+fn drop_captures<L: Language>(p: &Pattern<L>) -> Pattern<L> {
+  use MetaVariable as MV;
+  match p {
+    Pattern::MetaVar { meta_var, kind } => {
+      let meta_var = match meta_var {
+        MV::Capture(_, named) => MV::Dropped(*named),
+        MV::MultiCapture(_) | MV::LineContiguousCapture(_) => MV::Multiple,
+        mv => mv.clone(),
+      };
+      Pattern::MetaVar {
+        meta_var,
+        kind: *kind,
+      }
+    }
+    Pattern::Terminal { .. } => p.clone(),
+    Pattern::Optional(p) => Pattern::Optional(Box::new(drop_captures(p))),
+    Pattern::Descendant {
+      meta_var,
+      inner,
+      max_depth,
+    } => Pattern::Descendant {
+      meta_var: match meta_var {
+        MV::Capture(_, named) => MV::Dropped(*named),
+        mv => mv.clone(),
+      },
+      inner: Box::new(drop_captures(inner)),
+      max_depth: *max_depth,
+    },
+    Pattern::Repeat(group) => Pattern::Repeat(group.iter().map(drop_captures).collect()),
+    Pattern::Internal {
+      kind_id,
+      children,
+      first_kind,
+      lang,
+    } => Pattern::Internal {
+      kind_id: *kind_id,
+      children: children.iter().map(drop_captures).collect(),
+      first_kind: *first_kind,
+      lang: lang.clone(),
+    },
+  }
+}
+
+fn collect_meta_vars<L: Language>(p: &Pattern<L>, vars: &mut Vec<MetaVariable>) {
+  let mut push = |meta_var: &MetaVariable| {
+    let Some(name) = meta_var_name(meta_var) else {
+      return;
+    };
+    if !vars.iter().any(|v| meta_var_name(v) == Some(name)) {
+      vars.push(meta_var.clone());
+    }
+  };
+  match p {
+    Pattern::MetaVar { meta_var, .. } => push(meta_var),
+    Pattern::Terminal { .. } => (),
+    Pattern::Internal { children, .. } => {
+      for c in children {
+        collect_meta_vars(c, vars);
+      }
+    }
+    Pattern::Optional(p) => collect_meta_vars(p, vars),
+    Pattern::Descendant {
+      meta_var, inner, ..
+    } => {
+      push(meta_var);
+      collect_meta_vars(inner, vars);
+    }
+    // group captures are bound as lists
+    Pattern::Repeat(group) => {
+      for name in group.iter().flat_map(Pattern::meta_var_names) {
+        push(&MetaVariable::MultiCapture(name));
+      }
+    }
+  }
+}
+
+/// Reject a name used by both single and multiple meta variables, e.g. `$A` and `$$$A`.
+/// `vars` maps each name seen so far to whether it is a multiple meta variable.
+fn check_var_kinds<'p, L: Language>(
+  p: &'p Pattern<L>,
+  vars: &mut HashMap<&'p str, bool>,
+) -> Result<(), PatternError> {
+  let mut check = |meta_var: &'p MetaVariable| {
+    let Some(name) = meta_var_name(meta_var) else {
+      return Ok(());
+    };
+    let multi = matches!(
+      meta_var,
+      MetaVariable::MultiCapture(_) | MetaVariable::LineContiguousCapture(_)
+    );
+    match vars.insert(name, multi) {
+      Some(seen) if seen != multi => Err(PatternError::ConflictingMetaVar(name.into())),
+      _ => Ok(()),
+    }
+  };
+  match p {
+    Pattern::MetaVar { meta_var, .. } => check(meta_var),
+    Pattern::Terminal { .. } => Ok(()),
+    Pattern::Internal { children, .. } => {
+      children.iter().try_for_each(|c| check_var_kinds(c, vars))
+    }
+    Pattern::Optional(p) => check_var_kinds(p, vars),
+    Pattern::Descendant {
+      meta_var, inner, ..
+    } => {
+      check(meta_var)?;
+      check_var_kinds(inner, vars)
+    }
+    // group captures are bound as lists, so they conflict with single meta variables outside
+    Pattern::Repeat(group) => {
+      for name in group.iter().flat_map(Pattern::defined_vars) {
+        if vars.insert(name, true) == Some(false) {
+          return Err(PatternError::ConflictingMetaVar(name.into()));
+        }
+      }
+      Ok(())
+    }
+  }
+}
+
 fn collect_vars<'p, L: Language>(p: &'p Pattern<L>, vars: &mut HashSet<&'p str>) {
   match p {
     Pattern::MetaVar { meta_var, .. } => {
@@ -162,6 +660,20 @@ fn collect_vars<'p, L: Language>(p: &'p Pattern<L>, vars: &mut HashSet<&'p str>)
         collect_vars(c, vars);
       }
     }
+    Pattern::Optional(p) => collect_vars(p, vars),
+    Pattern::Descendant {
+      meta_var, inner, ..
+    } => {
+      if let Some(name) = meta_var_name(meta_var) {
+        vars.insert(name);
+      }
+      collect_vars(inner, vars);
+    }
+    Pattern::Repeat(group) => {
+      for g in group {
+        collect_vars(g, vars);
+      }
+    }
   }
 }
 
@@ -169,6 +681,26 @@ impl<L: Language> Pattern<L> {
   pub fn try_new(src: &str, lang: L) -> Result<Self, PatternError> {
     let processed = lang.pre_process_pattern(src);
     let root = Root::<StrDoc<L>>::try_new(&processed, lang)?;
+    Self::build_from_root(&root, src)
+  }
+
+  pub fn new(src: &str, lang: L) -> Self {
+    Self::try_new(src, lang).unwrap()
+  }
+
+  /// Builds the pattern from an already parsed tree, saving the parse in [`Pattern::try_new`].
+  /// The tree must be parsed from the pre-processed source, see [`Language::pre_process_pattern`],
+  /// for meta variables to be recognized.
+  pub fn try_from_root<D: Doc<Lang = L>>(root: &Root<D>) -> Result<Self, PatternError> {
+    let src = root.root().text().to_string();
+    Self::build_from_root(root, &src)
+  }
+
+  pub fn from_root<D: Doc<Lang = L>>(root: Root<D>) -> Self {
+    Self::try_from_root(&root).unwrap()
+  }
+
+  fn build_from_root<D: Doc<Lang = L>>(root: &Root<D>, src: &str) -> Result<Self, PatternError> {
     let goal = root.root();
     if goal.inner.child_count() == 0 {
       return Err(PatternError::NoContent(src.into()));
@@ -176,12 +708,19 @@ impl<L: Language> Pattern<L> {
     if !is_single_node(&goal.inner) {
       return Err(PatternError::MultipleNode(src.into()));
     }
-    let node = Self::single_matcher(&root);
-    Ok(Self::from(node))
+    let node = Self::single_matcher(root);
+    let lang = node.lang().clone();
+    let pattern = convert_node_to_pattern(node, None, None).with_root_lang(lang);
+    check_var_kinds(&pattern, &mut HashMap::new())?;
+    Ok(pattern)
   }
 
-  pub fn new(src: &str, lang: L) -> Self {
-    Self::try_new(src, lang).unwrap()
+  /// Builds a pattern from a concrete example whose holes are marked by `hole`, e.g. `__`,
+  /// so users can write `foo(__)` instead of learning meta variable syntax.
+  /// Every named leaf whose text is `hole` becomes an anonymous single node wildcard, see [`Pattern::with_wildcard`].
+  /// Holes bind nothing. Meta variables written in the example still work.
+  pub fn from_example(src: &str, hole: &str, lang: L) -> Result<Self, PatternError> {
+    Ok(Self::try_new(src, lang)?.with_wildcard(hole))
   }
 
   pub fn contextual(context: &str, selector: &str, lang: L) -> Result<Self, PatternError> {
@@ -195,16 +734,206 @@ impl<L: Language> Pattern<L> {
         selector: selector.into(),
       });
     };
-    Ok(convert_node_to_pattern(
-      node.get_node().clone(),
-      Some(node.kind_id()),
-    ))
+    let pattern = convert_node_to_pattern(node.get_node().clone(), Some(node.kind_id()), None);
+    let pattern = pattern.with_root_lang(goal.lang().clone());
+    check_var_kinds(&pattern, &mut HashMap::new())?;
+    Ok(pattern)
   }
   pub fn doc(doc: StrDoc<L>) -> Self {
     let root = Root::doc(doc);
     Self::from(root.root())
   }
-  fn single_matcher<D: Doc>(root: &Root<D>) -> Node<D> {
+  /// Returns every distinct MetaVarEnv under which the pattern matches the node.
+  /// Matching normally commits to the first split point an ellipsis finds, so `foo($$$A, $$$B)`
+  /// binds one split of `foo(a, b)`. This enumerates all three splits instead.
+  /// Returns an empty Vec if the pattern does not match.
+  pub fn match_all_bindings<'tree, D: Doc<Lang = L>>(
+    &self,
+    node: Node<'tree, D>,
+  ) -> Vec<MetaVarEnv<'tree, D>> {
+    match_all_bindings(self, node)
+  }
+  /// Matches the pattern against the node once and records every decision made,
+  /// such as kind and text comparisons, skipped nodes, ellipsis consumption and bindings.
+  /// Useful for debugging why a pattern does or does not match. Print it for an indented tree.
+  /// Requires the `trace` feature.
+  #[cfg(feature = "trace")]
+  pub fn trace_match<D: Doc<Lang = L>>(&self, node: Node<D>) -> MatchTrace {
+    trace_match(self, node)
+  }
+  /// Finds nodes under `root` that almost match: same kind as the pattern but failing deeper.
+  /// Each comes with its trace and failing sub-goal, closest first. Useful when a rule matches nothing.
+  /// Requires the `trace` feature.
+  #[cfg(feature = "trace")]
+  pub fn near_misses<'tree, D: Doc<Lang = L>>(
+    &self,
+    root: Node<'tree, D>,
+  ) -> Vec<NearMiss<'tree, D>> {
+    near_misses(self, root)
+  }
+  /// Scores how much of the pattern `node` matches instead of stopping at the first failure,
+  /// with the sub-goals that failed. Useful to rank candidate locations by closeness.
+  pub fn match_partial<D: Doc<Lang = L>>(&self, node: Node<D>) -> PartialMatch {
+    match_partial(self, node)
+  }
+  /// Returns whether the pattern matches `node` itself. Descendants of `node` are not searched.
+  ///
+  /// ```
+  /// # use ast_grep_core::language::{Language, TSLanguage};
+  /// # use ast_grep_core::Pattern;
+  /// # #[derive(Clone)]
+  /// # struct Tsx;
+  /// # impl Language for Tsx {
+  /// #   fn get_ts_language(&self) -> TSLanguage {
+  /// #     tree_sitter_typescript::language_tsx().into()
+  /// #   }
+  /// # }
+  /// let grep = Tsx.ast_grep("function f() { return 1 }");
+  /// let root = grep.root();
+  /// let node = root.dfs().find(|n| n.kind() == "return_statement").unwrap();
+  /// assert!(Pattern::new("return $A", Tsx).matches(&node));
+  /// assert!(!Pattern::new("return $A", Tsx).matches(&root));
+  /// ```
+  pub fn matches<D: Doc<Lang = L>>(&self, node: &Node<D>) -> bool {
+    let mut env = Cow::Owned(MetaVarEnv::new());
+    match_node_non_recursive(self, node.clone(), &mut env, &STANDARD_OPTIONS, None).is_some()
+  }
+  /// Returns the byte range the pattern matches at `node` itself, or None if it does not match.
+  /// The range ends at the last candidate child the pattern covers, so trailing trivia like
+  /// the comment in `return 123 /* trivia */` is excluded from the span of `return $A`.
+  pub fn match_span<D: Doc<Lang = L>>(&self, node: Node<D>) -> Option<Range<usize>> {
+    match_span_non_recursive(self, node, &STANDARD_OPTIONS)
+  }
+  /// Finds the first match in `node` or its descendants and returns the text captured by `$name`.
+  /// Returns None if nothing matches or `name` is not captured by a single meta variable.
+  ///
+  /// ```
+  /// # use ast_grep_core::language::{Language, TSLanguage};
+  /// # use ast_grep_core::Pattern;
+  /// # #[derive(Clone)]
+  /// # struct Tsx;
+  /// # impl Language for Tsx {
+  /// #   fn get_ts_language(&self) -> TSLanguage {
+  /// #     tree_sitter_typescript::language_tsx().into()
+  /// #   }
+  /// # }
+  /// let grep = Tsx.ast_grep("function f() { return 42 }");
+  /// let pattern = Pattern::new("return $A", Tsx);
+  /// assert_eq!(pattern.capture_text(grep.root(), "A").as_deref(), Some("42"));
+  /// assert_eq!(pattern.capture_text(grep.root(), "B"), None);
+  /// ```
+  pub fn capture_text<D: Doc<Lang = L>>(&self, node: Node<D>, name: &str) -> Option<String> {
+    let captured = self.capture_node(node, name)?;
+    Some(captured.text().to_string())
+  }
+  /// Like [`Pattern::capture_text`] but returns the captured node.
+  pub fn capture_node<'tree, D: Doc<Lang = L>>(
+    &self,
+    node: Node<'tree, D>,
+    name: &str,
+  ) -> Option<Node<'tree, D>> {
+    let found = self.find_node(node)?;
+    found.get_env().get_match(name).cloned()
+  }
+  /// Counts matches under `node`, the same as `node.find_all(self).count()` but without recording captures.
+  /// Every meta variable is matched like its anonymous form, e.g. `$A` like `$_` and `$$$A` like `$$$`.
+  /// A pattern reusing a meta variable, e.g. `$A == $A`, needs bindings to check consistency
+  /// and falls back to `find_all`.
+  pub fn count_matches<D: Doc<Lang = L>>(&self, node: Node<D>) -> usize {
+    if has_repeated_var(self, &mut HashSet::new()) {
+      return node.find_all(self).count();
+    }
+    node.find_all(drop_captures(self)).count()
+  }
+  /// Calls `f` with each match under `node`, in the same pre-order as `find_all`, without collecting them.
+  /// Every match gets a fresh env. Returning `ControlFlow::Break` from `f` stops the walk early,
+  /// and the break is returned. Returns `ControlFlow::Continue` if the whole tree was visited.
+  pub fn for_each_match<'tree, D, F>(&self, node: Node<'tree, D>, mut f: F) -> ControlFlow<()>
+  where
+    D: Doc<Lang = L>,
+    F: FnMut(NodeMatch<'tree, D>) -> ControlFlow<()>,
+  {
+    for matched in node.find_all(self) {
+      f(matched)?;
+    }
+    ControlFlow::Continue(())
+  }
+  /// Returns the `n`th match under `node`, counting from zero like `Iterator::nth`.
+  /// Matches are ordered like `find_all`: pre-order, i.e. by the start of their range
+  /// with an enclosing match before the matches nested in it. The walk stops once it is found.
+  pub fn find_nth<'tree, D: Doc<Lang = L>>(
+    &self,
+    node: Node<'tree, D>,
+    n: usize,
+  ) -> Option<NodeMatch<'tree, D>> {
+    let mut seen = 0;
+    let mut found = None;
+    let _ = self.for_each_match(node, |matched| {
+      if seen < n {
+        seen += 1;
+        return ControlFlow::Continue(());
+      }
+      found = Some(matched);
+      ControlFlow::Break(())
+    });
+    found
+  }
+  /// Finds all matches under `node` whose matched node lies fully inside the byte `range`,
+  /// e.g. an editor selection, in the same pre-order as `find_all`. A node partially overlapping
+  /// the range is not matched itself, but its children are visited. Subtrees entirely outside
+  /// the range are skipped without being visited, so a small range in a big file is cheap.
+  pub fn find_all_in_range<'tree, D: Doc<Lang = L>>(
+    &self,
+    node: Node<'tree, D>,
+    range: Range<usize>,
+  ) -> Vec<NodeMatch<'tree, D>> {
+    let mut found = vec![];
+    let mut stack = vec![node];
+    while let Some(node) = stack.pop() {
+      let r = node.range();
+      if range.start <= r.start && r.end <= range.end {
+        found.extend(self.match_node(node.clone()));
+      } else if r.end <= range.start || range.end <= r.start {
+        continue;
+      }
+      // push in reverse so the first child is visited next
+      let children: Vec<_> = node.children().collect();
+      stack.extend(children.into_iter().rev());
+    }
+    found
+  }
+  /// Matches the pattern against the node like `match_node` but consults `validator` before
+  /// binding each meta variable, with the variable name and the candidate node.
+  /// If the validator returns false, that capture fails, e.g. to bind only snake_case identifiers.
+  pub fn match_node_with_validator<'tree, D: Doc<Lang = L>>(
+    &self,
+    node: Node<'tree, D>,
+    validator: &dyn Fn(&str, &Node<'tree, D>) -> bool,
+  ) -> Option<NodeMatch<'tree, D>> {
+    let mut env = Cow::Owned(MetaVarEnv::new());
+    let node = match_node_non_recursive(self, node, &mut env, &STANDARD_OPTIONS, Some(validator))?;
+    Some(NodeMatch::new(node, env.into_owned()))
+  }
+  /// Matches the pattern at each of the given nodes, without searching their descendants,
+  /// and yields the successful matches in order.
+  /// This reuses nodes found by other means, e.g. captures of a tree-sitter `Query`.
+  /// The nodes must come from trees that outlive `'tree`, and the returned iterator
+  /// borrows both the pattern and `nodes`.
+  pub fn match_candidates<'a, 'tree, D>(
+    &'a self,
+    nodes: impl Iterator<Item = Node<'tree, D>> + 'a,
+  ) -> impl Iterator<Item = NodeMatch<'tree, D>> + 'a
+  where
+    D: Doc<Lang = L> + 'tree,
+    'tree: 'a,
+  {
+    nodes.filter_map(move |node| {
+      let mut env = Cow::Owned(MetaVarEnv::new());
+      let node = match_node_non_recursive(self, node, &mut env, &STANDARD_OPTIONS, None)?;
+      Some(NodeMatch::new(node, env.into_owned()))
+    })
+  }
+  pub(super) fn single_matcher<D: Doc>(root: &Root<D>) -> Node<D> {
     // debug_assert!(matches!(self.style, PatternStyle::Single));
     let node = root.root();
     let mut inner = node.inner;
@@ -221,7 +950,30 @@ impl<L: Language> Matcher<L> for Pattern<L> {
     node: Node<'tree, D>,
     env: &mut Cow<MetaVarEnv<'tree, D>>,
   ) -> Option<Node<'tree, D>> {
-    match_node_non_recursive(self, node, env)
+    self.debug_check_lang(&node);
+    match_node_non_recursive(self, node, env, &STANDARD_OPTIONS, None)
+  }
+
+  fn match_node<'tree, D: Doc<Lang = L>>(
+    &self,
+    node: Node<'tree, D>,
+  ) -> Option<NodeMatch<'tree, D>> {
+    let mut env = Cow::Owned(MetaVarEnv::with_capacity_for(self));
+    let node = self.match_node_with_env(node, &mut env)?;
+    Some(NodeMatch::new(node, env.into_owned()))
+  }
+
+  fn find_node<'tree, D: Doc<Lang = L>>(
+    &self,
+    node: Node<'tree, D>,
+  ) -> Option<NodeMatch<'tree, D>> {
+    // all descendants share the root's language
+    self.debug_check_lang(&node);
+    node.dfs().find_map(|n| {
+      let mut env = Cow::Owned(MetaVarEnv::with_capacity_for(self));
+      let n = match_node_non_recursive(self, n, &mut env, &STANDARD_OPTIONS, None)?;
+      Some(NodeMatch::new(n, env.into_owned()))
+    })
   }
 
   fn potential_kinds(&self) -> Option<bit_set::BitSet> {
@@ -229,6 +981,7 @@ impl<L: Language> Matcher<L> for Pattern<L> {
       Self::Terminal { kind_id, .. } => *kind_id,
       Self::MetaVar { kind, .. } => (*kind)?,
       Self::Internal { kind_id, .. } => *kind_id,
+      Self::Optional(_) | Self::Descendant { .. } | Self::Repeat(_) => return None,
     };
     let mut kinds = BitSet::new();
     kinds.insert(kind.into());
@@ -237,7 +990,83 @@ impl<L: Language> Matcher<L> for Pattern<L> {
 
   fn get_match_len<D: Doc<Lang = L>>(&self, node: Node<D>) -> Option<usize> {
     let start = node.range().start;
-    let end = match_end_non_recursive(self, node)?;
+    let end = match_end_non_recursive(self, node, &STANDARD_OPTIONS)?;
+    Some(end - start)
+  }
+}
+
+/// A [`Pattern`] matched with customized [`MatchOptions`].
+#[derive(Clone)]
+pub struct ConfiguredPattern<L: Language> {
+  pattern: Pattern<L>,
+  options: MatchOptions,
+}
+
+impl<L: Language> Pattern<L> {
+  pub fn with_options(self, options: MatchOptions) -> ConfiguredPattern<L> {
+    ConfiguredPattern {
+      pattern: self,
+      options,
+    }
+  }
+}
+
+impl<L: Language> ConfiguredPattern<L> {
+  pub fn pattern(&self) -> &Pattern<L> {
+    &self.pattern
+  }
+  pub fn options(&self) -> &MatchOptions {
+    &self.options
+  }
+  /// Like [`Pattern::match_all_bindings`] but gives up with an error once the steps allowed by
+  /// [`MatchOptions::max_backtrack_steps`] are taken, instead of hanging on a pathological pattern.
+  pub fn match_all_bindings<'tree, D: Doc<Lang = L>>(
+    &self,
+    node: Node<'tree, D>,
+  ) -> Result<Vec<MetaVarEnv<'tree, D>>, BacktrackLimitExceeded> {
+    match_all_bindings_with_options(&self.pattern, node, &self.options)
+  }
+}
+
+impl<L: Language> Matcher<L> for ConfiguredPattern<L> {
+  fn match_node_with_env<'tree, D: Doc<Lang = L>>(
+    &self,
+    node: Node<'tree, D>,
+    env: &mut Cow<MetaVarEnv<'tree, D>>,
+  ) -> Option<Node<'tree, D>> {
+    self.options.apply_var_aliases(env);
+    let checkpoint = env.checkpoint();
+    let matched = match_node_non_recursive(&self.pattern, node, env, &self.options, None)?;
+    if !self
+      .options
+      .captures_within_depth(&self.pattern, &matched, env)
+      || !self.options.captures_distinct(env)
+      || self.options.insert_match(&matched, env).is_none()
+    {
+      rollback_env(env, checkpoint);
+      return None;
+    }
+    Some(matched)
+  }
+
+  fn match_node<'tree, D: Doc<Lang = L>>(
+    &self,
+    node: Node<'tree, D>,
+  ) -> Option<NodeMatch<'tree, D>> {
+    let mut env = Cow::Owned(MetaVarEnv::with_capacity_for(&self.pattern));
+    let node = self.match_node_with_env(node, &mut env)?;
+    Some(NodeMatch::new(node, env.into_owned()))
+  }
+
+  fn potential_kinds(&self) -> Option<BitSet> {
+    let mut kinds = self.pattern.potential_kinds()?;
+    self.options.add_kind_aliases(&mut kinds);
+    Some(kinds)
+  }
+
+  fn get_match_len<D: Doc<Lang = L>>(&self, node: Node<D>) -> Option<usize> {
+    let start = node.range().start;
+    let end = match_end_non_recursive(&self.pattern, node, &self.options)?;
     Some(end - start)
   }
 }
@@ -248,6 +1077,11 @@ impl<L: Language> std::fmt::Debug for Pattern<L> {
       Self::MetaVar { meta_var, .. } => write!(f, "{:?}", meta_var),
       Self::Terminal { text, .. } => write!(f, "{}", text),
       Self::Internal { children, .. } => write!(f, "{:?}", children),
+      Self::Optional(p) => write!(f, "{:?}?", p),
+      Self::Descendant {
+        meta_var, inner, ..
+      } => write!(f, "{:?} <<< {:?}", meta_var, inner),
+      Self::Repeat(group) => write!(f, "{:?}*", group),
     }
   }
 }
@@ -341,6 +1175,47 @@ mod test {
     assert!(pattern.find_node(cand.root()).is_none());
   }
 
+  #[test]
+  fn test_generic_type_arguments() {
+    // standalone `Array<$T>` parses as an expression, select the type from a type annotation
+    let generic = |src| Pattern::contextual(src, "generic_type", Tsx).expect("should parse");
+    let pattern = generic("let a: Array<$T>");
+    let cand = pattern_node("function f(xs: Array<string>): Array<number[]> {}");
+    let found: Vec<_> = cand
+      .root()
+      .find_all(&pattern)
+      .map(|m| {
+        m.get_env()
+          .get_match("T")
+          .expect("should bind")
+          .text()
+          .to_string()
+      })
+      .collect();
+    assert_eq!(found, ["string", "number[]"]);
+    let cand = pattern_node("let m: Array<string, number>");
+    assert!(pattern.find_node(cand.root()).is_none());
+    // variadic type arguments, separators are skipped
+    let pattern = generic("let a: Map<$$$TS>");
+    let cand = pattern_node("let m: Map<K, V> = new Map()");
+    let found = pattern.find_node(cand.root()).expect("should match");
+    let args = found.get_env().get_multiple_matches("TS");
+    let args: Vec<_> = args
+      .iter()
+      .filter(|n| n.is_named())
+      .map(|n| n.text())
+      .collect();
+    assert_eq!(args, ["K", "V"]);
+    // the type name is matched by its field, not by position among the arguments
+    let pattern = generic("let a: $C<string>");
+    let cand = pattern_node("let s: Set<string>");
+    let found = pattern.find_node(cand.root()).expect("should match");
+    assert_eq!(
+      found.get_env().get_match("C").map(|n| n.text()),
+      Some("Set".into())
+    );
+  }
+
   #[test]
   fn test_contextual_match_with_env() {
     let pattern =
@@ -453,6 +1328,129 @@ mod test {
     assert!(ret.has_error());
   }
 
+  #[test]
+  fn test_potential_kind_ids() {
+    let pattern = Pattern::new("foo($A)", Tsx);
+    let call = Tsx
+      .get_ts_language()
+      .id_for_node_kind("call_expression", true);
+    assert_eq!(pattern.potential_kind_ids(), Some(HashSet::from([call])));
+    assert_eq!(Pattern::new("$A", Tsx).potential_kind_ids(), None);
+    assert_eq!(Pattern::new("$$$", Tsx).potential_kind_ids(), None);
+    let ident = Tsx
+      .get_ts_language()
+      .id_for_node_kind("property_identifier", true);
+    let pattern = Pattern::contextual("class A { $F }", "property_identifier", Tsx).expect("test");
+    assert_eq!(pattern.potential_kind_ids(), Some(HashSet::from([ident])));
+  }
+
+  #[test]
+  fn test_for_each_match() {
+    let pattern = Pattern::new("foo($A)", Tsx);
+    let grep = Tsx.ast_grep("foo(1); bar(2); foo(3); foo(4)");
+    let mut captured = vec![];
+    let flow = pattern.for_each_match(grep.root(), |m| {
+      let env = m.get_env();
+      captured.push(
+        env
+          .get_match("A")
+          .expect("should bind A")
+          .text()
+          .to_string(),
+      );
+      ControlFlow::Continue(())
+    });
+    assert_eq!(flow, ControlFlow::Continue(()));
+    // each match has its own env, $A is not required to equal the previous binding
+    assert_eq!(captured, ["1", "3", "4"]);
+    let mut count = 0;
+    let flow = pattern.for_each_match(grep.root(), |_| {
+      count += 1;
+      ControlFlow::Break(())
+    });
+    assert_eq!(flow, ControlFlow::Break(()));
+    assert_eq!(count, 1);
+  }
+
+  #[test]
+  fn test_find_nth() {
+    let pattern = Pattern::new("foo($A)", Tsx);
+    let grep = Tsx.ast_grep("foo(1); bar(2); foo(3); foo(4)");
+    let nth = |n| {
+      let found = pattern.find_nth(grep.root(), n)?;
+      Some(found.get_env().get_match("A")?.text().to_string())
+    };
+    assert_eq!(nth(0).as_deref(), Some("1"));
+    assert_eq!(nth(1).as_deref(), Some("3"));
+    assert_eq!(nth(2).as_deref(), Some("4"));
+    assert_eq!(nth(3), None);
+    // an enclosing match comes before the one nested in it
+    let grep = Tsx.ast_grep("foo(foo(1))");
+    let found = pattern.find_nth(grep.root(), 1).expect("should match");
+    assert_eq!(found.text(), "foo(1)");
+  }
+
+  #[test]
+  fn test_find_all_in_range() {
+    let pattern = Pattern::new("foo($A)", Tsx);
+    let src = "function a() { foo(1); foo(2) }\nfunction b() { foo(3) }";
+    let grep = Tsx.ast_grep(src);
+    let texts = |range: Range<usize>| {
+      let found = pattern.find_all_in_range(grep.root(), range);
+      found
+        .iter()
+        .map(|m| m.text().to_string())
+        .collect::<Vec<_>>()
+    };
+    // the body of function a
+    let start = src.find('{').expect("should have body");
+    let end = src.find('}').expect("should have body") + 1;
+    assert_eq!(texts(start..end), ["foo(1)", "foo(2)"]);
+    assert_eq!(texts(0..src.len()), ["foo(1)", "foo(2)", "foo(3)"]);
+    // partially overlapping calls are excluded
+    assert_eq!(texts(start..start + 12), ["foo(1)"]);
+    assert!(texts(0..10).is_empty());
+  }
+
+  #[test]
+  fn test_meta_var_names() {
+    let pattern = Pattern::new("foo($A, $$$B)", Tsx);
+    assert_eq!(pattern.meta_var_names(), ["A", "B"]);
+    assert_eq!(
+      pattern.meta_vars(),
+      [
+        MetaVariable::Capture("A".into(), true),
+        MetaVariable::MultiCapture("B".into()),
+      ]
+    );
+    let pattern = Pattern::new("foo($_, $$$, $$C, $A, $C)", Tsx);
+    assert_eq!(pattern.meta_var_names(), ["C", "A"]);
+  }
+
+  #[test]
+  fn test_conflicting_meta_var() {
+    let ret = Pattern::try_new("foo($A, $$$A)", Tsx);
+    assert!(matches!(ret, Err(PatternError::ConflictingMetaVar(name)) if name == "A"));
+    let ret = Pattern::contextual("foo($$$B, $B)", "arguments", Tsx);
+    assert!(matches!(ret, Err(PatternError::ConflictingMetaVar(name)) if name == "B"));
+    // reusing a name in the same role is fine
+    assert!(Pattern::try_new("foo($A, $A, $$A)", Tsx).is_ok());
+    assert!(Pattern::try_new("foo($$$A, x, $$$A)", Tsx).is_ok());
+    assert!(Pattern::try_new("foo($$$, $_, $$$)", Tsx).is_ok());
+  }
+
+  #[test]
+  fn test_contextual_unknown_kind() {
+    let ret = Pattern::contextual("class A { $F = 1 }", "public_field_defintion", Tsx);
+    let Err(err) = ret else {
+      panic!("misspelled kind should be rejected");
+    };
+    assert!(matches!(err, PatternError::InvalidKind(_)));
+    assert!(err.to_string().contains("public_field_defintion"));
+    let ret = Pattern::contextual("class A { $F = 1 }", "public_field_definition", Tsx);
+    assert!(ret.is_ok());
+  }
+
   #[test]
   fn test_bare_wildcard_in_context() {
     let pattern = Pattern::contextual("class A { $F }", "property_identifier", Tsx).expect("test");
@@ -525,4 +1523,345 @@ mod test {
   fn test_gh_1087() {
     test_match("($P) => $F($P)", "(x) => bar(x)");
   }
+
+  #[test]
+  fn test_wildcard() {
+    let pattern = Pattern::str("foo(_, $B)", Tsx).with_wildcard("_");
+    let cand = pattern_node("foo(x, y)");
+    let found = pattern.find_node(cand.root()).expect("should match");
+    let env = HashMap::from(found.get_env().clone());
+    assert_eq!(env.len(), 1);
+    assert_eq!(env["B"], "y");
+    assert!(pattern.find_node(pattern_node("foo(x)").root()).is_none());
+    assert!(pattern
+      .find_node(pattern_node("foo(a.b, 1)").root())
+      .is_some());
+    // first child wildcard disables first kind rejection
+    let pattern = Pattern::str("_.b", Tsx).with_wildcard("_");
+    assert_eq!(first_kind(&pattern), None);
+    assert!(pattern.find_node(pattern_node("a.b").root()).is_some());
+    // without opt-in `_` is an identifier
+    test_match("foo(_, $B)", "foo(_, y)");
+    test_non_match("foo(_, $B)", "foo(x, y)");
+  }
+
+  #[test]
+  fn test_from_example() {
+    let pattern = Pattern::from_example("foo(__)", "__", Tsx).expect("should parse");
+    for src in ["foo(anything)", "foo(a + b)", "foo('s')"] {
+      let cand = pattern_node(src);
+      let found = pattern.find_node(cand.root()).expect("should match");
+      assert_eq!(found.get_env().get_matched_variables().count(), 0);
+    }
+    assert!(pattern.find_node(pattern_node("foo()").root()).is_none());
+    assert!(pattern
+      .find_node(pattern_node("foo(a, b)").root())
+      .is_none());
+    assert!(pattern.find_node(pattern_node("bar(a)").root()).is_none());
+    // holes can appear anywhere a named leaf can
+    let pattern = Pattern::from_example("__.push(__)", "__", Tsx).expect("should parse");
+    assert!(pattern
+      .find_node(pattern_node("xs.push(1)").root())
+      .is_some());
+    assert!(Pattern::from_example("foo(__); bar(__)", "__", Tsx).is_err());
+  }
+
+  #[test]
+  fn test_count_matches() {
+    let cand = pattern_node("foo(a); foo(b, c); bar(foo(d)); a == a; a == b");
+    let count = |p: &str| {
+      let pattern = Pattern::str(p, Tsx);
+      let expected = cand.root().find_all(&pattern).count();
+      let count = pattern.count_matches(cand.root());
+      assert_eq!(count, expected, "{p}");
+      count
+    };
+    assert_eq!(count("foo($A)"), 2);
+    assert_eq!(count("foo($$$ARGS)"), 3);
+    assert_eq!(count("bar(foo($A))"), 1);
+    // consistency falls back to the full matcher
+    assert_eq!(count("$A == $A"), 1);
+    assert_eq!(count("$A == $B"), 2);
+  }
+
+  #[test]
+  fn test_validator() {
+    let pattern = Pattern::str("new $C($A)", Tsx);
+    let upper_first = |name: &str, node: &Node<StrDoc<Tsx>>| {
+      name != "C" || node.text().starts_with(|c: char| c.is_ascii_uppercase())
+    };
+    let cand = pattern_node("new Foo(a)");
+    let node = cand.root().find("new $$$").expect("should find");
+    let found = pattern.match_node_with_validator(node.get_node().clone(), &upper_first);
+    let env = HashMap::from(found.expect("should match").get_env().clone());
+    assert_eq!(env["C"], "Foo");
+    assert_eq!(env["A"], "a");
+    let cand = pattern_node("new foo(a)");
+    let node = cand.root().find("new $$$").expect("should find");
+    let found = pattern.match_node_with_validator(node.get_node().clone(), &upper_first);
+    assert!(found.is_none());
+    assert!(pattern.find_node(cand.root()).is_some());
+  }
+
+  fn first_kind(p: &Pattern<Tsx>) -> Option<u16> {
+    match p {
+      Pattern::Internal { first_kind, .. } => *first_kind,
+      _ => panic!("must be internal"),
+    }
+  }
+
+  #[test]
+  fn test_first_kind() {
+    let pattern = Pattern::str("console.log($A)", Tsx);
+    let kind = get_kind("member_expression") as u16;
+    assert_eq!(first_kind(&pattern), Some(kind));
+    let pattern = Pattern::str("$F($A)", Tsx);
+    assert_eq!(first_kind(&pattern), None);
+    test_match("console.log($A)", "console.log(123)");
+    test_non_match("console.log($A)", "log(123)");
+  }
+
+  fn optional_env(pattern: &Pattern<Tsx>, src: &str) -> Option<HashMap<String, String>> {
+    let cand = pattern_node(src);
+    let found = pattern.find_node(cand.root())?;
+    Some(HashMap::from(found.get_env().clone()))
+  }
+
+  #[test]
+  fn test_optional_child() {
+    let pattern = Pattern::str("let $A = $B", Tsx).with_optional("B");
+    let env = optional_env(&pattern, "let a = 1").expect("should match");
+    assert_eq!(env["A"], "a");
+    assert_eq!(env["B"], "1");
+    let env = optional_env(&pattern, "let a").expect("should match");
+    assert_eq!(env["A"], "a");
+    assert!(!env.contains_key("B"));
+    assert!(optional_env(&pattern, "var a").is_none());
+    let pattern = Pattern::str("let $A = $B", Tsx);
+    assert!(optional_env(&pattern, "let a").is_none());
+  }
+
+  #[test]
+  fn test_optional_with_ellipsis() {
+    let pattern = Pattern::str("foo($$$A, $B)", Tsx).with_optional("B");
+    let env = optional_env(&pattern, "foo(a, b)").expect("should match");
+    assert_eq!(env["B"], "b");
+    let env = optional_env(&pattern, "foo()").expect("should match");
+    assert!(!env.contains_key("B"));
+    let pattern = Pattern::str("foo($B, $$$A)", Tsx).with_optional("B");
+    let env = optional_env(&pattern, "foo(a, b)").expect("should match");
+    assert_eq!(env["B"], "a");
+    let env = optional_env(&pattern, "foo()").expect("should match");
+    assert!(!env.contains_key("B"));
+  }
+
+  #[test]
+  fn test_match_candidates() {
+    let pattern = Pattern::str("foo($A)", Tsx);
+    let root = pattern_node("foo(1); bar(2); foo(3, 4); foo(foo(5))");
+    let nodes: Vec<_> = root
+      .root()
+      .dfs()
+      .filter(|n| n.kind() == "call_expression")
+      .collect();
+    assert_eq!(nodes.len(), 5);
+    let found: Vec<_> = pattern
+      .match_candidates(nodes.into_iter())
+      .map(|m| {
+        m.get_env()
+          .get_match("A")
+          .expect("should bind")
+          .text()
+          .to_string()
+      })
+      .collect();
+    assert_eq!(found, ["1", "foo(5)", "5"]);
+    let stmts = root.root().children().collect::<Vec<_>>();
+    assert_eq!(pattern.match_candidates(stmts.into_iter()).count(), 0);
+  }
+
+  #[test]
+  fn test_capture_node() {
+    let pattern = Pattern::str("return $A", Tsx);
+    let root = pattern_node("function f() { if (a) return b + 1 }");
+    let node = pattern
+      .capture_node(root.root(), "A")
+      .expect("should capture");
+    assert_eq!(node.kind(), "binary_expression");
+    assert_eq!(
+      pattern.capture_text(root.root(), "A").as_deref(),
+      Some("b + 1")
+    );
+    let root = pattern_node("function f() { return }");
+    assert!(pattern.capture_text(root.root(), "A").is_none());
+    // multi captures are not returned
+    let pattern = Pattern::str("foo($$$A)", Tsx);
+    let root = pattern_node("foo(a)");
+    assert!(pattern.capture_node(root.root(), "A").is_none());
+  }
+
+  fn same_structure(a: &Pattern<Tsx>, b: &Pattern<Tsx>) -> bool {
+    use Pattern as P;
+    match (a, b) {
+      (
+        P::MetaVar {
+          meta_var: m1,
+          kind: k1,
+        },
+        P::MetaVar {
+          meta_var: m2,
+          kind: k2,
+        },
+      ) => format!("{m1:?}") == format!("{m2:?}") && k1 == k2,
+      (
+        P::Terminal {
+          text: t1,
+          is_named: n1,
+          kind_id: k1,
+          field_id: f1,
+        },
+        P::Terminal {
+          text: t2,
+          is_named: n2,
+          kind_id: k2,
+          field_id: f2,
+        },
+      ) => t1 == t2 && n1 == n2 && k1 == k2 && f1 == f2,
+      (
+        P::Internal {
+          kind_id: k1,
+          children: c1,
+          first_kind: f1,
+          ..
+        },
+        P::Internal {
+          kind_id: k2,
+          children: c2,
+          first_kind: f2,
+          ..
+        },
+      ) => {
+        k1 == k2
+          && f1 == f2
+          && c1.len() == c2.len()
+          && c1.iter().zip(c2).all(|(a, b)| same_structure(a, b))
+      }
+      _ => false,
+    }
+  }
+
+  #[test]
+  fn test_from_root() {
+    for src in ["foo($A, $$$B)", "class A { $$$ }", "let a = 123", "$A + $_"] {
+      let root = Root::<StrDoc<Tsx>>::new(&Tsx.pre_process_pattern(src), Tsx);
+      let pattern = Pattern::from_root(root);
+      assert!(same_structure(&pattern, &Pattern::new(src, Tsx)), "{src}");
+    }
+    let root = Root::<StrDoc<Tsx>>::new("a; b", Tsx);
+    assert!(matches!(
+      Pattern::try_from_root(&root),
+      Err(PatternError::MultipleNode(_))
+    ));
+  }
+
+  #[test]
+  fn test_with_fallbacks() {
+    let function = Pattern::with_fallbacks(
+      Pattern::new("function $F($$$) { $$$ }", Tsx),
+      vec![Pattern::new("const $F = ($$$) => $$$", Tsx)],
+    );
+    let grep = Tsx.ast_grep("const add = (a, b) => a + b");
+    let found = grep.root().find(&function).expect("fallback should match");
+    assert_eq!(found.variant_index(), Some(1));
+    assert_eq!(found.get_env().get_match("F").unwrap().text(), "add");
+    let grep = Tsx.ast_grep("function add(a, b) { return a + b }");
+    let found = grep.root().find(&function).expect("primary should match");
+    assert_eq!(found.variant_index(), Some(0));
+    // other matchers do not record a variant
+    let any = Pattern::any_of(function.inner().to_vec());
+    assert_eq!(grep.root().find(&any).unwrap().variant_index(), None);
+  }
+
+  #[test]
+  fn test_any_of() {
+    let any = Pattern::any_of(vec![
+      Pattern::new("foo($A, 1)", Tsx),
+      Pattern::new("foo($B, $C)", Tsx),
+    ]);
+    let grep = Tsx.ast_grep("foo(x, 2)");
+    let found = grep
+      .root()
+      .find(&any)
+      .expect("second alternative should match");
+    let env = HashMap::from(found.get_env().clone());
+    // $A was bound before the first alternative failed on `1`
+    assert!(!env.contains_key("A"));
+    assert_eq!(env["B"], "x");
+    assert_eq!(env["C"], "2");
+    // the first alternative wins when both match
+    let grep = Tsx.ast_grep("foo(x, 1)");
+    let found = grep.root().find(&any).expect("should match");
+    let env = HashMap::from(found.get_env().clone());
+    assert_eq!(env["A"], "x");
+    assert!(!env.contains_key("B"));
+    assert!(Tsx.ast_grep("bar(x, 1)").root().find(&any).is_none());
+  }
+
+  #[test]
+  fn test_all_of() {
+    let all = Pattern::all_of(vec![
+      Pattern::new("foo($A, $_)", Tsx),
+      Pattern::new("$F($_, $B)", Tsx),
+    ]);
+    let grep = Tsx.ast_grep("foo(x, 2)");
+    let found = grep.root().find(&all).expect("both should match");
+    let env = HashMap::from(found.get_env().clone());
+    assert_eq!(env["A"], "x");
+    assert_eq!(env["B"], "2");
+    assert_eq!(env["F"], "foo");
+    assert!(Tsx.ast_grep("foo(x)").root().find(&all).is_none());
+  }
+
+  #[test]
+  fn test_all_of_conflict() {
+    let all = Pattern::all_of(vec![
+      Pattern::new("foo($A, $_)", Tsx),
+      Pattern::new("foo($_, $A)", Tsx),
+    ]);
+    // $A is x in the first pattern but 2 in the second
+    assert!(Tsx.ast_grep("foo(x, 2)").root().find(&all).is_none());
+    let grep = Tsx.ast_grep("foo(x, x)");
+    let found = grep.root().find(&all).expect("same capture should merge");
+    assert_eq!(
+      found.get_env().get_match("A").map(|n| n.text()),
+      Some("x".into())
+    );
+    // bindings of the failed match are rolled back
+    let mut env = Cow::Owned(MetaVarEnv::new());
+    let grep = Tsx.ast_grep("foo(x, 2)");
+    let call = grep.root().find("foo($$$)").expect("should find call");
+    assert!(all
+      .match_node_with_env(call.get_node().clone(), &mut env)
+      .is_none());
+    assert!(env.get_match("A").is_none());
+  }
+
+  #[test]
+  fn test_is_single_node() {
+    assert!(Pattern::new("a", Tsx).is_single_node());
+    assert!(Pattern::new("$A", Tsx).is_single_node());
+    assert!(Pattern::new("$$_", Tsx).is_single_node());
+    assert!(!Pattern::new("$$$A", Tsx).is_single_node());
+    assert!(!Pattern::new("$$$", Tsx).is_single_node());
+    assert!(!Pattern::new("foo($A)", Tsx).is_single_node());
+    // optional goals only appear as children, see with_optional
+    let pattern = Pattern::Optional(Box::new(Pattern::new("$A", Tsx)));
+    assert!(!pattern.is_single_node());
+    let pattern = Pattern::new("$A", Tsx).with_descendant("A", Pattern::new("b", Tsx), 1);
+    assert!(matches!(pattern, Pattern::Descendant { .. }));
+    assert!(pattern.is_single_node());
+    let pattern = Pattern::new("$$$A", Tsx).with_repeat("A", vec![Pattern::new("b", Tsx)]);
+    assert!(matches!(pattern, Pattern::Repeat(_)));
+    assert!(!pattern.is_single_node());
+  }
 }
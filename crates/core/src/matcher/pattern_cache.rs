@@ -0,0 +1,140 @@
+use super::Pattern;
+use crate::Language;
+
+use std::any::Any;
+use std::sync::{Arc, Mutex};
+
+/// Max number of patterns kept by [`Pattern::new_cached`], the least recently used is evicted first.
+pub const PATTERN_CACHE_CAPACITY: usize = 128;
+
+/// Compiled patterns, the most recently used last.
+/// Entries are `Cached<L>` for any language type, told apart by downcasting.
+static CACHE: Mutex<Vec<Box<dyn Any + Send>>> = Mutex::new(Vec::new());
+
+struct Cached<L: Language> {
+  /// the source after `Language::pre_process_pattern`
+  src: String,
+  expando: char,
+  lang: L,
+  pattern: Arc<Pattern<L>>,
+}
+
+impl<L: Language + Send + Sync + 'static> Pattern<L> {
+  /// Like [`Pattern::new`] but returns a shared pattern from a process-wide cache,
+  /// so building the same pattern source again, e.g. a rule run across many files, skips parsing.
+  /// Patterns are cached by the pre-processed source, expando char and tree-sitter language, so languages
+  /// sharing a grammar but preparing patterns differently get their own entries. The cache is only used
+  /// by this method and holds at most [`PATTERN_CACHE_CAPACITY`] patterns.
+  pub fn new_cached(src: &str, lang: L) -> Arc<Self> {
+    let processed = lang.pre_process_pattern(src);
+    let expando = lang.expando_char();
+    let same_lang = |cached: &L| cached.get_ts_language() == lang.get_ts_language();
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    let found = cache.iter().position(|entry| {
+      let cached = entry.downcast_ref::<Cached<L>>();
+      matches!(cached, Some(c) if c.src == processed && c.expando == expando && same_lang(&c.lang))
+    });
+    if let Some(i) = found {
+      let entry = cache.remove(i);
+      let pattern = downcast::<L>(entry.as_ref()).pattern.clone();
+      cache.push(entry);
+      return pattern;
+    }
+    // parse outside of the lock, another thread may cache the same source meanwhile
+    drop(cache);
+    let pattern = Arc::new(Self::new(src, lang.clone()));
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    if cache.len() >= PATTERN_CACHE_CAPACITY {
+      cache.remove(0);
+    }
+    cache.push(Box::new(Cached {
+      src: processed.into_owned(),
+      expando,
+      lang,
+      pattern: pattern.clone(),
+    }));
+    pattern
+  }
+}
+
+fn downcast<L: Language + 'static>(entry: &(dyn Any + Send)) -> &Cached<L> {
+  entry
+    .downcast_ref()
+    .expect("entry should be found by its type")
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::language::{TSLanguage, Tsx};
+  use crate::Matcher;
+  use std::borrow::Cow;
+
+  // the cache is shared, filling it in one test must not evict the patterns of another
+  static SERIAL: Mutex<()> = Mutex::new(());
+
+  #[test]
+  fn test_new_cached() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let a = Pattern::new_cached("foo($A, cached_1)", Tsx);
+    let b = Pattern::new_cached("foo($A, cached_1)", Tsx);
+    assert!(Arc::ptr_eq(&a, &b));
+    let grep = Tsx.ast_grep("foo(x, cached_1); foo(y, 2)");
+    let found = |p: &Pattern<Tsx>| {
+      let found = grep.root().find_all(p).map(|m| m.text().to_string());
+      found.collect::<Vec<_>>()
+    };
+    assert_eq!(found(&a), ["foo(x, cached_1)"]);
+    assert_eq!(found(&a), found(&b));
+    let other = Pattern::new_cached("foo($A, 2)", Tsx);
+    assert!(!Arc::ptr_eq(&a, &other));
+    assert_eq!(found(&other), ["foo(y, 2)"]);
+  }
+
+  /// Tsx with a configurable expando char, like custom languages sharing a grammar.
+  #[derive(Clone)]
+  struct Custom(char);
+  impl Language for Custom {
+    fn get_ts_language(&self) -> TSLanguage {
+      Tsx.get_ts_language()
+    }
+    fn expando_char(&self) -> char {
+      self.0
+    }
+    fn pre_process_pattern<'q>(&self, query: &'q str) -> Cow<'q, str> {
+      Cow::Owned(query.replace('$', &self.0.to_string()))
+    }
+  }
+
+  #[test]
+  fn test_cache_by_language_value() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let src = "foo(µA, cached_custom)";
+    let plain = Pattern::new_cached(src, Custom('$'));
+    let expando = Pattern::new_cached(src, Custom('µ'));
+    assert!(!Arc::ptr_eq(&plain, &expando));
+    let grep = Custom('µ').ast_grep("foo(x, cached_custom)");
+    // `µA` is a meta variable only with the µ expando
+    assert!(plain.find_node(grep.root()).is_none());
+    assert!(expando.find_node(grep.root()).is_some());
+    // the same source is shared through pre-processing
+    let processed = Pattern::new_cached("foo($A, cached_custom)", Custom('µ'));
+    assert!(Arc::ptr_eq(&expando, &processed));
+  }
+
+  #[test]
+  fn test_cache_bounded() {
+    let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+    let first = Pattern::new_cached("cached_bound_0", Tsx);
+    for i in 1..=PATTERN_CACHE_CAPACITY {
+      Pattern::new_cached(&format!("cached_bound_{i}"), Tsx);
+    }
+    assert!(CACHE.lock().expect("should lock").len() <= PATTERN_CACHE_CAPACITY);
+    // the least recently used pattern is evicted and parsed again
+    let again = Pattern::new_cached("cached_bound_0", Tsx);
+    assert!(!Arc::ptr_eq(&first, &again));
+    assert!(again
+      .find_node(Tsx.ast_grep("cached_bound_0").root())
+      .is_some());
+  }
+}
@@ -0,0 +1,80 @@
+use super::{ConfiguredPattern, MatchOptions, NodeMatch, Pattern, PatternError};
+use crate::{Doc, Language, Node};
+
+/// A pattern compiled once and run against many roots, e.g. one per file of a file walker.
+/// `run` only takes `&self` and the runner holds no interior mutability, so a runner is
+/// `Send + Sync` whenever the language is and can be shared by reference across threads.
+/// Each thread parses its own tree, the matches borrow from that tree.
+#[derive(Clone)]
+pub struct PatternRunner<L: Language> {
+  pattern: ConfiguredPattern<L>,
+}
+
+impl<L: Language> PatternRunner<L> {
+  pub fn new(pattern: Pattern<L>) -> Self {
+    Self::with_options(pattern, MatchOptions::default())
+  }
+
+  pub fn with_options(pattern: Pattern<L>, options: MatchOptions) -> Self {
+    Self {
+      pattern: pattern.with_options(options),
+    }
+  }
+
+  pub fn try_new(src: &str, lang: L) -> Result<Self, PatternError> {
+    Ok(Self::new(Pattern::try_new(src, lang)?))
+  }
+
+  pub fn pattern(&self) -> &ConfiguredPattern<L> {
+    &self.pattern
+  }
+
+  /// Finds all matches under `root`, in the same order as `root.find_all(pattern)`.
+  pub fn run<'r, D: Doc<Lang = L>>(&self, root: Node<'r, D>) -> Vec<NodeMatch<'r, D>> {
+    root.find_all(&self.pattern).collect()
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use super::*;
+  use crate::language::Tsx;
+  use std::thread;
+
+  fn assert_send_sync<T: Send + Sync>() {}
+
+  #[test]
+  fn test_runner_send_sync() {
+    assert_send_sync::<PatternRunner<Tsx>>();
+  }
+
+  #[test]
+  fn test_run_across_threads() {
+    let runner = PatternRunner::try_new("foo($A)", Tsx).expect("should parse");
+    let sources = ["foo(1); bar(2)", "foo(a); foo(b)", "bar(foo(c))", "baz()"];
+    let found: Vec<Vec<String>> = thread::scope(|s| {
+      let handles: Vec<_> = sources
+        .iter()
+        .map(|src| {
+          let runner = &runner;
+          s.spawn(move || {
+            let root = Tsx.ast_grep(src);
+            let matches = runner.run(root.root());
+            let found = matches.iter().map(|m| m.get_env().get_match("A"));
+            found
+              .map(|a| a.expect("should capture").text().to_string())
+              .collect()
+          })
+        })
+        .collect();
+      handles
+        .into_iter()
+        .map(|h| h.join().expect("should not panic"))
+        .collect()
+    });
+    assert_eq!(found, [vec!["1"], vec!["a", "b"], vec!["c"], vec![]]);
+    // the runner is unchanged and gives the same result on the calling thread
+    let root = Tsx.ast_grep(sources[1]);
+    assert_eq!(runner.run(root.root()).len(), 2);
+  }
+}
@@ -0,0 +1,107 @@
+use super::NodeMatch;
+use crate::meta_var::MetaVariable;
+use crate::{Doc, Node};
+
+use serde::{Serialize, Serializer};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+#[derive(Serialize)]
+struct ByteRange {
+  start: usize,
+  end: usize,
+}
+
+#[derive(Serialize)]
+struct Position {
+  line: usize,
+  column: usize,
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum Capture<'a> {
+  Single {
+    text: Cow<'a, str>,
+    range: ByteRange,
+  },
+  Multi(Vec<ByteRange>),
+}
+
+/// JSON shape of a match. Lines and columns are zero-based, columns count bytes.
+#[derive(Serialize)]
+struct MatchJson<'a> {
+  kind: Cow<'a, str>,
+  range: ByteRange,
+  start: Position,
+  end: Position,
+  captures: BTreeMap<String, Capture<'a>>,
+}
+
+fn byte_range<D: Doc>(node: &Node<D>) -> ByteRange {
+  let range = node.range();
+  ByteRange {
+    start: range.start,
+    end: range.end,
+  }
+}
+
+fn position((line, column): (usize, usize)) -> Position {
+  Position { line, column }
+}
+
+/// Serializes the matched node and its captures. Captures are sorted by name.
+/// A single capture has its text and range, a multi capture like `$$$A` is an array of ranges.
+impl<'tree, D: Doc> Serialize for NodeMatch<'tree, D> {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    let env = self.get_env();
+    let captures = env
+      .get_matched_variables()
+      .filter_map(|var| match var {
+        MetaVariable::Capture(name, _) => {
+          let node = env.get_match(&name)?;
+          let capture = Capture::Single {
+            text: node.text(),
+            range: byte_range(node),
+          };
+          Some((name, capture))
+        }
+        MetaVariable::MultiCapture(name) | MetaVariable::LineContiguousCapture(name) => {
+          let nodes = env.get_multiple_matches(&name);
+          let ranges = nodes.iter().map(byte_range).collect();
+          Some((name, Capture::Multi(ranges)))
+        }
+        _ => None,
+      })
+      .collect();
+    let json = MatchJson {
+      kind: self.kind(),
+      range: byte_range(self.get_node()),
+      start: position(self.start_pos()),
+      end: position(self.end_pos()),
+      captures,
+    };
+    json.serialize(serializer)
+  }
+}
+
+#[cfg(test)]
+mod test {
+  use crate::language::{Language, Tsx};
+  use crate::Pattern;
+
+  #[test]
+  fn test_serialize_match() {
+    let grep = Tsx.ast_grep("foo(1, 2, 3)");
+    let pattern = Pattern::new("foo($A, $$$B)", Tsx);
+    let found = grep.root().find(pattern).expect("should match");
+    let json = serde_json::to_string(&found).expect("should serialize");
+    let expected = concat!(
+      r#"{"kind":"call_expression","range":{"start":0,"end":12},"#,
+      r#""start":{"line":0,"column":0},"end":{"line":0,"column":12},"#,
+      r#""captures":{"A":{"text":"1","range":{"start":4,"end":5}},"#,
+      r#""B":[{"start":7,"end":8},{"start":8,"end":9},{"start":10,"end":11}]}}"#,
+    );
+    assert_eq!(json, expected);
+  }
+}
@@ -1,63 +1,366 @@
-use crate::match_tree::does_node_match_exactly;
-use crate::matcher::Matcher;
+use crate::match_tree::{does_node_match_exactly, MatchOptions};
+use crate::matcher::{capture_counts, Matcher};
 use crate::source::Content;
-use crate::{Doc, Language, Node, StrDoc};
+use crate::{Doc, Language, Node, Pattern, StrDoc};
 use std::borrow::Cow;
 use std::collections::HashMap;
+use std::ops::Range;
+use thiserror::Error;
 
 pub type MetaVariableID = String;
 
 type Underlying<D> = Vec<<<D as Doc>::Source as Content>::Underlying>;
 /// a dictionary that stores metavariable instantiation
 /// const a = 123 matched with const a = $A will produce env: $A => 123
+///
+/// Bindings are stored in insertion order and each name is bound at most once, a consistent
+/// rebinding keeps the existing entry. This makes undoing bindings as cheap as truncating, see [`MetaVarEnv::rollback`].
 #[derive(Clone)]
 pub struct MetaVarEnv<'tree, D: Doc> {
-  single_matched: HashMap<MetaVariableID, Node<'tree, D>>,
-  multi_matched: HashMap<MetaVariableID, Vec<Node<'tree, D>>>,
+  /// each binding with whether its meta variable is named, e.g. `$A` but not `$$A`
+  single_matched: Vec<(MetaVariableID, Node<'tree, D>, bool)>,
+  multi_matched: Vec<(MetaVariableID, Vec<Node<'tree, D>>)>,
   transformed_var: HashMap<MetaVariableID, Underlying<D>>,
+  /// named nodes consumed by each anonymous ellipsis `$$$`, in pattern order
+  ellipsis_counts: Vec<usize>,
+  /// index of the alternative that matched, see `Pattern::with_fallbacks`
+  variant_index: Option<usize>,
+  /// single and multiple bindings to reserve room for on the first insert, see `with_capacity_for`
+  capacity_hint: (usize, usize),
+  /// names bound and looked up as another name, see `alias_var`
+  var_aliases: Vec<(MetaVariableID, MetaVariableID)>,
+}
+
+/// How [`MetaVarEnv::merge`] resolves a meta variable bound in both envs.
+/// Bindings are consistent if they match exactly, like repeated meta variables in one pattern.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergePolicy {
+  /// Fail if any binding is inconsistent, leaving the env unchanged.
+  RejectConflicts,
+  /// Keep the binding of the env being merged into.
+  KeepExisting,
+  /// Replace the binding with the one from the other env.
+  PreferOther,
+}
+
+#[derive(Debug, Error)]
+#[error("Meta variable `{0}` is bound to inconsistent values.")]
+pub struct MergeConflict(pub MetaVariableID);
+
+/// A snapshot of meta variable bindings in [`MetaVarEnv`].
+/// Bindings inserted after the checkpoint can be discarded by [`MetaVarEnv::rollback`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EnvCheckpoint {
+  single: usize,
+  multi: usize,
+  ellipsis: usize,
+  variant: Option<usize>,
 }
 
 impl<'tree, D: Doc> MetaVarEnv<'tree, D> {
   pub fn new() -> Self {
     Self {
-      single_matched: HashMap::new(),
-      multi_matched: HashMap::new(),
+      single_matched: Vec::new(),
+      multi_matched: Vec::new(),
       transformed_var: HashMap::new(),
+      ellipsis_counts: Vec::new(),
+      variant_index: None,
+      capacity_hint: (0, 0),
+      var_aliases: Vec::new(),
+    }
+  }
+
+  /// Makes `alias` another name of `canonical`: inserting or looking up `alias` uses the binding of
+  /// `canonical`, so `$Y` must match the same node as `$X` once aliased to it. This holds for labels,
+  /// transformations and constraints too. Bindings are recorded under `canonical`, e.g. `get_matched_variables`
+  /// only lists it. Aliases are not chained, are kept on rollback and are merged by `merge`.
+  /// Aliasing a name to itself does nothing.
+  pub fn alias_var(&mut self, alias: &str, canonical: &str) {
+    if alias == canonical || self.var_aliases.iter().any(|(a, _)| a == alias) {
+      return;
+    }
+    self.var_aliases.push((alias.into(), canonical.into()));
+  }
+
+  fn resolve<'a>(&'a self, var: &'a str) -> &'a str {
+    if self.var_aliases.is_empty() {
+      return var;
+    }
+    self
+      .var_aliases
+      .iter()
+      .find_map(|(alias, canonical)| (alias == var).then_some(canonical.as_str()))
+      .unwrap_or(var)
+  }
+
+  /// Creates an empty env sized for every capture `pattern` can bind, so matching does not grow it.
+  /// Room is reserved when the first capture is inserted,
+  /// so candidates failing before any binding still allocate nothing.
+  pub fn with_capacity_for(pattern: &Pattern<D::Lang>) -> Self {
+    let mut env = Self::new();
+    env.capacity_hint = capture_counts(pattern);
+    env
+  }
+
+  /// Creates an env with pre-bound meta variables. Matching against it respects the bindings:
+  /// a later `$A` in the pattern must match the same text as the pre-bound `$A`.
+  /// Returns None if the same variable is bound to different text.
+  pub fn with_bindings<'a, I>(bindings: I) -> Option<Self>
+  where
+    I: IntoIterator<Item = (&'a str, Node<'tree, D>)>,
+  {
+    let mut env = Self::new();
+    for (id, node) in bindings {
+      env.insert(id, node)?;
     }
+    Some(env)
   }
 
+  /// Binds `id` to the node, recording the node's own `is_named` as the capture's named flag.
   pub fn insert(&mut self, id: &str, ret: Node<'tree, D>) -> Option<&mut Self> {
-    if self.match_variable(id, &ret) {
-      self.single_matched.insert(id.to_string(), ret);
-      Some(self)
-    } else {
-      None
+    let named = ret.is_named();
+    self.insert_capture(id, ret, named)
+  }
+
+  /// Binds `id` to the node for a meta variable like `$A` (named) or `$$A` (not named).
+  pub fn insert_capture(
+    &mut self,
+    id: &str,
+    ret: Node<'tree, D>,
+    named: bool,
+  ) -> Option<&mut Self> {
+    self.insert_capture_by(id, ret, named, does_node_match_exactly)
+  }
+
+  /// Like [`MetaVarEnv::insert_capture`], but an existing binding is compared with the node by `equal`.
+  pub(crate) fn insert_capture_by(
+    &mut self,
+    id: &str,
+    ret: Node<'tree, D>,
+    named: bool,
+    equal: impl Fn(&Node<D>, &Node<D>) -> bool,
+  ) -> Option<&mut Self> {
+    if let Some(bound) = self.get_match(id) {
+      return equal(bound, &ret).then_some(self);
     }
+    if self.single_matched.capacity() == 0 {
+      self.single_matched.reserve(self.capacity_hint.0);
+    }
+    let id = self.resolve(id).to_string();
+    self.single_matched.push((id, ret, named));
+    Some(self)
   }
 
   pub fn insert_multi(&mut self, id: &str, ret: Vec<Node<'tree, D>>) -> Option<&mut Self> {
-    if self.match_multi_var(id, &ret) {
-      self.multi_matched.insert(id.to_string(), ret);
-      Some(self)
-    } else {
-      None
+    if self.get_labels(id).is_some() {
+      return self.match_multi_var(id, &ret).then_some(self);
+    }
+    if self.multi_matched.capacity() == 0 {
+      self.multi_matched.reserve(self.capacity_hint.1);
+    }
+    let id = self.resolve(id).to_string();
+    self.multi_matched.push((id, ret));
+    Some(self)
+  }
+
+  /// Adds the bindings of `other` to this env. Variables bound in both envs are resolved by `policy`.
+  /// A binding replaced by `PreferOther` is updated in place and is not restored by `rollback`.
+  pub fn merge(&mut self, other: Self, policy: MergePolicy) -> Result<(), MergeConflict> {
+    // aliases of this env take precedence, bindings of other are recorded under the merged names
+    let aliases = other.var_aliases.iter();
+    let new_aliases: Vec<_> = aliases
+      .filter(|(alias, _)| self.var_aliases.iter().all(|(a, _)| a != alias))
+      .cloned()
+      .collect();
+    self.var_aliases.extend(new_aliases);
+    let singles: Vec<_> = other
+      .single_bindings()
+      .map(|(id, node)| {
+        let named = other.is_named_capture(id).unwrap_or(true);
+        (self.resolve(id).to_string(), node.clone(), named)
+      })
+      .collect();
+    let multis: Vec<_> = unique_ids(&other.multi_matched)
+      .map(|id| {
+        let nodes = other.get_multiple_matches(&id);
+        (self.resolve(&id).to_string(), nodes)
+      })
+      .collect();
+    let transformed: Vec<_> = other
+      .transformed_var
+      .into_iter()
+      .map(|(id, bytes)| (self.resolve(&id).to_string(), bytes))
+      .collect();
+    if policy == MergePolicy::RejectConflicts {
+      let single = singles
+        .iter()
+        .find(|(id, n, _)| !self.match_variable(id, n));
+      let multi = multis.iter().find(|(id, ns)| !self.match_multi_var(id, ns));
+      let transformed = transformed.iter().find(
+        |(id, bytes)| matches!(self.transformed_var.get(id), Some(existing) if existing != bytes),
+      );
+      let conflict = single
+        .map(|(id, _, _)| id)
+        .or(multi.map(|(id, _)| id))
+        .or(transformed.map(|(id, _)| id));
+      if let Some(id) = conflict {
+        return Err(MergeConflict(id.clone()));
+      }
+    }
+    let prefer_other = policy == MergePolicy::PreferOther;
+    for (id, node, named) in singles {
+      match self
+        .single_matched
+        .iter()
+        .position(|(bound, ..)| *bound == id)
+      {
+        Some(i) if prefer_other => self.single_matched[i] = (id, node, named),
+        Some(_) => (),
+        None => self.single_matched.push((id, node, named)),
+      }
+    }
+    for (id, nodes) in multis {
+      match self
+        .multi_matched
+        .iter()
+        .position(|(bound, _)| *bound == id)
+      {
+        Some(i) if prefer_other => self.multi_matched[i].1 = nodes,
+        Some(_) => (),
+        None => self.multi_matched.push((id, nodes)),
+      }
+    }
+    for (id, bytes) in transformed {
+      if prefer_other || !self.transformed_var.contains_key(&id) {
+        self.transformed_var.insert(id, bytes);
+      }
     }
+    Ok(())
+  }
+
+  /// Record the current bindings so that later insertions can be undone.
+  pub fn checkpoint(&self) -> EnvCheckpoint {
+    EnvCheckpoint {
+      single: self.single_matched.len(),
+      multi: self.multi_matched.len(),
+      ellipsis: self.ellipsis_counts.len(),
+      variant: self.variant_index,
+    }
+  }
+
+  /// Discard all meta variables inserted after the checkpoint was taken.
+  /// Labels added to an existing entry by `add_label` are not undone.
+  pub fn rollback(&mut self, checkpoint: EnvCheckpoint) {
+    self.single_matched.truncate(checkpoint.single);
+    self.multi_matched.truncate(checkpoint.multi);
+    self.ellipsis_counts.truncate(checkpoint.ellipsis);
+    self.variant_index = checkpoint.variant;
   }
 
   pub fn insert_transformation(&mut self, name: &str, src: Underlying<D>) {
-    self.transformed_var.insert(name.to_string(), src);
+    let name = self.resolve(name).to_string();
+    self.transformed_var.insert(name, src);
   }
 
   pub fn get_match(&self, var: &str) -> Option<&'_ Node<'tree, D>> {
-    self.single_matched.get(var)
+    let var = self.resolve(var);
+    self
+      .single_matched
+      .iter()
+      .rev()
+      .find_map(|(id, node, _)| (id == var).then_some(node))
+  }
+
+  /// Returns whether the binding of `var` was captured by a named meta variable like `$A`
+  /// rather than `$$A`, which also binds anonymous tokens. Returns None if `var` is not bound.
+  pub fn is_named_capture(&self, var: &str) -> Option<bool> {
+    let var = self.resolve(var);
+    self
+      .single_matched
+      .iter()
+      .rev()
+      .find_map(|(id, _, named)| (id == var).then_some(*named))
   }
 
   pub fn get_multiple_matches(&self, var: &str) -> Vec<Node<'tree, D>> {
-    self.multi_matched.get(var).cloned().unwrap_or_default()
+    self.get_labels(var).cloned().unwrap_or_default()
+  }
+
+  /// Returns the byte ranges of the named nodes captured by a multi meta variable like `$$$A`,
+  /// in source order. Separators like commas are skipped. Ranges do not borrow the tree.
+  pub fn get_multi_ranges(&self, var: &str) -> Option<Vec<Range<usize>>> {
+    let nodes = self.get_labels(var)?;
+    Some(
+      nodes
+        .iter()
+        .filter(|n| n.is_named())
+        .map(Node::range)
+        .collect(),
+    )
+  }
+
+  /// Returns the byte range of the capture `var` extended over its surrounding trivia, such as comments
+  /// and whitespace, so a moved capture keeps its attached comments. The range reaches back to the end
+  /// of the previous significant sibling and forward to the start of the next one, where nodes of
+  /// the `comment_kinds` of `opts` are not significant. Without such a sibling it reaches the start or end of the parent.
+  /// A multi capture extends from its first node to its last. It is computed from the bound nodes on each call.
+  pub fn get_extended_range(&self, var: &str, opts: &MatchOptions) -> Option<Range<usize>> {
+    let (first, last) = match self.get_match(var) {
+      Some(node) => (node, node),
+      None => {
+        let nodes = self.get_labels(var)?;
+        (nodes.first()?, nodes.last()?)
+      }
+    };
+    Some(extended_start(first, opts)..extended_end(last, opts))
+  }
+
+  /// Like `get_multi_ranges` but pairs each range with the kind id of the captured node,
+  /// so reporters can filter or color-code captures by kind without holding nodes.
+  pub fn get_multi_spans(&self, var: &str) -> Option<Vec<(Range<usize>, u16)>> {
+    let nodes = self.get_labels(var)?;
+    Some(
+      nodes
+        .iter()
+        .filter(|n| n.is_named())
+        .map(|n| (n.range(), n.kind_id()))
+        .collect(),
+    )
+  }
+
+  /// Returns the text of the leaf tokens of the node captured by the single meta variable `var`,
+  /// in source order and including punctuation, e.g. `foo`, `(`, `a` and `)` for `foo(a)`.
+  /// This supports token-level checks on a capture. Tokens are walked lazily from the bound node,
+  /// so captures never asked for cost nothing.
+  pub fn get_tokens(&self, var: &str) -> Option<impl Iterator<Item = Cow<'tree, str>>> {
+    let leaves = self.get_match(var)?.dfs().filter(Node::is_leaf);
+    Some(leaves.map(|n| n.text()))
+  }
+
+  /// Returns how many named nodes each anonymous ellipsis `$$$` consumed, indexed by
+  /// the ellipsis's position among the anonymous ellipses of the pattern.
+  /// For example, `foo($$$, x, $$$)` against `foo(a, b, x, c)` gives `[2, 1]`.
+  /// Ellipses whose content was not compared, e.g. inside `opaque_kinds`, are not counted.
+  pub fn ellipsis_counts(&self) -> &[usize] {
+    &self.ellipsis_counts
+  }
+
+  pub(crate) fn push_ellipsis_count(&mut self, count: usize) {
+    self.ellipsis_counts.push(count);
+  }
+
+  /// Returns which alternative of `Pattern::with_fallbacks` matched, 0 for the primary pattern.
+  /// None if the match did not go through alternatives.
+  pub fn variant_index(&self) -> Option<usize> {
+    self.variant_index
+  }
+
+  pub(crate) fn set_variant_index(&mut self, index: usize) {
+    self.variant_index = Some(index);
   }
 
   pub fn get_transformed(&self, var: &str) -> Option<&Underlying<D>> {
-    self.transformed_var.get(var)
+    self.transformed_var.get(self.resolve(var))
   }
   pub fn get_var_bytes<'s>(
     &'s self,
@@ -67,33 +370,37 @@ impl<'tree, D: Doc> MetaVarEnv<'tree, D> {
   }
 
   pub fn add_label(&mut self, label: &str, node: Node<'tree, D>) {
-    self
+    let label = self.resolve(label).to_string();
+    let entry = self
       .multi_matched
-      .entry(label.into())
-      .or_default()
-      .push(node);
+      .iter_mut()
+      .rev()
+      .find(|(id, _)| *id == label);
+    if let Some((_, nodes)) = entry {
+      nodes.push(node);
+    } else {
+      self.multi_matched.push((label, vec![node]));
+    }
   }
 
   pub fn get_labels(&self, label: &str) -> Option<&Vec<Node<'tree, D>>> {
-    self.multi_matched.get(label)
+    let label = self.resolve(label);
+    self
+      .multi_matched
+      .iter()
+      .rev()
+      .find_map(|(id, nodes)| (id == label).then_some(nodes))
   }
 
   pub fn get_matched_variables(&self) -> impl Iterator<Item = MetaVariable> + '_ {
-    let single = self
-      .single_matched
-      .keys()
-      .cloned()
-      .map(|n| MetaVariable::Capture(n, false));
+    let single = self.single_matched.iter().map(|(id, ..)| id.clone());
+    let single = single.map(|n| MetaVariable::Capture(n, false));
     let transformed = self
       .transformed_var
       .keys()
       .cloned()
       .map(|n| MetaVariable::Capture(n, false));
-    let multi = self
-      .multi_matched
-      .keys()
-      .cloned()
-      .map(MetaVariable::MultiCapture);
+    let multi = unique_ids(&self.multi_matched).map(MetaVariable::MultiCapture);
     single.chain(multi).chain(transformed)
   }
 
@@ -102,8 +409,11 @@ impl<'tree, D: Doc> MetaVarEnv<'tree, D> {
     var_matchers: &HashMap<MetaVariableID, M>,
   ) -> bool {
     let mut env = Cow::Borrowed(self);
-    for (var_id, candidate) in &self.single_matched {
-      if let Some(m) = var_matchers.get(var_id) {
+    for (var_id, candidate) in self.single_bindings() {
+      // constraints may be keyed by an alias of the bound name
+      let aliases = self.var_aliases.iter().filter(|(_, c)| c == var_id);
+      let names = std::iter::once(var_id).chain(aliases.map(|(a, _)| a));
+      for m in names.filter_map(|name| var_matchers.get(name)) {
         if m.match_node_with_env(candidate.clone(), &mut env).is_none() {
           return false;
         }
@@ -115,14 +425,19 @@ impl<'tree, D: Doc> MetaVarEnv<'tree, D> {
     true
   }
 
+  /// Iterate the binding of every single meta variable.
+  fn single_bindings(&self) -> impl Iterator<Item = (&MetaVariableID, &Node<'tree, D>)> {
+    self.single_matched.iter().map(|(id, node, _)| (id, node))
+  }
+
   fn match_variable(&self, id: &str, candidate: &Node<D>) -> bool {
-    if let Some(m) = self.single_matched.get(id) {
+    if let Some(m) = self.get_match(id) {
       return does_node_match_exactly(m, candidate);
     }
     true
   }
   fn match_multi_var(&self, id: &str, cands: &[Node<D>]) -> bool {
-    let Some(nodes) = self.multi_matched.get(id) else {
+    let Some(nodes) = self.get_labels(id) else {
       return true;
     };
     let mut named_nodes = nodes.iter().filter(|n| n.is_named());
@@ -147,6 +462,42 @@ impl<'tree, D: Doc> MetaVarEnv<'tree, D> {
   }
 }
 
+fn extended_start<D: Doc>(node: &Node<D>, opts: &MatchOptions) -> usize {
+  let mut prev = node.prev();
+  while let Some(sibling) = prev {
+    if !opts.is_comment(&sibling) {
+      return sibling.range().end;
+    }
+    prev = sibling.prev();
+  }
+  node
+    .parent()
+    .map_or(node.range().start, |p| p.range().start)
+}
+
+fn extended_end<D: Doc>(node: &Node<D>, opts: &MatchOptions) -> usize {
+  let mut next = node.next();
+  while let Some(sibling) = next {
+    if !opts.is_comment(&sibling) {
+      return sibling.range().start;
+    }
+    next = sibling.next();
+  }
+  node.parent().map_or(node.range().end, |p| p.range().end)
+}
+
+/// Undo bindings made after checkpoint. A borrowed env has no new bindings to undo.
+pub(crate) fn rollback_env<D: Doc>(env: &mut Cow<MetaVarEnv<D>>, checkpoint: EnvCheckpoint) {
+  if let Cow::Owned(env) = env {
+    env.rollback(checkpoint);
+  }
+}
+
+/// Iterate the ids in a binding stack, which are distinct.
+fn unique_ids<T>(bindings: &[(MetaVariableID, T)]) -> impl Iterator<Item = MetaVariableID> + '_ {
+  bindings.iter().map(|(id, _)| id.clone())
+}
+
 fn get_var_bytes_impl<'t, C, D>(
   env: &'t MetaVarEnv<'t, D>,
   var: &MetaVariable,
@@ -166,7 +517,7 @@ where
         None
       }
     }
-    MetaVariable::MultiCapture(n) => {
+    MetaVariable::MultiCapture(n) | MetaVariable::LineContiguousCapture(n) => {
       let nodes = env.get_multiple_matches(n);
       if nodes.is_empty() {
         None
@@ -191,14 +542,18 @@ impl<'tree, D: Doc> Default for MetaVarEnv<'tree, D> {
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum MetaVariable {
-  /// $A for captured meta var
+  /// $A for captured meta var. The flag is false for $$A, which also matches unnamed nodes
+  /// and matches nothing if its slot is absent, e.g. `foo($$A)` against `foo()`.
   Capture(MetaVariableID, bool),
-  /// $_ for non-captured meta var
+  /// $_ for non-captured meta var, $$_ if the flag is false
   Dropped(bool),
   /// $$$ for non-captured multi var
   Multiple,
   /// $$$A for captured ellipsis
   MultiCapture(MetaVariableID),
+  /// $$$$A for captured ellipsis whose named nodes must be on consecutive lines with at most one blank
+  /// line between them, e.g. to find blocks of related statements
+  LineContiguousCapture(MetaVariableID),
 }
 
 pub(crate) fn extract_meta_var(src: &str, meta_char: char) -> Option<MetaVariable> {
@@ -207,6 +562,16 @@ pub(crate) fn extract_meta_var(src: &str, meta_char: char) -> Option<MetaVariabl
   if src == ellipsis {
     return Some(Multiple);
   }
+  // four meta chars, e.g. $$$$A, capture nodes on consecutive lines
+  let contiguous: String = std::iter::repeat(meta_char).take(4).collect();
+  if let Some(trimmed) = src.strip_prefix(&contiguous) {
+    // only named, an anonymous ellipsis captures nothing to check
+    let named = trimmed.starts_with(|c: char| c.is_ascii_uppercase());
+    if !named || !trimmed.chars().all(is_valid_meta_var_char) {
+      return None;
+    }
+    return Some(LineContiguousCapture(trimmed.to_owned()));
+  }
   if let Some(trimmed) = src.strip_prefix(&ellipsis) {
     if !trimmed.chars().all(is_valid_meta_var_char) {
       return None;
@@ -252,7 +617,7 @@ pub(crate) fn is_valid_meta_var_char(c: char) -> bool {
 impl<'tree, L: Language> From<MetaVarEnv<'tree, StrDoc<L>>> for HashMap<String, String> {
   fn from(env: MetaVarEnv<'tree, StrDoc<L>>) -> Self {
     let mut ret = HashMap::new();
-    for (id, node) in env.single_matched {
+    for (id, node, _) in env.single_matched {
       ret.insert(id, node.text().into());
     }
     for (id, bytes) in env.transformed_var {
@@ -287,6 +652,10 @@ mod test {
     assert_eq!(extract_var("$$ABC"), Some(Capture("ABC".into(), false)));
     assert_eq!(extract_var("$MATCH1"), Some(Capture("MATCH1".into(), true)));
     assert_eq!(extract_var("$$$ABC"), Some(MultiCapture("ABC".into())));
+    assert_eq!(
+      extract_var("$$$$ABC"),
+      Some(LineContiguousCapture("ABC".into()))
+    );
     assert_eq!(extract_var("$_"), Some(Dropped(true)));
     assert_eq!(extract_var("$_123"), Some(Dropped(true)));
     assert_eq!(extract_var("$$_"), Some(Dropped(false)));
@@ -297,6 +666,8 @@ mod test {
     assert_eq!(extract_var("$123"), None);
     assert_eq!(extract_var("$"), None);
     assert_eq!(extract_var("$$"), None);
+    assert_eq!(extract_var("$$$$"), None);
+    assert_eq!(extract_var("$$$$_"), None);
     assert_eq!(extract_var("abc"), None);
     assert_eq!(extract_var("$abc"), None);
   }
@@ -334,6 +705,281 @@ mod test {
     assert!(!match_constraints("a - b", "a + b"));
   }
 
+  #[test]
+  fn test_checkpoint_rollback() {
+    let grep = Tsx.ast_grep("a + b");
+    let root = grep.root();
+    let a = root.find("a").expect("should find a");
+    let b = root.find("b").expect("should find b");
+    let mut env = MetaVarEnv::new();
+    env
+      .insert("A", a.get_node().clone())
+      .expect("should insert");
+    let checkpoint = env.checkpoint();
+    env
+      .insert("B", b.get_node().clone())
+      .expect("should insert");
+    env.insert_multi("C", vec![b.get_node().clone()]);
+    env.push_ellipsis_count(1);
+    env
+      .insert("A", a.get_node().clone())
+      .expect("should insert");
+    assert_eq!(env.get_matched_variables().count(), 3);
+    // the consistent rebinding of A keeps the existing entry
+    assert_eq!(env.single_matched.len(), 2);
+    env.rollback(checkpoint);
+    assert!(env.ellipsis_counts().is_empty());
+    assert_eq!(env.get_match("A").map(|n| n.text()), Some("a".into()));
+    assert!(env.get_match("B").is_none());
+    assert!(env.get_labels("C").is_none());
+    assert_eq!(env.checkpoint(), checkpoint);
+  }
+
+  #[test]
+  fn test_named_capture_flag() {
+    let grep = Tsx.ast_grep("let a = 1; return;");
+    let root = grep.root();
+    let found = root.find("let $A = $$B").expect("should match");
+    let env = found.get_env();
+    assert_eq!(env.is_named_capture("A"), Some(true));
+    assert_eq!(env.is_named_capture("B"), Some(false));
+    assert_eq!(env.is_named_capture("C"), None);
+    let found = root.find("return $$B").expect("should match");
+    assert_eq!(
+      found.get_env().get_match("B").map(|n| n.text()),
+      Some(";".into())
+    );
+    assert_eq!(found.get_env().is_named_capture("B"), Some(false));
+  }
+
+  #[test]
+  fn test_with_capacity_for() {
+    let pattern = Pattern::new("foo($A, $B, $$$C, $_, $A)", Tsx);
+    let env = MetaVarEnv::<StrDoc<Tsx>>::with_capacity_for(&pattern);
+    assert_eq!(env.capacity_hint, (3, 1));
+    assert_eq!(env.single_matched.capacity(), 0);
+    let pattern = Pattern::new("foo($$$)", Tsx);
+    let env = MetaVarEnv::<StrDoc<Tsx>>::with_capacity_for(&pattern);
+    assert_eq!(env.capacity_hint, (0, 0));
+    // find_all binds the same with pre-sized envs
+    let grep = Tsx.ast_grep("foo(a, b, c, a); foo(a, b, c, d)");
+    let pattern = Pattern::new("foo($A, $B, $$$C, $A)", Tsx);
+    let found: Vec<_> = grep.root().find_all(&pattern).collect();
+    assert_eq!(found.len(), 1);
+    let env = found[0].get_env();
+    assert_eq!(env.get_match("B").map(|n| n.text()), Some("b".into()));
+    assert_eq!(env.get_multiple_matches("C").len(), 1);
+    assert!(env.single_matched.capacity() >= 3);
+  }
+
+  #[test]
+  fn test_with_bindings() {
+    let grep = Tsx.ast_grep("let a = 1; let b = 2; f(a + a); f(a + b)");
+    let root = grep.root();
+    let a = root.find("a").expect("should find a");
+    let b = root.find("b").expect("should find b");
+    let pattern = Pattern::new("f($A + $B)", Tsx);
+    let seeded = MetaVarEnv::with_bindings([("B", b.get_node().clone())]).expect("should bind");
+    let mut found = vec![];
+    for call in root.find_all("f($$$)") {
+      let mut env = Cow::Owned(seeded.clone());
+      if pattern
+        .match_node_with_env(call.get_node().clone(), &mut env)
+        .is_some()
+      {
+        found.push(call.text().to_string());
+        assert_eq!(env.get_match("A").map(|n| n.text()), Some("a".into()));
+      }
+    }
+    assert_eq!(found, ["f(a + b)"]);
+    // conflicting pre-bound values
+    let conflict = [("A", a.get_node().clone()), ("A", b.get_node().clone())];
+    assert!(MetaVarEnv::with_bindings(conflict).is_none());
+  }
+
+  #[test]
+  fn test_alias_var() {
+    let grep = Tsx.ast_grep("a; b");
+    let root = grep.root();
+    let a = root.find("a").expect("should find a").get_node().clone();
+    let b = root.find("b").expect("should find b").get_node().clone();
+    let mut env = MetaVarEnv::new();
+    env.alias_var("Y", "X");
+    env.insert("X", a.clone()).expect("should bind");
+    assert!(env.insert("Y", b).is_none());
+    assert!(env.insert("Y", a).is_some());
+    assert_eq!(env.get_match("Y").map(|n| n.text()), Some("a".into()));
+    let vars: Vec<_> = env.get_matched_variables().collect();
+    assert_eq!(vars, [MetaVariable::Capture("X".into(), false)]);
+  }
+
+  #[test]
+  fn test_alias_labels_and_transforms() {
+    let grep = Tsx.ast_grep("a; b");
+    let root = grep.root();
+    let a = root.find("a").expect("should find a").get_node().clone();
+    let b = root.find("b").expect("should find b").get_node().clone();
+    let mut env = MetaVarEnv::new();
+    env.alias_var("L", "K");
+    env.add_label("L", a.clone());
+    env.add_label("K", b.clone());
+    let texts = |env: &MetaVarEnv<_>, var| {
+      let labels = env.get_labels(var).expect("should label");
+      labels
+        .iter()
+        .map(|n| n.text().to_string())
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(texts(&env, "L"), ["a", "b"]);
+    assert_eq!(texts(&env, "K"), ["a", "b"]);
+    env.alias_var("T", "S");
+    env.insert_transformation("T", "x".into());
+    assert_eq!(env.get_transformed("S"), Some(&b"x".to_vec()));
+    assert_eq!(env.get_transformed("T"), Some(&b"x".to_vec()));
+    // merge keeps the aliases and records bindings of other under the canonical name
+    let mut other = MetaVarEnv::new();
+    other.alias_var("Y", "X");
+    other.insert("Y", a.clone()).expect("should bind");
+    let mut merged = MetaVarEnv::new();
+    merged.alias_var("Z", "X");
+    merged
+      .merge(other, MergePolicy::RejectConflicts)
+      .expect("should merge");
+    assert_eq!(merged.get_match("Y").map(|n| n.text()), Some("a".into()));
+    assert_eq!(merged.get_match("Z").map(|n| n.text()), Some("a".into()));
+    let mut other = MetaVarEnv::new();
+    other.insert("Z", b).expect("should bind");
+    let conflict = merged.merge(other, MergePolicy::RejectConflicts);
+    assert!(conflict.is_err());
+  }
+
+  #[test]
+  fn test_get_extended_range() {
+    let src = "foo(a /* first */, /* second */ b)";
+    let grep = Tsx.ast_grep(src);
+    let found = grep.root().find("foo($A, $$$)").expect("should match");
+    let env = found.get_env();
+    let comment = Tsx.get_ts_language().id_for_node_kind("comment", true);
+    let opts = MatchOptions::default().comment_kinds([comment]);
+    let text = |var| env.get_extended_range(var, &opts).map(|r| &src[r]);
+    // the trailing comment is attached, the comma is significant
+    assert_eq!(text("A"), Some("a /* first */"));
+    assert_eq!(text("B"), None);
+    // leading trivia is attached up to the previous significant sibling
+    let found = grep.root().find("b").expect("should find b");
+    let env = MetaVarEnv::with_bindings([("B", found.get_node().clone())]).expect("should bind");
+    let range = env.get_extended_range("B", &opts).expect("should bind");
+    assert_eq!(&src[range], " /* second */ b");
+    // comments are significant without comment_kinds
+    let default = MatchOptions::default();
+    let range = env.get_extended_range("B", &default).expect("should bind");
+    assert_eq!(&src[range], " b");
+    let found = grep.root().find("foo($$$ARGS)").expect("should match");
+    let range = found
+      .get_env()
+      .get_extended_range("ARGS", &opts)
+      .expect("should bind");
+    assert_eq!(&src[range], "a /* first */, /* second */ b");
+    // statements have no separator, the previous statement is significant
+    let src = "a();\n// note\nb();\nc();";
+    let grep = Tsx.ast_grep(src);
+    let found = grep.root().find("b();").expect("should find");
+    let env = MetaVarEnv::with_bindings([("B", found.get_node().clone())]).expect("should bind");
+    let range = env.get_extended_range("B", &opts).expect("should bind");
+    assert_eq!(&src[range], "\n// note\nb();\n");
+  }
+
+  #[test]
+  fn test_get_multi_ranges() {
+    let grep = Tsx.ast_grep("foo(a, bb, c)");
+    let found = grep.root().find("foo($$$A)").expect("should match");
+    let env = found.get_env();
+    let ranges = env.get_multi_ranges("A").expect("should capture");
+    assert_eq!(ranges, [4..5, 7..9, 11..12]);
+    assert!(env.get_multi_ranges("B").is_none());
+  }
+
+  #[test]
+  fn test_get_multi_spans() {
+    let grep = Tsx.ast_grep("foo(a, 1, 'b')");
+    let found = grep.root().find("foo($$$A)").expect("should match");
+    let spans = found
+      .get_env()
+      .get_multi_spans("A")
+      .expect("should capture");
+    let kind = |k| Tsx.get_ts_language().id_for_node_kind(k, true);
+    let expected = [
+      (4..5, kind("identifier")),
+      (7..8, kind("number")),
+      (10..13, kind("string")),
+    ];
+    assert_eq!(spans, expected);
+    assert!(found.get_env().get_multi_spans("B").is_none());
+  }
+
+  #[test]
+  fn test_get_tokens() {
+    let grep = Tsx.ast_grep("let a = foo(b, 1 + c)");
+    let found = grep.root().find("let a = $A").expect("should match");
+    let env = found.get_env();
+    let tokens: Vec<_> = env.get_tokens("A").expect("should capture").collect();
+    let expected = ["foo", "(", "b", ",", "1", "+", "c", ")"];
+    assert_eq!(tokens, expected);
+    assert!(env.get_tokens("B").is_none());
+  }
+
+  fn env_of<'t>(
+    grep: &'t crate::AstGrep<StrDoc<Tsx>>,
+    pattern: &str,
+  ) -> MetaVarEnv<'t, StrDoc<Tsx>> {
+    let found = grep.root().find(pattern).expect("should match");
+    found.get_env().clone()
+  }
+
+  fn texts(env: &MetaVarEnv<StrDoc<Tsx>>) -> Vec<(String, String)> {
+    let mut texts: Vec<_> = HashMap::from(env.clone()).into_iter().collect();
+    texts.sort();
+    texts
+  }
+
+  #[test]
+  fn test_merge_policies() {
+    let grep = Tsx.ast_grep("foo(a, b, c); bar(x, b, y)");
+    let pair = |k: &str, v: &str| (k.to_string(), v.to_string());
+    // A conflicts, B is consistent
+    let other = env_of(&grep, "bar($A, $B, $$$D)");
+    let mut env = env_of(&grep, "foo($A, $B, $$$C)");
+    let err = env.merge(other.clone(), MergePolicy::RejectConflicts);
+    assert_eq!(err.map_err(|e| e.0), Err("A".to_string()));
+    assert_eq!(texts(&env).len(), 3);
+    let mut env = env_of(&grep, "foo($A, $B, $$$C)");
+    env.merge(other.clone(), MergePolicy::KeepExisting).unwrap();
+    let expected = [
+      pair("A", "a"),
+      pair("B", "b"),
+      pair("C", "[c]"),
+      pair("D", "[y]"),
+    ];
+    assert_eq!(texts(&env), expected);
+    let mut env = env_of(&grep, "foo($A, $B, $$$C)");
+    env.merge(other, MergePolicy::PreferOther).unwrap();
+    let expected = [
+      pair("A", "x"),
+      pair("B", "b"),
+      pair("C", "[c]"),
+      pair("D", "[y]"),
+    ];
+    assert_eq!(texts(&env), expected);
+    // consistent bindings merge under every policy
+    let other = env_of(&grep, "bar($_, $B, $$$)");
+    let mut env = env_of(&grep, "foo($A, $B, $$$C)");
+    env.merge(other, MergePolicy::RejectConflicts).unwrap();
+    assert_eq!(
+      texts(&env),
+      [pair("A", "a"), pair("B", "b"), pair("C", "[c]")]
+    );
+  }
+
   #[test]
   fn test_multi_var_match() {
     let grep = Tsx.ast_grep("if (true) { a += 1; b += 1 } else { a += 1; b += 1 }");
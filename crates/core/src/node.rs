@@ -1,5 +1,5 @@
 use crate::language::Language;
-use crate::matcher::{FindAllNodes, Matcher, NodeMatch};
+use crate::matcher::{FindAllNodes, FindAllResult, KindIndex, Matcher, NodeMatch};
 use crate::replacer::Replacer;
 use crate::source::{perform_edit, Content, Edit as E, TSParseError};
 use crate::traversal::{Pre, Visitor};
@@ -88,6 +88,17 @@ impl<D: Doc> Root<D> {
   }
 }
 
+/// How columns are counted by [`Node::start_line_col`] and [`Node::end_line_col`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColumnUnit {
+  /// UTF-8 bytes, as tree-sitter counts them
+  Byte,
+  /// Unicode scalar values
+  Char,
+  /// UTF-16 code units, as LSP counts them by default
+  Utf16,
+}
+
 /// 'r represents root lifetime
 #[derive(Clone)]
 pub struct Node<'r, D: Doc> {
@@ -174,6 +185,32 @@ impl<'r, D: Doc> Node<'r, D> {
     (pos.row() as usize, pos.column() as usize)
   }
 
+  /// Node's start position as a 1-based line and a 0-based column counted in `unit`, for reporters.
+  /// Unlike [`Node::start_pos`], whose column is in bytes, multi-byte characters count as one column
+  /// with [`ColumnUnit::Char`] or as their UTF-16 length with [`ColumnUnit::Utf16`], e.g. for LSP.
+  pub fn start_line_col(&self, unit: ColumnUnit) -> (usize, usize) {
+    let (row, col) = self.start_pos();
+    self.line_col(self.range().start, row, col, unit)
+  }
+
+  /// Node's end position as a 1-based line and a 0-based column, see [`Node::start_line_col`].
+  pub fn end_line_col(&self, unit: ColumnUnit) -> (usize, usize) {
+    let (row, col) = self.end_pos();
+    self.line_col(self.range().end, row, col, unit)
+  }
+
+  fn line_col(&self, byte: usize, row: usize, byte_col: usize, unit: ColumnUnit) -> (usize, usize) {
+    let source = self.root.doc.get_source();
+    let line = source.get_range(byte - byte_col..byte);
+    let line = <D::Source as Content>::encode_bytes(line);
+    let col = match unit {
+      ColumnUnit::Byte => byte_col,
+      ColumnUnit::Char => line.chars().count(),
+      ColumnUnit::Utf16 => line.encode_utf16().count(),
+    };
+    (row + 1, col)
+  }
+
   pub fn text(&self) -> Cow<'r, str> {
     let source = self.root.doc.get_source();
     source.get_text(&self.inner)
@@ -323,6 +360,19 @@ impl<'r, D: Doc> Node<'r, D> {
     })
   }
 
+  /// Returns the id of the field this node is assigned to in its parent, if any.
+  pub(crate) fn field_id_in_parent(&self) -> Option<u16> {
+    let parent = self.inner.parent()?;
+    let mut cursor = parent.walk();
+    cursor.goto_first_child();
+    while cursor.node().id() != self.inner.id() {
+      if !cursor.goto_next_sibling() {
+        return None;
+      }
+    }
+    cursor.field_id()
+  }
+
   pub fn field_children(&self, name: &str) -> impl Iterator<Item = Node<'r, D>> {
     let field_id = self
       .root
@@ -441,9 +491,76 @@ impl<'r, D: Doc> Node<'r, D> {
     pat.find_node(self.clone())
   }
 
+  /// Finds all matches in pre-order. The walk descends into matched nodes,
+  /// so matches overlapping or nested in another match are also yielded, each with its own env.
+  /// Use `Visitor::reentrant(false)` to skip nested matches.
   pub fn find_all<M: Matcher<D::Lang>>(&self, pat: M) -> impl Iterator<Item = NodeMatch<'r, D>> {
     FindAllNodes::new(pat, self.clone())
   }
+
+  /// Like `find_all` but visits at most `max_nodes_visited` nodes, or all nodes if it is None.
+  /// Returns `FindAllResult::Partial` with the matches found so far if the budget runs out,
+  /// so interactive tools are not blocked by a slow pattern on a huge file.
+  pub fn find_all_with_budget<M: Matcher<D::Lang>>(
+    &self,
+    pat: M,
+    max_nodes_visited: Option<usize>,
+  ) -> FindAllResult<'r, D> {
+    let mut iter = FindAllNodes::new(pat, self.clone()).max_nodes_visited(max_nodes_visited);
+    let matches = iter.by_ref().collect();
+    if iter.budget_exceeded() {
+      FindAllResult::Partial(matches)
+    } else {
+      FindAllResult::Complete(matches)
+    }
+  }
+
+  /// Like `find_all` but does not descend into nodes of `stop_kinds` below this node,
+  /// e.g. to find usages in a function body but not in nested functions.
+  /// Nodes of the stop kinds are still matched themselves.
+  pub fn find_all_pruned<M: Matcher<D::Lang>>(
+    &self,
+    pat: M,
+    stop_kinds: &[u16],
+  ) -> impl Iterator<Item = NodeMatch<'r, D>> {
+    FindAllNodes::new(pat, self.clone()).stop_descent_at_kinds(stop_kinds.iter().copied())
+  }
+
+  /// Like `find_all` but skips every node for which `should_enter` returns false, with its descendants.
+  /// The predicate is called before each node is visited, see [`FindAllNodes::should_enter`].
+  pub fn find_all_entering<M, F>(
+    &self,
+    pat: M,
+    should_enter: F,
+  ) -> impl Iterator<Item = NodeMatch<'r, D>>
+  where
+    M: Matcher<D::Lang>,
+    F: Fn(&Node<'r, D>) -> bool + 'r,
+  {
+    FindAllNodes::new(pat, self.clone()).should_enter(should_enter)
+  }
+
+  /// Like `find_all` but skips subtrees that `index` shows have no node of the pattern's `potential_kinds`,
+  /// which pays off when matches are sparse and the index is reused. See [`KindIndex`].
+  pub fn find_all_indexed<M: Matcher<D::Lang>>(
+    &self,
+    pat: M,
+    index: &'r KindIndex,
+  ) -> impl Iterator<Item = NodeMatch<'r, D>> {
+    FindAllNodes::new(pat, self.clone()).kind_index(index)
+  }
+
+  /// Finds all matches and sorts them by ascending `cost` of the matched node.
+  /// Matches with equal cost keep their order in `find_all`.
+  pub fn find_all_ranked<M, F>(&self, pat: M, cost: F) -> Vec<NodeMatch<'r, D>>
+  where
+    M: Matcher<D::Lang>,
+    F: Fn(&Node<'r, D>) -> u64,
+  {
+    let mut matches: Vec<_> = self.find_all(pat).collect();
+    matches.sort_by_cached_key(|m| cost(m.get_node()));
+    matches
+  }
 }
 
 /// Tree manipulation API
@@ -517,6 +634,38 @@ mod test {
     assert!(!node.is_leaf());
   }
 
+  #[test]
+  fn test_line_col() {
+    use super::ColumnUnit;
+    let root = Tsx.ast_grep("let a = 1;\nlet é = '日本' + b;");
+    let node = root.root();
+    let find = |kind: &str, text: &str| {
+      let found = node.dfs().find(|n| n.kind() == kind && n.text() == text);
+      found.expect("should find node")
+    };
+    let b = find("identifier", "b");
+    assert_eq!(b.start_pos(), (1, 20));
+    assert_eq!(b.start_line_col(ColumnUnit::Byte), (2, 20));
+    assert_eq!(b.start_line_col(ColumnUnit::Char), (2, 15));
+    assert_eq!(b.start_line_col(ColumnUnit::Utf16), (2, 15));
+    let string = find("string", "'日本'");
+    assert_eq!(string.start_line_col(ColumnUnit::Char), (2, 8));
+    assert_eq!(string.end_line_col(ColumnUnit::Char), (2, 12));
+    assert_eq!(string.end_line_col(ColumnUnit::Byte), (2, 17));
+    let a = find("identifier", "a");
+    assert_eq!(a.start_line_col(ColumnUnit::Char), (1, 4));
+    // characters outside the BMP take two UTF-16 units
+    let root = Tsx.ast_grep("'😀'; c");
+    let c = root
+      .root()
+      .dfs()
+      .find(|n| n.text() == "c")
+      .expect("should find c");
+    assert_eq!(c.start_line_col(ColumnUnit::Char), (1, 5));
+    assert_eq!(c.start_line_col(ColumnUnit::Utf16), (1, 6));
+    assert_eq!(c.end_line_col(ColumnUnit::Byte), (1, 9));
+  }
+
   #[test]
   fn test_children() {
     let root = Tsx.ast_grep("let a = 123");
@@ -590,6 +739,111 @@ if (a) {
     }
   }
 
+  #[test]
+  fn test_find_all_overlapping() {
+    let root = Tsx.ast_grep("a.b.c.d");
+    let found: Vec<_> = root
+      .root()
+      .find_all("$A.$B")
+      .map(|m| {
+        let env = m.get_env();
+        let var = |v| env.get_match(v).expect("should bind").text().to_string();
+        (m.text().to_string(), var("A"), var("B"))
+      })
+      .collect();
+    let expected = [
+      ("a.b.c.d", "a.b.c", "d"),
+      ("a.b.c", "a.b", "c"),
+      ("a.b", "a", "b"),
+    ];
+    let expected: Vec<_> = expected
+      .iter()
+      .map(|(m, a, b)| (m.to_string(), a.to_string(), b.to_string()))
+      .collect();
+    assert_eq!(found, expected);
+  }
+
+  #[test]
+  fn test_find_all_with_budget() {
+    let src = "log(1);".repeat(1000);
+    let root = Tsx.ast_grep(src);
+    let found = root.root().find_all_with_budget("log($A)", Some(20));
+    assert!(found.is_partial());
+    let matches = found.into_matches();
+    assert!(!matches.is_empty() && matches.len() < 10);
+    assert_eq!(matches[0].get_env().get_match("A").unwrap().text(), "1");
+    let found = root.root().find_all_with_budget("log($A)", None);
+    assert!(!found.is_partial());
+    assert_eq!(found.matches().len(), 1000);
+    // a budget exactly covering the tree is not exceeded
+    let total = root.root().dfs().count();
+    let found = root.root().find_all_with_budget("log($A)", Some(total));
+    assert!(!found.is_partial());
+    assert_eq!(found.matches().len(), 1000);
+  }
+
+  #[test]
+  fn test_find_all_pruned() {
+    let src = "function outer() {\n  if (a) { return 1 }\n  function inner() { return 2 }\n  const f = () => { return 3 }\n  return inner\n}";
+    let root = Tsx.ast_grep(src);
+    let outer = root
+      .root()
+      .find("function outer() { $$$ }")
+      .expect("should find");
+    let lang = Tsx.get_ts_language();
+    let stops = ["function_declaration", "arrow_function"].map(|k| lang.id_for_node_kind(k, true));
+    let found = outer.get_node().find_all_pruned("return $A", &stops);
+    let texts: Vec<_> = found.map(|m| m.text().to_string()).collect();
+    assert_eq!(texts, ["return 1", "return inner"]);
+    // stop kinds themselves still match
+    let found = outer
+      .get_node()
+      .find_all_pruned("function $F() { $$$ }", &stops);
+    let names: Vec<_> = found
+      .map(|m| m.get_env().get_match("F").unwrap().text().to_string())
+      .collect();
+    assert_eq!(names, ["outer", "inner"]);
+    assert_eq!(outer.get_node().find_all("return $A").count(), 4);
+  }
+
+  #[test]
+  fn test_find_all_entering() {
+    let src = "a();\nfunction f() { b() }\n// @generated\nc();\nfunction g() { d() }";
+    let root = Tsx.ast_grep(src);
+    type Node<'r> = super::Node<'r, crate::StrDoc<Tsx>>;
+    let calls = |should_enter: &dyn Fn(&Node) -> bool| {
+      let found = root.root().find_all_entering("$F()", should_enter);
+      found.map(|m| m.text().to_string()).collect::<Vec<_>>()
+    };
+    let marker = src.find("// @generated").expect("should have marker");
+    assert_eq!(calls(&|n| n.range().start < marker), ["a()", "b()"]);
+    // rejected nodes are not descended into
+    assert_eq!(
+      calls(&|n| n.kind() != "function_declaration"),
+      ["a()", "c()"]
+    );
+    assert!(calls(&|n| n.kind() != "program").is_empty());
+    assert_eq!(calls(&|_| true).len(), 4);
+  }
+
+  #[test]
+  fn test_find_all_ranked() {
+    let src = "function a() { log(1) }\nfunction b() { log(2); x(); y() }\nlog(3)";
+    let root = Tsx.ast_grep(src);
+    // prefer matches in larger functions, top level ones come last
+    let cost = |n: &crate::Node<_>| {
+      let func = n.ancestors().find(|a| a.kind() == "function_declaration");
+      func.map_or(u64::MAX, |f| u64::MAX - f.range().len() as u64)
+    };
+    let ranked = root.root().find_all_ranked("log($A)", cost);
+    let texts: Vec<_> = ranked.iter().map(|m| m.text()).collect();
+    assert_eq!(texts, ["log(2)", "log(1)", "log(3)"]);
+    // ties keep source order
+    let ranked = root.root().find_all_ranked("log($A)", |_| 0);
+    let texts: Vec<_> = ranked.iter().map(|m| m.text()).collect();
+    assert_eq!(texts, ["log(1)", "log(2)", "log(3)"]);
+  }
+
   #[test]
   fn test_replace_all_nested() {
     let root = Tsx.ast_grep("Some(Some(1))");
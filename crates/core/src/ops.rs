@@ -1,5 +1,5 @@
 use crate::matcher::{MatchAll, MatchNone, Matcher};
-use crate::meta_var::MetaVarEnv;
+use crate::meta_var::{rollback_env, MetaVarEnv};
 use crate::{Doc, Language, Node};
 use bit_set::BitSet;
 use std::borrow::Cow;
@@ -87,11 +87,15 @@ impl<L: Language, P: Matcher<L>> Matcher<L> for All<L, P> {
         return None;
       }
     }
-    self
+    let checkpoint = env.checkpoint();
+    let all = self
       .patterns
       .iter()
-      .all(|p| p.match_node_with_env(node.clone(), env).is_some())
-      .then_some(node)
+      .all(|p| p.match_node_with_env(node.clone(), env).is_some());
+    if !all {
+      rollback_env(env, checkpoint);
+    }
+    all.then_some(node)
   }
 
   fn potential_kinds(&self) -> Option<BitSet> {
@@ -142,17 +146,15 @@ impl<L: Language, M: Matcher<L>> Matcher<L> for Any<L, M> {
         return None;
       }
     }
-    let mut new_env = Cow::Borrowed(env.as_ref());
+    let checkpoint = env.checkpoint();
     let found = self.patterns.iter().find_map(|p| {
-      new_env = Cow::Borrowed(env.as_ref());
-      p.match_node_with_env(node.clone(), &mut new_env)
+      let ret = p.match_node_with_env(node.clone(), env);
+      if ret.is_none() {
+        rollback_env(env, checkpoint);
+      }
+      ret
     });
-    if found.is_some() {
-      *env = Cow::Owned(new_env.into_owned());
-      Some(node)
-    } else {
-      None
-    }
+    found.map(|_| node)
   }
 
   fn potential_kinds(&self) -> Option<BitSet> {
@@ -160,6 +162,51 @@ impl<L: Language, M: Matcher<L>> Matcher<L> for Any<L, M> {
   }
 }
 
+/// Like [`Any`] but records the index of the pattern that matched, see `Pattern::with_fallbacks`.
+pub struct Fallbacks<L, P> {
+  any: Any<L, P>,
+}
+
+impl<L: Language, P: Matcher<L>> Fallbacks<L, P> {
+  pub fn new<PS: IntoIterator<Item = P>>(primary: P, fallbacks: PS) -> Self {
+    let patterns = std::iter::once(primary).chain(fallbacks);
+    Self {
+      any: Any::new(patterns),
+    }
+  }
+
+  pub fn inner(&self) -> &[P] {
+    self.any.inner()
+  }
+}
+
+impl<L: Language, M: Matcher<L>> Matcher<L> for Fallbacks<L, M> {
+  fn match_node_with_env<'tree, D: Doc<Lang = L>>(
+    &self,
+    node: Node<'tree, D>,
+    env: &mut Cow<MetaVarEnv<'tree, D>>,
+  ) -> Option<Node<'tree, D>> {
+    if let Some(kinds) = &self.any.kinds {
+      if !kinds.contains(node.kind_id().into()) {
+        return None;
+      }
+    }
+    let checkpoint = env.checkpoint();
+    for (index, pattern) in self.any.patterns.iter().enumerate() {
+      if pattern.match_node_with_env(node.clone(), env).is_some() {
+        env.to_mut().set_variant_index(index);
+        return Some(node);
+      }
+      rollback_env(env, checkpoint);
+    }
+    None
+  }
+
+  fn potential_kinds(&self) -> Option<BitSet> {
+    self.any.kinds.clone()
+  }
+}
+
 pub struct Or<L: Language, P1: Matcher<L>, P2: Matcher<L>> {
   pattern1: P1,
   pattern2: P2,
@@ -177,14 +224,11 @@ where
     node: Node<'tree, D>,
     env: &mut Cow<MetaVarEnv<'tree, D>>,
   ) -> Option<Node<'tree, D>> {
-    let mut new_env = Cow::Borrowed(env.as_ref());
-    if let Some(ret) = self
-      .pattern1
-      .match_node_with_env(node.clone(), &mut new_env)
-    {
-      *env = Cow::Owned(new_env.into_owned());
+    let checkpoint = env.checkpoint();
+    if let Some(ret) = self.pattern1.match_node_with_env(node.clone(), env) {
       Some(ret)
     } else {
+      rollback_env(env, checkpoint);
       self.pattern2.match_node_with_env(node, env)
     }
   }
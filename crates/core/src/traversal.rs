@@ -23,7 +23,7 @@ use crate::{Doc, Node, NodeMatch, Root};
 
 use tree_sitter as ts;
 
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
 use std::iter::FusedIterator;
 use std::marker::PhantomData;
 
@@ -32,6 +32,8 @@ pub struct Visitor<M, A = PreOrder> {
   reentrant: bool,
   /// Whether visit named node only
   named_only: bool,
+  /// Whether to skip a match with the same range as a previous match
+  dedup_by_range: bool,
   /// optional matcher to filter nodes
   matcher: M,
   /// The algorithm to traverse the tree, can be pre/post/level order
@@ -43,6 +45,7 @@ impl<M> Visitor<M> {
     Visitor {
       reentrant: true,
       named_only: false,
+      dedup_by_range: false,
       matcher,
       algorithm: PhantomData,
     }
@@ -54,6 +57,7 @@ impl<M, A> Visitor<M, A> {
     Visitor {
       reentrant: self.reentrant,
       named_only: self.named_only,
+      dedup_by_range: self.dedup_by_range,
       matcher: self.matcher,
       algorithm: PhantomData,
     }
@@ -66,6 +70,16 @@ impl<M, A> Visitor<M, A> {
   pub fn named_only(self, named_only: bool) -> Self {
     Self { named_only, ..self }
   }
+
+  /// Skip a match whose range equals a previously yielded match's range,
+  /// e.g. an expression statement and the sole expression it wraps.
+  /// The first match of a range is kept, so the output order is unchanged otherwise.
+  pub fn dedup_by_range(self, dedup_by_range: bool) -> Self {
+    Self {
+      dedup_by_range,
+      ..self
+    }
+  }
 }
 
 impl<M, A> Visitor<M, A>
@@ -80,6 +94,7 @@ where
     Visit {
       reentrant: self.reentrant,
       named: self.named_only,
+      seen: self.dedup_by_range.then(HashSet::new),
       matcher: self.matcher,
       traversal,
       lang: PhantomData,
@@ -90,6 +105,8 @@ where
 pub struct Visit<'t, D, T, M> {
   reentrant: bool,
   named: bool,
+  /// ranges yielded so far if deduplicating by range
+  seen: Option<HashSet<(usize, usize)>>,
   matcher: M,
   traversal: T,
   lang: PhantomData<&'t D>,
@@ -122,6 +139,12 @@ where
       let pass_named = !self.named || node.is_named();
       if let Some(node_match) = pass_named.then(|| self.matcher.match_node(node)).flatten() {
         self.mark_match(Some(match_depth));
+        if let Some(seen) = &mut self.seen {
+          let range = node_match.range();
+          if !seen.insert((range.start, range.end)) {
+            continue;
+          }
+        }
         return Some(node_match);
       } else {
         self.mark_match(None);
@@ -579,4 +602,26 @@ mod test {
       .collect();
     assert_eq!(recur, visit);
   }
+
+  #[test]
+  fn test_dedup_by_range() {
+    // program, expression_statement and identifier share the same range
+    let grep = Tsx.ast_grep("a");
+    let all: Vec<_> = Visitor::new("$A")
+      .visit(grep.root())
+      .map(|n| n.range())
+      .collect();
+    assert_eq!(all, [0..1, 0..1, 0..1]);
+    let grep = Tsx.ast_grep("a; (b)");
+    let visit = |dedup| {
+      Visitor::new("$A")
+        .named_only(true)
+        .dedup_by_range(dedup)
+        .visit(grep.root())
+        .map(|n| n.text().to_string())
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(visit(false), ["a; (b)", "a;", "a", "(b)", "(b)", "b"]);
+    assert_eq!(visit(true), ["a; (b)", "a;", "a", "(b)", "b"]);
+  }
 }
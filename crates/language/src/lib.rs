@@ -27,6 +27,7 @@ mod swift;
 
 use ast_grep_core::language::TSLanguage;
 use ast_grep_core::meta_var::MetaVariable;
+use ast_grep_core::{Doc, Node};
 use ignore::types::{Types, TypesBuilder};
 use serde::{de, Deserialize, Deserializer, Serialize};
 use std::borrow::Cow;
@@ -49,8 +50,35 @@ macro_rules! impl_lang {
       }
     }
   };
+  // with identifier kinds that are compatible leaves, see `Language::leaf_kinds_compatible`
+  ($lang: ident, $func: ident, $identifiers: expr) => {
+    #[derive(Clone, Copy)]
+    pub struct $lang;
+    impl Language for $lang {
+      fn get_ts_language(&self) -> TSLanguage {
+        parsers::$func().into()
+      }
+      fn leaf_kinds_compatible(&self, goal_kind: u16, candidate_kind: u16) -> bool {
+        let lang = self.get_ts_language();
+        let is_identifier =
+          |kind| matches!(lang.node_kind_for_id(kind), Some(k) if $identifiers.contains(&&*k));
+        goal_kind == candidate_kind || is_identifier(goal_kind) && is_identifier(candidate_kind)
+      }
+    }
+  };
 }
 
+/// Kinds naming a binding in JavaScript and TypeScript, e.g. `a` in `a`, `x.a` and `{ a }`.
+const JS_IDENTIFIERS: &[&str] = &[
+  "identifier",
+  "property_identifier",
+  "private_property_identifier",
+  "shorthand_property_identifier",
+  "shorthand_property_identifier_pattern",
+  "statement_identifier",
+  "type_identifier",
+];
+
 fn pre_process_pattern(expando: char, query: &str) -> std::borrow::Cow<str> {
   let mut ret = Vec::with_capacity(query.len());
   let mut dollar_count = 0;
@@ -131,13 +159,13 @@ impl_lang_expando!(Swift, language_swift, 'µ');
 impl_lang!(Bash, language_bash);
 impl_lang!(Dart, language_dart);
 impl_lang!(Java, language_java);
-impl_lang!(JavaScript, language_javascript);
+impl_lang!(JavaScript, language_javascript, JS_IDENTIFIERS);
 impl_lang!(Json, language_json);
 impl_lang!(Lua, language_lua);
 impl_lang!(Php, language_php);
 impl_lang!(Scala, language_scala);
-impl_lang!(Tsx, language_tsx);
-impl_lang!(TypeScript, language_typescript);
+impl_lang!(Tsx, language_tsx, JS_IDENTIFIERS);
+impl_lang!(TypeScript, language_typescript, JS_IDENTIFIERS);
 // See ripgrep for extensions
 // https://github.com/BurntSushi/ripgrep/blob/master/crates/ignore/src/default_types.rs
 
@@ -305,10 +333,16 @@ impl Language for SupportLang {
   impl_lang_method!(meta_var_char, () => char);
   impl_lang_method!(expando_char, () => char);
   impl_lang_method!(extract_meta_var, (source: &str) => Option<MetaVariable>);
+  impl_lang_method!(terminator_tokens, () => &'static [&'static str]);
+  impl_lang_method!(leaf_kinds_compatible, (goal_kind: u16, candidate_kind: u16) => bool);
 
   fn pre_process_pattern<'q>(&self, query: &'q str) -> Cow<'q, str> {
     execute_lang_method! { self, pre_process_pattern, query }
   }
+
+  fn is_trivial<D: Doc>(&self, node: &Node<D>) -> bool {
+    execute_lang_method! { self, is_trivial, node }
+  }
 }
 
 fn extensions(lang: &SupportLang) -> &[&str] {
@@ -418,6 +452,22 @@ mod test {
     Ok(source.generate())
   }
 
+  #[test]
+  fn test_js_identifier_kinds() {
+    // `a` is an identifier and then a property_identifier or shorthand_property_identifier
+    test_match_lang("foo($A, x.$A)", "foo(a, x.a)", JavaScript);
+    test_match_lang("foo($A, { $A })", "foo(a, { a })", Tsx);
+    test_non_match_lang("foo($A, x.$A)", "foo(a, x.b)", TypeScript);
+  }
+
+  #[test]
+  fn test_default_leaf_kinds() {
+    // other languages compare leaf text only, gh#1087
+    test_match_lang("Foo { $A: $A }", "fn m() { Foo { a: a }; }", Rust);
+    test_match_lang("$A.$A", "a.a", Rust);
+    test_match_lang("$A.$A", "a.a", Go);
+  }
+
   #[test]
   fn test_js_string() {
     test_match_lang("'a'", "'a'", JavaScript);